@@ -0,0 +1,89 @@
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    let git_hash = Command::new("git")
+        .args(&["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=CC_SWITCH_GIT_HASH={}", git_hash);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    // HEAD only names the branch, so a commit changes the branch's ref instead, which is either
+    // its own file or a line in packed-refs. A file that doesn't exist would rerun every build.
+    let branch = std::fs::read_to_string(".git/HEAD")
+        .ok()
+        .and_then(|head| head.trim().strip_prefix("ref: ").map(str::to_owned));
+    for path in branch
+        .map(|branch| format!(".git/{}", branch))
+        .into_iter()
+        .chain(Some(".git/packed-refs".to_owned()))
+    {
+        if Path::new(&path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+
+    let days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+        / 86400;
+    let (year, month, day) = civil_from_days(days);
+    println!(
+        "cargo:rustc-env=CC_SWITCH_BUILD_DATE={:04}-{:02}-{:02}",
+        year, month, day
+    );
+
+    let cold_clear = std::fs::read_to_string("Cargo.lock")
+        .ok()
+        .and_then(|lock| cold_clear_version(&lock))
+        .unwrap_or_else(|| "unknown".to_owned());
+    println!(
+        "cargo:rustc-env=CC_SWITCH_COLD_CLEAR_VERSION={}",
+        cold_clear
+    );
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    let mut features: Vec<_> = std::env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|feature| feature.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=CC_SWITCH_FEATURES={}", features.join(","));
+}
+
+fn cold_clear_version(lock: &str) -> Option<String> {
+    let mut lines = lock
+        .lines()
+        .skip_while(|&line| line != "name = \"cold-clear\"");
+    lines.next()?;
+    let version = lines.next()?.strip_prefix("version = ")?.trim_matches('"');
+    let rev = lines
+        .next()?
+        .rsplit('#')
+        .next()?
+        .trim_matches('"')
+        .get(..7)?;
+    Some(format!("{} ({})", version, rev))
+}
+
+// Howard Hinnant's days-to-civil-date algorithm.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,289 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+// Alternates two evaluator presets between the games of the bot in one slot, to compare them
+// against the same human opponents. A game starts when a bot is launched into the slot or reset
+// there, and ends when that bot goes away. Every game in which something was placed is appended
+// to the results file as a JSON line, and the profiles take turns by how many games the file
+// holds, so a restarted bridge carries on with the rotation where it left off.
+#[derive(Clone, Debug)]
+pub struct AbTest {
+    pub profiles: [String; 2],
+    pub slot: u8,
+    // How many games each profile plays before the other takes over.
+    pub games_in_a_row: u32,
+    // Keeps which profile is playing out of the log, for a blind test.
+    pub blind: bool,
+    results: Arc<Mutex<Results>>,
+}
+
+#[derive(Debug)]
+struct Results {
+    file: File,
+    recorded: u64,
+}
+
+// `finished` is milliseconds since the Unix epoch. `won` is left out when the console didn't
+// report how the game went.
+#[derive(Serialize, Deserialize)]
+pub struct AbResult {
+    pub finished: u64,
+    pub profile: String,
+    pub duration_ms: u64,
+    pub pieces_placed: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub won: Option<bool>,
+}
+
+impl AbTest {
+    pub fn open(
+        profiles: [String; 2],
+        slot: u8,
+        games_in_a_row: u32,
+        blind: bool,
+        path: &Path,
+    ) -> io::Result<AbTest> {
+        let recorded = match fs::read_to_string(path) {
+            Ok(text) => text.lines().filter(|line| !line.trim().is_empty()).count() as u64,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AbTest {
+            profiles,
+            slot,
+            games_in_a_row: games_in_a_row.max(1),
+            blind,
+            results: Arc::new(Mutex::new(Results { file, recorded })),
+        })
+    }
+    // Picks the profile for a game starting now. `replacing` is whether the game it takes over
+    // from is about to be recorded, which it only is once the new bot has taken its place.
+    pub fn start(&self, replacing: bool) -> AbGame {
+        let played = self.results.lock().unwrap().recorded + u64::from(replacing);
+        let profile = self.profiles[self.turn(played)].clone();
+        if self.blind {
+            info!("A/B test: game {} in slot {}", played + 1, self.slot);
+        } else {
+            info!(
+                "A/B test: game {} in slot {} is played by {}",
+                played + 1,
+                self.slot,
+                profile
+            );
+        }
+        AbGame {
+            test: self.clone(),
+            profile,
+            started: Instant::now(),
+            won: None,
+        }
+    }
+    // Which profile plays once `played` games have been recorded.
+    fn turn(&self, played: u64) -> usize {
+        (played / u64::from(self.games_in_a_row) % 2) as usize
+    }
+    fn record(&self, result: &AbResult) {
+        let mut line = match serde_json::to_vec(result) {
+            Ok(line) => line,
+            Err(err) => {
+                warn!("Could not write an A/B test result: {}", err);
+                return;
+            }
+        };
+        line.push(b'\n');
+        let mut results = self.results.lock().unwrap();
+        results.recorded += 1;
+        if let Err(err) = results.file.write_all(&line) {
+            warn!("Could not write an A/B test result: {}", err);
+        }
+    }
+}
+
+pub struct AbGame {
+    test: AbTest,
+    pub profile: String,
+    started: Instant,
+    // As reported by the console with ReportResult.
+    pub won: Option<bool>,
+}
+
+impl AbGame {
+    // Games in which nothing was placed weren't played, and don't count.
+    pub fn counts(pieces_placed: u32) -> bool {
+        pieces_placed > 0
+    }
+    pub fn finish(&self, pieces_placed: u32) {
+        if !AbGame::counts(pieces_placed) {
+            return;
+        }
+        self.test.record(&AbResult {
+            finished: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as u64),
+            profile: self.profile.clone(),
+            duration_ms: self.started.elapsed().as_millis() as u64,
+            pieces_placed,
+            won: self.won,
+        });
+    }
+}
+
+#[derive(Default)]
+pub struct ProfileTotals {
+    pub games: u32,
+    pub reported: u32,
+    pub wins: u32,
+    pub pieces: u64,
+    pub duration_ms: u64,
+}
+
+// Totals for each profile in a results file, by name.
+pub struct AbReport {
+    pub profiles: BTreeMap<String, ProfileTotals>,
+}
+
+impl AbReport {
+    pub fn load(path: &Path) -> io::Result<AbReport> {
+        let text = fs::read_to_string(path)?;
+        let mut results = vec![];
+        for (index, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let result = serde_json::from_str(line).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", index + 1, err),
+                )
+            })?;
+            results.push(result);
+        }
+        Ok(AbReport::new(&results))
+    }
+    pub fn new(results: &[AbResult]) -> AbReport {
+        let mut profiles: BTreeMap<String, ProfileTotals> = BTreeMap::new();
+        for result in results {
+            let totals = profiles.entry(result.profile.clone()).or_default();
+            totals.games += 1;
+            totals.pieces += u64::from(result.pieces_placed);
+            totals.duration_ms += result.duration_ms;
+            if let Some(won) = result.won {
+                totals.reported += 1;
+                totals.wins += u32::from(won);
+            }
+        }
+        AbReport { profiles }
+    }
+}
+
+impl fmt::Display for AbReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.profiles.is_empty() {
+            return write!(f, "No games recorded yet.");
+        }
+        let mut first = true;
+        for (name, totals) in &self.profiles {
+            if !first {
+                writeln!(f)?;
+            }
+            first = false;
+            let games = f64::from(totals.games.max(1));
+            // Games without a reported result don't count towards the win rate.
+            let win_rate = match totals.reported {
+                0 => "no results reported".to_owned(),
+                reported => format!(
+                    "won {} of {} ({:.1}%)",
+                    totals.wins,
+                    reported,
+                    f64::from(totals.wins) * 100.0 / f64::from(reported)
+                ),
+            };
+            write!(
+                f,
+                "{}: {} games, {}, {:.1} pieces and {:.1} s a game",
+                name,
+                totals.games,
+                win_rate,
+                totals.pieces as f64 / games,
+                totals.duration_ms as f64 / games / 1000.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn results_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "cc-switch-usb-rs-test-{}-{}.jsonl",
+            std::process::id(),
+            name
+        ));
+        fs::remove_file(&path).ok();
+        path
+    }
+
+    fn test(path: &Path, games_in_a_row: u32) -> AbTest {
+        let profiles = ["a".to_owned(), "b".to_owned()];
+        AbTest::open(profiles, 1, games_in_a_row, false, path).unwrap()
+    }
+
+    fn play(test: &AbTest, won: bool) -> String {
+        let mut game = test.start(false);
+        game.won = Some(won);
+        game.finish(10);
+        game.profile.clone()
+    }
+
+    #[test]
+    fn profiles_take_turns() {
+        let path = results_path("turns");
+        let test = test(&path, 2);
+        let played: Vec<_> = (0..6).map(|_| play(&test, true)).collect();
+        assert_eq!(played, ["a", "a", "b", "b", "a", "a"]);
+    }
+
+    #[test]
+    fn unplayed_games_keep_the_turn() {
+        let path = results_path("unplayed");
+        let test = test(&path, 1);
+        test.start(false).finish(0);
+        assert_eq!(play(&test, true), "a");
+        // Reset with a game in progress: the new one already counts it.
+        assert_eq!(test.start(true).profile, "b");
+    }
+
+    #[test]
+    fn rotation_carries_on_after_a_restart() {
+        let path = results_path("restart");
+        play(&test(&path, 1), true);
+        assert_eq!(play(&test(&path, 1), false), "b");
+    }
+
+    #[test]
+    fn report_counts_wins_per_profile() {
+        let path = results_path("report");
+        let test = test(&path, 1);
+        play(&test, true);
+        play(&test, false);
+        play(&test, false);
+        test.start(false).finish(20);
+        let report = AbReport::load(&path).unwrap();
+        let a = &report.profiles["a"];
+        let b = &report.profiles["b"];
+        assert_eq!((a.games, a.reported, a.wins), (2, 2, 1));
+        assert_eq!((b.games, b.reported, b.wins), (2, 1, 0));
+        assert_eq!(b.pieces, 30);
+    }
+}
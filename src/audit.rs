@@ -0,0 +1,103 @@
+use crate::build_info::BuildInfo;
+use crate::protocol::Command;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+// One line of the audit log: either a command as it was decoded, or a response as it was sent,
+// or, at the start of each session, the build that handled it. `time` is milliseconds since the
+// Unix epoch.
+#[derive(Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub time: u64,
+    pub session: u64,
+    pub request_id: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<serde_json::Value>,
+}
+
+// Every session appends to the same file, one JSON object per line. Lines are written whole and
+// unbuffered so a crash loses nothing that was already handled.
+#[derive(Clone, Debug)]
+pub struct AuditLog {
+    file: Arc<Mutex<File>>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> io::Result<AuditLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AuditLog {
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+    pub fn session(&self, session: u64) -> SessionAudit {
+        self.record(&AuditEntry {
+            time: now(),
+            session,
+            request_id: None,
+            command: None,
+            response: None,
+            build: serde_json::to_value(BuildInfo::get()).ok(),
+        });
+        SessionAudit {
+            log: self.clone(),
+            session,
+        }
+    }
+    fn record(&self, entry: &AuditEntry) {
+        let mut line = match serde_json::to_vec(entry) {
+            Ok(line) => line,
+            Err(err) => {
+                warn!("Could not write an entry to the audit log: {}", err);
+                return;
+            }
+        };
+        line.push(b'\n');
+        if let Err(err) = self.file.lock().unwrap().write_all(&line) {
+            warn!("Could not write to the audit log: {}", err);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SessionAudit {
+    log: AuditLog,
+    session: u64,
+}
+
+impl SessionAudit {
+    pub fn command(&self, request_id: u32, command: &Command) {
+        self.log.record(&AuditEntry {
+            time: now(),
+            session: self.session,
+            request_id: Some(request_id),
+            command: serde_json::to_value(command).ok(),
+            response: None,
+            build: None,
+        });
+    }
+    pub fn response(&self, request_id: Option<u32>, response: &impl Serialize) {
+        self.log.record(&AuditEntry {
+            time: now(),
+            session: self.session,
+            request_id,
+            command: None,
+            response: serde_json::to_value(response).ok(),
+            build: None,
+        });
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
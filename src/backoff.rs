@@ -0,0 +1,45 @@
+use crate::garbage::SplitMix64;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Delays between attempts, doubling from `min` up to `max`. Each delay is randomized by up to a
+// fifth either way so bridges restarted together don't keep retrying in lockstep.
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    next: Duration,
+    attempts: u32,
+    rng: SplitMix64,
+}
+
+impl Backoff {
+    pub const DEFAULT_MIN: Duration = Duration::from_secs(1);
+    pub const DEFAULT_MAX: Duration = Duration::from_secs(30);
+    pub fn new(min: Duration, max: Duration) -> Backoff {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        Backoff {
+            min,
+            max: max.max(min),
+            next: min,
+            attempts: 0,
+            rng: SplitMix64(seed),
+        }
+    }
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        self.attempts += 1;
+        let jitter = 0.8 + 0.4 * (self.rng.next() as f64 / u64::MAX as f64);
+        delay.mul_f64(jitter)
+    }
+    // Number of delays handed out since the last reset.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+    pub fn reset(&mut self) {
+        self.next = self.min;
+        self.attempts = 0;
+    }
+}
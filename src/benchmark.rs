@@ -0,0 +1,105 @@
+use crate::garbage::SplitMix64;
+use crate::latency::{LatencyStats, LatencyWindow};
+use crate::priority::ThreadPolicy;
+use libtetris::{Board, Piece};
+use std::time::{Duration, Instant};
+
+pub struct BenchmarkReport {
+    pub moves: u32,
+    pub topped_out: bool,
+    pub elapsed: Duration,
+    pub total_nodes: u64,
+    pub average_depth: f64,
+    pub latency: LatencyStats,
+}
+
+impl std::fmt::Display for BenchmarkReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let secs = self.elapsed.as_secs_f64();
+        writeln!(f, "moves:      {} in {:.2}s", self.moves, secs)?;
+        if self.topped_out {
+            writeln!(f, "            (the bot topped out)")?;
+        }
+        writeln!(f, "moves/sec:  {:.2}", f64::from(self.moves) / secs)?;
+        writeln!(f, "nodes/sec:  {:.0}", self.total_nodes as f64 / secs)?;
+        writeln!(f, "depth:      {:.2} on average", self.average_depth)?;
+        write!(
+            f,
+            "latency:    min {:.1}ms, median {:.1}ms, p95 {:.1}ms, max {:.1}ms",
+            self.latency.min_ms, self.latency.median_ms, self.latency.p95_ms, self.latency.max_ms
+        )
+    }
+}
+
+// Plays `moves` pieces from a seeded 7-bag queue, asking for each move as soon as the previous
+// one is known, the way a console that never waits on animations would.
+pub fn run(
+    options: cold_clear::Options,
+    evaluator: cold_clear::evaluation::Standard,
+    policy: &ThreadPolicy,
+    moves: u32,
+    seed: u64,
+) -> BenchmarkReport {
+    const PREVIEWS: usize = 5;
+    let queue = bag_queue(moves as usize + PREVIEWS, seed);
+    let interface =
+        policy.run(move || cold_clear::Interface::launch(Board::new(), options, evaluator));
+    for &piece in &queue[..PREVIEWS] {
+        interface.add_next_piece(piece);
+    }
+    let mut latency = LatencyWindow::with_capacity(moves as usize);
+    let mut played = 0;
+    let mut total_nodes = 0;
+    let mut total_depth = 0;
+    let mut topped_out = false;
+    let started = Instant::now();
+    for &piece in &queue[PREVIEWS..] {
+        let requested_at = Instant::now();
+        interface.request_next_move(0);
+        let info = match interface.block_next_move() {
+            Some((_, info)) => info,
+            None => {
+                topped_out = true;
+                break;
+            }
+        };
+        latency.record(requested_at.elapsed());
+        played += 1;
+        total_nodes += u64::from(info.nodes);
+        total_depth += u64::from(info.depth);
+        interface.add_next_piece(piece);
+    }
+    BenchmarkReport {
+        moves: played,
+        topped_out,
+        elapsed: started.elapsed(),
+        total_nodes,
+        average_depth: match played {
+            0 => 0.0,
+            played => total_depth as f64 / f64::from(played),
+        },
+        latency: latency.stats(),
+    }
+}
+
+pub(crate) fn bag_queue(len: usize, seed: u64) -> Vec<Piece> {
+    let mut rng = SplitMix64(seed);
+    let mut queue = Vec::with_capacity(len + 7);
+    while queue.len() < len {
+        let mut bag = [
+            Piece::I,
+            Piece::O,
+            Piece::T,
+            Piece::L,
+            Piece::J,
+            Piece::S,
+            Piece::Z,
+        ];
+        for i in (1..bag.len()).rev() {
+            bag.swap(i, rng.below(i as u64 + 1) as usize);
+        }
+        queue.extend_from_slice(&bag);
+    }
+    queue.truncate(len);
+    queue
+}
@@ -0,0 +1,754 @@
+use crate::build_info::COLD_CLEAR_VERSION;
+use crate::fumen;
+use crate::protocol::{CommandError, ErrorCode};
+use libtetris::{
+    find_moves, Board, FallingPiece, MovementMode, Piece, PieceState, RotationState, TspinStatus,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+#[derive(Debug)]
+pub enum BookError {
+    Io(PathBuf, std::io::Error),
+    Invalid(PathBuf, String),
+}
+
+// Opening books, loaded from `<name>.json` files. A book is a list of positions and the placement
+// to make in each; a position is the field, as rows of `x` (filled) and `.` (empty) from the
+// bottom up, with the current piece and the hold piece. A bot launched with a book plays from it
+// for as long as its position is in it, and is relaunched from the board it leaves behind.
+#[derive(Default)]
+pub struct Books {
+    books: HashMap<String, Arc<Book>>,
+}
+
+impl fmt::Debug for Books {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    field: Vec<String>,
+    current: Piece,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    hold: Option<Piece>,
+    location: FallingPiece,
+}
+
+#[derive(PartialEq, Eq, Hash, Serialize, Deserialize, Debug)]
+struct Position {
+    // Bit x is column x; empty rows at the top are left off.
+    rows: Vec<u16>,
+    current: u8,
+    hold: Option<u8>,
+}
+
+impl Position {
+    fn new(mut rows: Vec<u16>, current: Piece, hold: Option<Piece>) -> Position {
+        while rows.last() == Some(&0) {
+            rows.pop();
+        }
+        Position {
+            rows,
+            current: current as u8,
+            hold: hold.map(|hold| hold as u8),
+        }
+    }
+}
+
+impl Books {
+    pub fn load(dir: &Path, cache: Option<&BookCache>) -> Result<Books, BookError> {
+        let mut books = HashMap::new();
+        let entries = dir
+            .read_dir()
+            .map_err(|err| BookError::Io(dir.to_owned(), err))?;
+        for entry in entries {
+            let path = entry
+                .map_err(|err| BookError::Io(dir.to_owned(), err))?
+                .path();
+            if path.extension().map_or(true, |ext| ext != "json") {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+            let book = Book::load(name.clone(), &path, cache)?;
+            books.insert(name, Arc::new(book));
+        }
+        Ok(Books { books })
+    }
+    pub fn get(&self, name: &str) -> Result<Arc<Book>, CommandError> {
+        self.books.get(name).cloned().ok_or_else(|| {
+            CommandError::new(
+                ErrorCode::InvalidArgument,
+                format!("there is no opening book named {:?}", name),
+            )
+        })
+    }
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.books.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+pub struct Book {
+    name: String,
+    moves: HashMap<Position, FallingPiece>,
+}
+
+impl fmt::Debug for Book {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Book({})", self.name)
+    }
+}
+
+impl Book {
+    fn load(name: String, path: &Path, cache: Option<&BookCache>) -> Result<Book, BookError> {
+        let source = fs::read(path).map_err(|err| BookError::Io(path.to_owned(), err))?;
+        let cache = match cache {
+            Some(cache) => cache,
+            None => return Book::parse(name, path, &source),
+        };
+        let key = BookCache::key(&source);
+        // Held until the book is in the cache, so a bridge loading the same book at the same time
+        // waits for this one rather than parsing it too.
+        let _lock = match cache.lock(&name) {
+            Ok(lock) => Some(lock),
+            Err(err) => {
+                warn!("Could not lock the opening book cache: {}", err);
+                None
+            }
+        };
+        if let Some(moves) = cache.get(&name, &key) {
+            debug!("Opening book {} came from the cache", name);
+            return Ok(Book { name, moves });
+        }
+        let book = Book::parse(name, path, &source)?;
+        if let Err(err) = cache.put(&book.name, &key, &book.moves) {
+            warn!("Could not cache the opening book {}: {}", book.name, err);
+        }
+        Ok(book)
+    }
+    fn parse(name: String, path: &Path, source: &[u8]) -> Result<Book, BookError> {
+        let invalid = |message: String| BookError::Invalid(path.to_owned(), message);
+        let entries: Vec<Entry> =
+            serde_json::from_slice(source).map_err(|err| invalid(err.to_string()))?;
+        let mut moves = HashMap::new();
+        for (index, entry) in entries.into_iter().enumerate() {
+            let rows = entry
+                .field
+                .iter()
+                .map(|row| parse_row(row))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| invalid(format!("position {} has a malformed row", index + 1)))?;
+            if rows.len() > 40 {
+                return Err(invalid(format!("position {} is too tall", index + 1)));
+            }
+            let position = Position::new(rows, entry.current, entry.hold);
+            if moves.insert(position, entry.location).is_some() {
+                return Err(invalid(format!("position {} is listed twice", index + 1)));
+            }
+        }
+        Ok(Book { name, moves })
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    // The book's move for the board, if it has one that can be made there.
+    pub fn lookup(
+        &self,
+        board: &Board,
+        mode: MovementMode,
+    ) -> Option<(cold_clear::Move, cold_clear::Info)> {
+        let mut queue = board.next_queue();
+        let current = queue.next()?;
+        let location =
+            *self
+                .moves
+                .get(&Position::new(board_rows(board), current, board.hold_piece))?;
+        let hold = location.kind.0 != current;
+        if hold && board.hold_piece.or_else(|| queue.next()) != Some(location.kind.0) {
+            return None;
+        }
+        let spawned = FallingPiece::spawn(location.kind.0, board)?;
+        let placement = find_moves(board, spawned, mode)
+            .into_iter()
+            .find(|placement| placement.location.same_location(&location))?;
+
+        let mut after = board.clone();
+        let current = after.advance_queue();
+        if let (true, Some(current)) = (hold, current) {
+            if after.hold(current).is_none() {
+                after.advance_queue();
+            }
+        }
+        let lock = after.lock_piece(placement.location);
+        let mv = cold_clear::Move {
+            inputs: placement.inputs.movements.iter().copied().collect(),
+            expected_location: placement.location,
+            hold,
+        };
+        // Book moves aren't searched for.
+        let info = cold_clear::Info {
+            nodes: 0,
+            depth: 0,
+            original_rank: 0,
+            plan: vec![(placement.location, lock)],
+        };
+        Some((mv, info))
+    }
+}
+
+// Builds a book from openers written as fumens, each page placing the next piece. A page's
+// placement is its piece when it has one, and otherwise whatever cells are new since the page
+// before, so fumens from editors and from `fumen::plan` both work; on a first page without a
+// piece, the cells drawn in a piece's colour are the placement if there are four of them. An
+// opener is followed up to the first page that can't be turned into a placement the bot could
+// make, and that page is reported.
+pub struct BookBuilder {
+    mode: MovementMode,
+    entries: Vec<Entry>,
+    moves: HashMap<Position, FallingPiece>,
+    pub problems: Vec<String>,
+}
+
+impl BookBuilder {
+    pub fn new(mode: MovementMode) -> BookBuilder {
+        BookBuilder {
+            mode,
+            entries: vec![],
+            moves: HashMap::new(),
+            problems: vec![],
+        }
+    }
+    // How many positions the book has so far.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    // `name` is how the opener is referred to in problems, e.g. its line in the input.
+    pub fn add(&mut self, name: &str, fumen: &str) {
+        let pages = match fumen::decode(fumen) {
+            Ok(pages) => pages,
+            Err(err) => return self.problems.push(format!("{}: {}", name, err)),
+        };
+        let mut before: Option<Vec<u16>> = None;
+        for (number, page) in pages.iter().enumerate() {
+            let problem = |message: &str| format!("{}, page {}: {}", name, number + 1, message);
+            let drawn: Vec<u16> = page.rows.iter().map(|row| bits(row, |_| true)).collect();
+            let (rows, cells, piece) = match (&page.piece, &before) {
+                (Some((piece, cells)), _) => (drawn, cells.to_vec(), Some(*piece)),
+                (None, Some(before)) => {
+                    if drawn.iter().zip(before).any(|(now, was)| was & !now != 0) {
+                        let message = "the field changed other than by a placement";
+                        self.problems.push(problem(message));
+                        break;
+                    }
+                    let new: Vec<u16> = drawn
+                        .iter()
+                        .zip(before)
+                        .map(|(now, was)| now & !was)
+                        .collect();
+                    if new.iter().all(|&row| row == 0) {
+                        continue;
+                    }
+                    (before.clone(), cells(&new), None)
+                }
+                (None, None) => {
+                    let coloured: Vec<u16> = page
+                        .rows
+                        .iter()
+                        .map(|row| bits(row, |block| block != fumen::GRAY_BLOCK))
+                        .collect();
+                    let coloured = cells(&coloured);
+                    if coloured.len() != 4 {
+                        // The position the opener starts from.
+                        before = Some(drawn);
+                        continue;
+                    }
+                    let rows = page
+                        .rows
+                        .iter()
+                        .map(|row| bits(row, |block| block == fumen::GRAY_BLOCK))
+                        .collect();
+                    (rows, coloured, None)
+                }
+            };
+            let mut board = Board::new();
+            board.set_field(field(&rows));
+            let location = match locate(&cells, piece) {
+                Some(location) => location,
+                None => {
+                    self.problems
+                        .push(problem("the new cells aren't one piece"));
+                    break;
+                }
+            };
+            if !self.reachable(&board, &location) {
+                let message = format!("{:?} can't be placed there", location.kind.0);
+                self.problems.push(problem(&message));
+                break;
+            }
+            let position = Position::new(rows, location.kind.0, None);
+            match self.moves.get(&position) {
+                // Openers often start the same way.
+                Some(known) if known.same_location(&location) => {}
+                Some(_) => {
+                    let message = "an earlier opener places a different piece here";
+                    self.problems.push(problem(message));
+                    break;
+                }
+                None => {
+                    self.entries.push(Entry {
+                        field: position.rows.iter().map(|&row| format_row(row)).collect(),
+                        current: location.kind.0,
+                        hold: None,
+                        location,
+                    });
+                    self.moves.insert(position, location);
+                }
+            }
+            board.lock_piece(location);
+            let mut settled = board_rows(&board);
+            settled.truncate(page.rows.len());
+            before = Some(settled);
+        }
+    }
+    fn reachable(&self, board: &Board, location: &FallingPiece) -> bool {
+        if board.obstructed(location) {
+            return false;
+        }
+        let spawned = match FallingPiece::spawn(location.kind.0, board) {
+            Some(spawned) => spawned,
+            None => return false,
+        };
+        find_moves(board, spawned, self.mode)
+            .iter()
+            .any(|placement| placement.location.same_location(location))
+    }
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(&self.entries)?)
+    }
+}
+
+fn board_rows(board: &Board) -> Vec<u16> {
+    (0..40)
+        .map(|y| {
+            (0..10)
+                .filter(|&x| board.occupied(x, y))
+                .fold(0, |row, x| row | 1 << x)
+        })
+        .collect()
+}
+
+// Bit x is set where `filled` holds for column x's block; empty blocks never count.
+fn bits(row: &[u8; 10], filled: impl Fn(u8) -> bool) -> u16 {
+    row.iter()
+        .enumerate()
+        .filter(|&(_, &block)| block != 0 && filled(block))
+        .fold(0, |bits, (x, _)| bits | 1 << x)
+}
+
+fn cells(rows: &[u16]) -> Vec<(i32, i32)> {
+    let mut cells = vec![];
+    for (y, &row) in rows.iter().enumerate() {
+        for x in 0..10 {
+            if row & 1 << x != 0 {
+                cells.push((x, y as i32));
+            }
+        }
+    }
+    cells
+}
+
+fn field(rows: &[u16]) -> [[bool; 10]; 40] {
+    let mut field = [[false; 10]; 40];
+    for (y, &row) in rows.iter().enumerate().take(40) {
+        for x in 0..10 {
+            field[y][x] = row & 1 << x != 0;
+        }
+    }
+    field
+}
+
+fn format_row(row: u16) -> String {
+    (0..10)
+        .map(|x| if row & 1 << x != 0 { 'x' } else { '.' })
+        .collect()
+}
+
+// The placement that covers exactly `cells`, of `piece` if it is known.
+fn locate(cells: &[(i32, i32)], piece: Option<Piece>) -> Option<FallingPiece> {
+    if cells.len() != 4 {
+        return None;
+    }
+    let mut wanted = cells.to_vec();
+    wanted.sort();
+    let pieces = [
+        Piece::I,
+        Piece::O,
+        Piece::T,
+        Piece::L,
+        Piece::J,
+        Piece::S,
+        Piece::Z,
+    ];
+    let rotations = [
+        RotationState::North,
+        RotationState::East,
+        RotationState::South,
+        RotationState::West,
+    ];
+    let (min_x, max_x) = (
+        wanted.iter().map(|c| c.0).min()?,
+        wanted.iter().map(|c| c.0).max()?,
+    );
+    let (min_y, max_y) = (
+        wanted.iter().map(|c| c.1).min()?,
+        wanted.iter().map(|c| c.1).max()?,
+    );
+    for &kind in pieces
+        .iter()
+        .filter(|&&kind| piece.map_or(true, |piece| piece == kind))
+    {
+        for &rotation in &rotations {
+            for x in min_x - 2..=max_x + 2 {
+                for y in min_y - 2..=max_y + 2 {
+                    let location = FallingPiece {
+                        kind: PieceState(kind, rotation),
+                        x,
+                        y,
+                        tspin: TspinStatus::None,
+                    };
+                    let mut covered: Vec<_> =
+                        location.cells().iter().map(|&(x, y, _)| (x, y)).collect();
+                    covered.sort();
+                    if covered == wanted {
+                        return Some(location);
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+fn parse_row(row: &str) -> Option<u16> {
+    if row.chars().count() != 10 {
+        return None;
+    }
+    row.chars()
+        .enumerate()
+        .try_fold(0, |bits, (x, cell)| match cell {
+            'x' => Some(bits | 1 << x),
+            '.' => Some(bits),
+            _ => None,
+        })
+}
+
+// Books as they are after parsing, kept between runs so large books load quickly. An entry is
+// named after its book and a hash of the book's file, the cache format and the cold clear version,
+// so it is only used while all of them are the same; the entries it replaces are removed once it
+// is written. What a book contains doesn't depend on the evaluator, so the weights aren't part of
+// the key.
+#[derive(Clone, Debug)]
+pub struct BookCache {
+    dir: PathBuf,
+}
+
+impl BookCache {
+    // Bumped whenever what is cached changes shape.
+    const VERSION: u32 = 1;
+
+    pub fn default_dir() -> PathBuf {
+        std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+            .unwrap_or_else(std::env::temp_dir)
+            .join("cc-switch-usb-rs")
+            .join("books")
+    }
+    pub fn new(dir: PathBuf) -> io::Result<BookCache> {
+        fs::create_dir_all(&dir)?;
+        Ok(BookCache { dir })
+    }
+    // Returns how many entries were removed.
+    pub fn clear(&self) -> io::Result<usize> {
+        let mut removed = 0;
+        for entry in self.dir.read_dir()? {
+            let path = entry?.path();
+            if path.extension().map_or(false, |ext| ext == "bin") {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+    fn key(source: &[u8]) -> String {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&BookCache::VERSION.to_le_bytes());
+        hasher.update(COLD_CLEAR_VERSION.as_bytes());
+        hasher.update(source);
+        format!("{:08x}-{}", hasher.finalize(), source.len())
+    }
+    fn path(&self, name: &str, key: &str) -> PathBuf {
+        self.dir.join(format!("{}-{}.bin", name, key))
+    }
+    // An entry that doesn't decode is treated as missing and gets written again.
+    fn get(&self, name: &str, key: &str) -> Option<HashMap<Position, FallingPiece>> {
+        let bytes = fs::read(self.path(name, key)).ok()?;
+        bincode::deserialize(&bytes).ok()
+    }
+    fn put(
+        &self,
+        name: &str,
+        key: &str,
+        moves: &HashMap<Position, FallingPiece>,
+    ) -> io::Result<()> {
+        let bytes = bincode::serialize(moves)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let path = self.path(name, key);
+        // Written aside and moved into place, so nobody reads half an entry.
+        let partial = path.with_extension("partial");
+        fs::write(&partial, bytes)?;
+        fs::rename(&partial, &path)?;
+        let prefix = format!("{}-", name);
+        for entry in self.dir.read_dir()? {
+            let stale = entry?.path();
+            let file_name = match stale.file_name().and_then(|name| name.to_str()) {
+                Some(file_name) => file_name,
+                None => continue,
+            };
+            // Another book's name can start like this one's, but not with a key after it.
+            let is_other_key = file_name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".bin"))
+                .map_or(false, |rest| rest != key && is_key(rest));
+            if is_other_key {
+                fs::remove_file(&stale)?;
+            }
+        }
+        Ok(())
+    }
+    fn lock(&self, name: &str) -> io::Result<File> {
+        lock(&self.dir.join(format!("{}.lock", name)))
+    }
+}
+
+// Whether `key` looks like one made by `BookCache::key`.
+fn is_key(key: &str) -> bool {
+    let bytes = key.as_bytes();
+    bytes.len() > 9
+        && bytes[..8].iter().all(u8::is_ascii_hexdigit)
+        && bytes[8] == b'-'
+        && bytes[9..].iter().all(u8::is_ascii_digit)
+}
+
+#[cfg(unix)]
+fn lock(path: &Path) -> io::Result<File> {
+    use std::os::unix::io::AsRawFd;
+    let file = OpenOptions::new().write(true).create(true).open(path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+// Without a lock two bridges may both parse a book, which only costs the time.
+#[cfg(not(unix))]
+fn lock(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Command;
+    use crate::server::{Bots, Responder, SessionConfig};
+    use serde_json::Value;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+
+    const BOOK: &str = r#"[{
+        "field": [],
+        "current": "T",
+        "location": {"kind": ["T", "North"], "x": 4, "y": 0, "tspin": "None"}
+    }]"#;
+
+    fn dirs(name: &str) -> (PathBuf, BookCache) {
+        let root = std::env::temp_dir().join(format!(
+            "cc-switch-usb-rs-test-{}-{}",
+            std::process::id(),
+            name
+        ));
+        fs::remove_dir_all(&root).ok();
+        let books = root.join("books");
+        fs::create_dir_all(&books).unwrap();
+        (books, BookCache::new(root.join("cache")).unwrap())
+    }
+
+    fn cached(cache: &BookCache) -> Vec<String> {
+        let mut names: Vec<_> = cache
+            .dir
+            .read_dir()
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".bin"))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn cached_books_load_the_same() {
+        let (books, cache) = dirs("same");
+        fs::write(books.join("tki.json"), BOOK).unwrap();
+        let parsed = Books::load(&books, None).unwrap();
+        let first = Books::load(&books, Some(&cache)).unwrap();
+        assert_eq!(cached(&cache).len(), 1);
+        let second = Books::load(&books, Some(&cache)).unwrap();
+        assert_eq!(parsed.books["tki"].moves, first.books["tki"].moves);
+        assert_eq!(parsed.books["tki"].moves, second.books["tki"].moves);
+    }
+
+    #[test]
+    fn changed_books_replace_their_entry() {
+        let (books, cache) = dirs("changed");
+        fs::write(books.join("tki.json"), BOOK).unwrap();
+        fs::write(books.join("tki-2.json"), BOOK).unwrap();
+        Books::load(&books, Some(&cache)).unwrap();
+        let before = cached(&cache);
+        fs::write(books.join("tki.json"), BOOK.replace("\"x\": 4", "\"x\": 5")).unwrap();
+        let loaded = Books::load(&books, Some(&cache)).unwrap();
+        let location = loaded.books["tki"].moves.values().next().unwrap();
+        assert_eq!(location.x, 5);
+        let after = cached(&cache);
+        assert_eq!(after.len(), 2);
+        // The other book's entry is left alone.
+        assert_eq!(before.iter().filter(|name| after.contains(name)).count(), 1);
+        assert_eq!(cache.clear().unwrap(), 2);
+        assert!(cached(&cache).is_empty());
+    }
+
+    // A T in the bottom left corner and an O against the right wall: nothing cold clear would
+    // play on an empty board, so a bot that plays them is playing from the book.
+    fn opener() -> [FallingPiece; 2] {
+        let piece = |kind, x| FallingPiece {
+            kind: PieceState(kind, RotationState::North),
+            x,
+            y: 0,
+            tspin: TspinStatus::None,
+        };
+        [piece(Piece::T, 1), piece(Piece::O, 8)]
+    }
+
+    fn built(name: &str) -> PathBuf {
+        let (books, _) = dirs(name);
+        let mut builder = BookBuilder::new(cold_clear::Options::default().mode);
+        builder.add("opener", &fumen::plan(&Board::new(), &opener()));
+        assert!(builder.problems.is_empty(), "{:?}", builder.problems);
+        assert_eq!(builder.len(), 2);
+        builder.write(&books.join("corner.json")).unwrap();
+        books
+    }
+
+    #[test]
+    fn built_books_hold_the_opener() {
+        let books = Books::load(&built("built"), None).unwrap();
+        let [t, o] = opener();
+        let mut after_t = Board::new();
+        after_t.lock_piece(t);
+        let moves = &books.books["corner"].moves;
+        assert_eq!(moves[&Position::new(vec![], Piece::T, None)], t);
+        let position = Position::new(board_rows(&after_t), Piece::O, None);
+        assert_eq!(moves[&position], o);
+    }
+
+    #[test]
+    fn conflicting_openers_are_reported() {
+        let [t, _] = opener();
+        let other = FallingPiece { x: 7, ..t };
+        let mut builder = BookBuilder::new(cold_clear::Options::default().mode);
+        builder.add("first", &fumen::plan(&Board::new(), &[t]));
+        builder.add("again", &fumen::plan(&Board::new(), &[t]));
+        builder.add("second", &fumen::plan(&Board::new(), &[other]));
+        assert_eq!(builder.len(), 1);
+        assert_eq!(builder.problems.len(), 1);
+        assert!(builder.problems[0].starts_with("second, page 1:"));
+    }
+
+    #[derive(Clone)]
+    struct Answer(Sender<Value>);
+
+    impl Responder for Answer {
+        fn respond(&mut self, msg: &impl Serialize) {
+            self.0.send(serde_json::to_value(msg).unwrap()).ok();
+        }
+    }
+
+    fn command(
+        bots: &mut Bots,
+        out: &mut Answer,
+        answers: &Receiver<Value>,
+        command: Command,
+    ) -> Value {
+        bots.execute(command, out);
+        let mut answer = answers.recv().unwrap();
+        answer.get_mut("Ok").expect("the command failed").take()
+    }
+
+    #[test]
+    fn bots_play_from_built_books() {
+        let config = SessionConfig {
+            books: Arc::new(Books::load(&built("bot"), None).unwrap()),
+            ..Default::default()
+        };
+        let mut bots = Bots::new(&config);
+        let (send, answers) = channel();
+        let mut out = Answer(send);
+        let launch = Command::Launch {
+            options: cold_clear::Options::default(),
+            evaluator: None,
+            preset: None,
+            slot: None,
+            board: None,
+            label: None,
+            plugin: None,
+            coach: false,
+            book: Some("corner".to_owned()),
+        };
+        let handle = command(&mut bots, &mut out, &answers, launch);
+        let handle: u32 = serde_json::from_value(handle).unwrap();
+        for &piece in &[Piece::T, Piece::O, Piece::I, Piece::L, Piece::J] {
+            let add = Command::AddNextPiece { handle, piece };
+            command(&mut bots, &mut out, &answers, add);
+        }
+        let request = Command::RequestNextMove {
+            handle,
+            incoming: 0,
+            budget_ms: None,
+        };
+        command(&mut bots, &mut out, &answers, request);
+        let result = command(
+            &mut bots,
+            &mut out,
+            &answers,
+            Command::BlockNextMove { handle },
+        );
+        let played: FallingPiece =
+            serde_json::from_value(result["move"]["expected_location"].clone()).unwrap();
+        assert!(played.same_location(&opener()[0]));
+        assert_eq!(result["info"]["nodes"], 0);
+    }
+}
@@ -0,0 +1,45 @@
+use serde::Serialize;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_HASH: &str = env!("CC_SWITCH_GIT_HASH");
+pub const BUILD_DATE: &str = env!("CC_SWITCH_BUILD_DATE");
+pub const COLD_CLEAR_VERSION: &str = env!("CC_SWITCH_COLD_CLEAR_VERSION");
+pub const FEATURES: &str = env!("CC_SWITCH_FEATURES");
+pub use crate::protocol::PROTOCOL_VERSION;
+
+#[derive(Serialize, Debug)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_hash: &'static str,
+    pub build_date: &'static str,
+    pub cold_clear_version: &'static str,
+    pub features: Vec<&'static str>,
+    pub protocol_version: u32,
+}
+
+impl BuildInfo {
+    pub fn get() -> BuildInfo {
+        BuildInfo {
+            version: VERSION,
+            git_hash: GIT_HASH,
+            build_date: BUILD_DATE,
+            cold_clear_version: COLD_CLEAR_VERSION,
+            features: FEATURES.split(',').filter(|f| !f.is_empty()).collect(),
+            protocol_version: PROTOCOL_VERSION,
+        }
+    }
+}
+
+impl std::fmt::Display for BuildInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "cc-switch-usb-rs {} ({})", self.version, self.git_hash)?;
+        writeln!(f, "built:      {}", self.build_date)?;
+        writeln!(f, "cold clear: {}", self.cold_clear_version)?;
+        if self.features.is_empty() {
+            writeln!(f, "features:   none")?;
+        } else {
+            writeln!(f, "features:   {}", self.features.join(", "))?;
+        }
+        write!(f, "protocol:   {}", self.protocol_version)
+    }
+}
@@ -0,0 +1,137 @@
+use crate::protocol::FieldRows;
+use cold_clear::evaluation::{Evaluator, Standard};
+use libtetris::{Board, FallingPiece};
+use serde::Serialize;
+use std::cmp::Reverse;
+
+// Judges a human's placements by how much worse the position each leaves is than the one the
+// bot's own choice would have left, as the bot's evaluator sees them. Only the positions right
+// after the placement are compared, not what the bot could go on to make of them.
+#[derive(Default)]
+pub struct Coach {
+    placements: Vec<Coached>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Fine,
+    Inaccuracy,
+    Mistake,
+}
+
+// The answer to a reported placement. `best` is None when the bot had no move to offer, in which
+// case nothing is held against the placement.
+#[derive(Serialize)]
+pub struct Verdict {
+    pub best: Option<cold_clear::Move>,
+    pub loss: i32,
+    pub severity: Severity,
+}
+
+// `piece` counts from 1, and `field` is the board the piece was placed on.
+#[derive(Serialize, Clone)]
+pub struct Coached {
+    pub piece: u32,
+    pub hold: bool,
+    pub played: FallingPiece,
+    pub best: Option<FallingPiece>,
+    pub loss: i32,
+    pub severity: Severity,
+    pub field: FieldRows,
+}
+
+#[derive(Serialize, Clone)]
+pub struct CoachSummary {
+    pub pieces: u32,
+    pub average_loss: f64,
+    pub inaccuracies: u32,
+    pub mistakes: u32,
+    pub worst: Vec<Coached>,
+}
+
+impl Coach {
+    pub const INACCURACY: i32 = 100;
+    pub const MISTAKE: i32 = 400;
+    const WORST: usize = 3;
+
+    pub fn review(
+        &mut self,
+        board: &Board,
+        hold: bool,
+        played: FallingPiece,
+        played_score: i32,
+        best: Option<(cold_clear::Move, i32)>,
+    ) -> Verdict {
+        let loss = best
+            .as_ref()
+            .map_or(0, |&(_, best_score)| (best_score - played_score).max(0));
+        let severity = if loss >= Coach::MISTAKE {
+            Severity::Mistake
+        } else if loss >= Coach::INACCURACY {
+            Severity::Inaccuracy
+        } else {
+            Severity::Fine
+        };
+        let best = best.map(|(mv, _)| mv);
+        self.placements.push(Coached {
+            piece: self.placements.len() as u32 + 1,
+            hold,
+            played,
+            best: best.as_ref().map(|mv| mv.expected_location),
+            loss,
+            severity,
+            field: FieldRows(board.get_field()),
+        });
+        Verdict {
+            best,
+            loss,
+            severity,
+        }
+    }
+    pub fn summary(&self) -> CoachSummary {
+        let count = |severity| {
+            self.placements
+                .iter()
+                .filter(|placement| placement.severity == severity)
+                .count() as u32
+        };
+        let total: i64 = self
+            .placements
+            .iter()
+            .map(|placement| i64::from(placement.loss))
+            .sum();
+        let mut worst: Vec<_> = self
+            .placements
+            .iter()
+            .filter(|placement| placement.loss > 0)
+            .collect();
+        worst.sort_by_key(|placement| Reverse(placement.loss));
+        CoachSummary {
+            pieces: self.placements.len() as u32,
+            average_loss: total as f64 / self.placements.len().max(1) as f64,
+            inaccuracies: count(Severity::Inaccuracy),
+            mistakes: count(Severity::Mistake),
+            worst: worst.into_iter().take(Coach::WORST).cloned().collect(),
+        }
+    }
+}
+
+// How the bot scores the position a placement leaves, the way it scores a node of its search:
+// the position's value plus the reward for the placement. Time spent moving isn't counted, since
+// the console doesn't report how the human got the piece there.
+pub fn score<E>(evaluator: &E, board: &Board, hold: bool, location: FallingPiece) -> i32
+where
+    E: Evaluator<Value = <Standard as Evaluator>::Value, Reward = <Standard as Evaluator>::Reward>,
+{
+    let mut board = board.clone();
+    let current = board.advance_queue();
+    if let (true, Some(current)) = (hold, current) {
+        if board.hold(current).is_none() {
+            board.advance_queue();
+        }
+    }
+    let lock = board.lock_piece(location);
+    let (value, reward) = evaluator.evaluate(&lock, &board, 0, location.kind.0);
+    value.value + reward.value
+}
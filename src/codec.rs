@@ -0,0 +1,210 @@
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_cbor::Value;
+use std::fmt;
+use strum::{EnumString, EnumVariantNames};
+
+// How message payloads are serialized. The handshake itself is always CBOR; the codec agreed on
+// there is used for everything after the welcome.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, EnumString, EnumVariantNames,
+)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum Codec {
+    Cbor,
+    // Slower and bigger, but readable in a packet dump. Meant for development builds of the
+    // homebrew.
+    Json,
+    // Smallest on the wire for the numbers most messages are made of. Bincode needs to know a
+    // message's shape to read it, which the protocol's tagged and optional fields don't give it,
+    // so messages go through `Tree`, which bincode can carry, on the way.
+    Bincode,
+}
+
+// The self-describing shape of a message, as bincode sees it.
+#[derive(Serialize, Deserialize)]
+enum Tree {
+    Null,
+    Bool(bool),
+    Integer(i128),
+    Float(f64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Tree>),
+    Map(Vec<(Tree, Tree)>),
+    Tag(u64, Box<Tree>),
+}
+
+impl Tree {
+    fn from_value(value: Value) -> Result<Tree, CodecError> {
+        Ok(match value {
+            Value::Null => Tree::Null,
+            Value::Bool(value) => Tree::Bool(value),
+            Value::Integer(value) => Tree::Integer(value),
+            Value::Float(value) => Tree::Float(value),
+            Value::Bytes(value) => Tree::Bytes(value),
+            Value::Text(value) => Tree::Text(value),
+            Value::Array(values) => Tree::Array(
+                values
+                    .into_iter()
+                    .map(Tree::from_value)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Map(entries) => Tree::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| Ok((Tree::from_value(key)?, Tree::from_value(value)?)))
+                    .collect::<Result<_, CodecError>>()?,
+            ),
+            Value::Tag(tag, value) => Tree::Tag(tag, Box::new(Tree::from_value(*value)?)),
+            _ => return Err(CodecError::Unsupported),
+        })
+    }
+    fn into_value(self) -> Value {
+        match self {
+            Tree::Null => Value::Null,
+            Tree::Bool(value) => Value::Bool(value),
+            Tree::Integer(value) => Value::Integer(value),
+            Tree::Float(value) => Value::Float(value),
+            Tree::Bytes(value) => Value::Bytes(value),
+            Tree::Text(value) => Value::Text(value),
+            Tree::Array(values) => Value::Array(values.into_iter().map(Tree::into_value).collect()),
+            Tree::Map(entries) => Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key.into_value(), value.into_value()))
+                    .collect(),
+            ),
+            Tree::Tag(tag, value) => Value::Tag(tag, Box::new(value.into_value())),
+        }
+    }
+}
+
+impl Default for Codec {
+    fn default() -> Codec {
+        Codec::Cbor
+    }
+}
+
+#[derive(Debug)]
+pub enum CodecError {
+    Cbor(serde_cbor::Error),
+    Json(serde_json::Error),
+    Bincode(bincode::Error),
+    // A CBOR value with no bincode equivalent.
+    Unsupported,
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CodecError::Cbor(err) => err.fmt(f),
+            CodecError::Json(err) => err.fmt(f),
+            CodecError::Bincode(err) => err.fmt(f),
+            CodecError::Unsupported => write!(f, "the message can't be encoded as bincode"),
+        }
+    }
+}
+
+impl Codec {
+    // The first of the client's choices that the bridge knows, in the client's order of preference.
+    pub fn choose(offered: &[String]) -> Codec {
+        offered
+            .iter()
+            .find_map(|name| name.parse().ok())
+            .unwrap_or_default()
+    }
+    pub fn encode(self, msg: &impl Serialize) -> Result<Vec<u8>, CodecError> {
+        match self {
+            Codec::Cbor => serde_cbor::to_vec(msg).map_err(CodecError::Cbor),
+            Codec::Json => serde_json::to_vec(msg).map_err(CodecError::Json),
+            Codec::Bincode => {
+                let value = serde_cbor::value::to_value(msg).map_err(CodecError::Cbor)?;
+                bincode()
+                    .serialize(&Tree::from_value(value)?)
+                    .map_err(CodecError::Bincode)
+            }
+        }
+    }
+    pub fn decode<T: DeserializeOwned>(self, buf: &[u8]) -> Result<T, CodecError> {
+        match self {
+            Codec::Cbor => serde_cbor::from_slice(buf).map_err(CodecError::Cbor),
+            Codec::Json => serde_json::from_slice(buf).map_err(CodecError::Json),
+            Codec::Bincode => {
+                let tree: Tree = bincode().deserialize(buf).map_err(CodecError::Bincode)?;
+                serde_cbor::value::from_value(tree.into_value()).map_err(CodecError::Cbor)
+            }
+        }
+    }
+}
+
+// Variable-length integers keep small numbers, which are most of them, to a byte or two.
+fn bincode() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_varint_encoding()
+        .with_limit(crate::transport::MAX_FRAME_LEN as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    #[serde(tag = "kind", content = "data")]
+    enum Shape {
+        Empty,
+        Cells {
+            rows: Vec<[bool; 3]>,
+            hole: Option<u8>,
+        },
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Message {
+        id: u32,
+        offset: i64,
+        score: f64,
+        label: Option<String>,
+        shapes: Vec<Shape>,
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let message = Message {
+            id: 70_000,
+            offset: -3,
+            score: 0.25,
+            label: Some("P1".to_owned()),
+            shapes: vec![
+                Shape::Empty,
+                Shape::Cells {
+                    rows: vec![[true, false, true]],
+                    hole: None,
+                },
+            ],
+        };
+        for &codec in &[Codec::Cbor, Codec::Json, Codec::Bincode] {
+            let buf = codec.encode(&message).unwrap();
+            let decoded: Message = codec.decode(&buf).unwrap();
+            assert_eq!(decoded, message, "{:?}", codec);
+        }
+    }
+
+    #[test]
+    fn the_first_known_codec_is_chosen() {
+        let offered = |names: &[&str]| {
+            names
+                .iter()
+                .map(|&name| name.to_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            Codec::choose(&offered(&["zstd", "json", "bincode"])),
+            Codec::Json
+        );
+        assert_eq!(Codec::choose(&offered(&["zstd"])), Codec::Cbor);
+        assert_eq!(Codec::choose(&[]), Codec::Cbor);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::presets::patch;
+use crate::priority::ThreadPolicy;
+use crate::transport::{DeviceFilter, InterfaceFilter, UsbTimeouts};
+use serde::Deserialize;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    Invalid(String),
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(err: io::Error) -> ConfigError {
+        ConfigError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(err: toml::de::Error) -> ConfigError {
+        ConfigError::Parse(err)
+    }
+}
+
+// Everything in the file is optional. Settings that also have a command line flag are only used
+// when the flag isn't given.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub usb: UsbSection,
+    pub transport: TransportSection,
+    pub session: SessionSection,
+    pub threads: ThreadsSection,
+    pub log: LogSection,
+    pub engine: EngineSection,
+    pub ab_test: AbTestSection,
+    defaults: DefaultsSection,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UsbSection {
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub serial: Option<String>,
+    pub interface_class: Option<u8>,
+    pub interface_subclass: Option<u8>,
+    pub interface_protocol: Option<u8>,
+    pub interface_name: Option<String>,
+    pub identify: bool,
+    pub retry_min: Option<u64>,
+    pub retry_max: Option<u64>,
+    pub transfer_timeout_ms: Option<u64>,
+    pub write_deadline: Option<u64>,
+    pub capture: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransportSection {
+    pub listen: Option<SocketAddr>,
+    // Keep serving USB consoles alongside `listen`.
+    pub usb: bool,
+    pub stdio: bool,
+    pub compression: Option<bool>,
+    pub web: Option<SocketAddr>,
+    pub tbp_spectate: Option<SocketAddr>,
+    pub spectate: Option<SocketAddr>,
+    pub spectate_interval_ms: Option<u64>,
+    pub discovery: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionSection {
+    pub warm_pool: Option<usize>,
+    pub idle_timeout: Option<u64>,
+    pub resume_grace: Option<u64>,
+    pub max_bots: Option<usize>,
+    pub evict_idle: bool,
+    pub bot_deadline: Option<u64>,
+    pub presets: Option<PathBuf>,
+    pub plugins: Option<PathBuf>,
+    pub books: Option<PathBuf>,
+    pub book_cache: Option<PathBuf>,
+    pub record_games: Option<PathBuf>,
+    pub record_ttr: bool,
+    pub commands_per_second: Option<f64>,
+    pub command_burst: Option<f64>,
+    pub reset_when_flooding: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThreadsSection {
+    pub bot_cpus: Option<Vec<usize>>,
+    pub bot_nice: Option<i32>,
+    pub usb_nice: Option<i32>,
+    pub bot_threads: Option<u32>,
+    pub max_threads_per_bot: Option<u32>,
+    pub governor: bool,
+    pub governor_min_percent: Option<u8>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSection {
+    pub level: Option<String>,
+    pub json: bool,
+    pub file: Option<PathBuf>,
+    pub max_size_mb: Option<u64>,
+    pub max_age_hours: Option<u64>,
+    pub keep: Option<u32>,
+    pub audit: Option<PathBuf>,
+    pub render: bool,
+}
+
+// Without `tbp`, bots are cold clear.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineSection {
+    pub tbp: Option<PathBuf>,
+    pub tbp_args: Vec<String>,
+}
+
+// Two evaluator presets that take turns playing the bot in `slot`, with every game's result
+// appended to `results`. Off while no profiles are given.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AbTestSection {
+    pub profiles: Vec<String>,
+    pub slot: Option<u8>,
+    pub games_in_a_row: Option<u32>,
+    pub results: Option<PathBuf>,
+    pub blind: bool,
+}
+
+// Like evaluator presets, these only list the fields that differ from cold clear's defaults.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DefaultsSection {
+    options: Option<toml::Value>,
+    evaluator: Option<toml::Value>,
+}
+
+impl ConfigFile {
+    pub fn load(path: &Path) -> Result<ConfigFile, ConfigError> {
+        let text = std::fs::read_to_string(path)?;
+        let mut config: ConfigFile = toml::from_str(&text)?;
+        if config.transport.stdio && config.transport.listen.is_some() {
+            return Err(ConfigError::Invalid(
+                "transport.listen and transport.stdio can't both be set".to_owned(),
+            ));
+        }
+        // libusb takes a zero timeout to mean no timeout at all.
+        if config.usb.transfer_timeout_ms == Some(0) {
+            return Err(ConfigError::Invalid(
+                "usb.transfer_timeout_ms must be at least 1".to_owned(),
+            ));
+        }
+        let cpus = config.threads.bot_cpus.iter().flatten();
+        if let Some(cpu) = cpus.copied().find(|&cpu| cpu >= ThreadPolicy::MAX_CPUS) {
+            return Err(ConfigError::Invalid(format!(
+                "threads.bot_cpus has CPU {}, but CPUs are numbered below {}",
+                cpu,
+                ThreadPolicy::MAX_CPUS
+            )));
+        }
+        let ab_test = &config.ab_test;
+        if !ab_test.profiles.is_empty() {
+            if ab_test.profiles.len() != 2 {
+                return Err(ConfigError::Invalid(
+                    "ab_test.profiles must name exactly two presets".to_owned(),
+                ));
+            }
+            if ab_test.slot.is_none() || ab_test.results.is_none() {
+                return Err(ConfigError::Invalid(
+                    "ab_test needs a slot and a results file".to_owned(),
+                ));
+            }
+        }
+        // Relative paths are relative to the file, not to wherever the bridge was started from.
+        if let Some(dir) = path.parent() {
+            config.session.presets = config.session.presets.map(|presets| dir.join(presets));
+            config.session.plugins = config.session.plugins.map(|plugins| dir.join(plugins));
+            config.session.books = config.session.books.map(|books| dir.join(books));
+            config.session.book_cache = config.session.book_cache.map(|cache| dir.join(cache));
+            config.session.record_games = config.session.record_games.map(|games| dir.join(games));
+            config.log.file = config.log.file.map(|file| dir.join(file));
+            config.log.audit = config.log.audit.map(|audit| dir.join(audit));
+            config.usb.capture = config.usb.capture.map(|capture| dir.join(capture));
+            config.ab_test.results = config.ab_test.results.map(|results| dir.join(results));
+        }
+        // Checked now so mistakes are reported at startup rather than on the first launch.
+        config.default_options()?;
+        config.default_evaluator()?;
+        Ok(config)
+    }
+    pub fn device_filter(&self) -> DeviceFilter {
+        let default = DeviceFilter::default();
+        DeviceFilter {
+            vendor_id: self.usb.vendor_id.unwrap_or(default.vendor_id),
+            product_id: self.usb.product_id.unwrap_or(default.product_id),
+            serial: self.usb.serial.clone(),
+            interface: InterfaceFilter {
+                class: self.usb.interface_class,
+                subclass: self.usb.interface_subclass,
+                protocol: self.usb.interface_protocol,
+                name: self.usb.interface_name.clone(),
+                identify: self.usb.identify,
+            },
+        }
+    }
+    pub fn usb_timeouts(&self) -> UsbTimeouts {
+        let default = UsbTimeouts::default();
+        UsbTimeouts {
+            transfer: self
+                .usb
+                .transfer_timeout_ms
+                .map_or(default.transfer, Duration::from_millis),
+            write_deadline: self
+                .usb
+                .write_deadline
+                .map_or(default.write_deadline, Duration::from_secs),
+        }
+    }
+    pub fn default_options(&self) -> Result<cold_clear::Options, ConfigError> {
+        patch_default(&self.defaults.options, "defaults.options")
+    }
+    pub fn default_evaluator(&self) -> Result<cold_clear::evaluation::Standard, ConfigError> {
+        patch_default(&self.defaults.evaluator, "defaults.evaluator")
+    }
+}
+
+fn patch_default<T>(fields: &Option<toml::Value>, section: &str) -> Result<T, ConfigError>
+where
+    T: Default + serde::Serialize + serde::de::DeserializeOwned,
+{
+    let invalid = |message: String| ConfigError::Invalid(format!("{}: {}", section, message));
+    let fields = match fields {
+        Some(fields) => fields,
+        None => return Ok(T::default()),
+    };
+    let fields = serde_cbor::value::to_value(fields).map_err(|err| invalid(err.to_string()))?;
+    patch(&T::default(), fields).map_err(|err| invalid(err.message))
+}
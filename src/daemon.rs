@@ -0,0 +1,230 @@
+use cc_switch_usb_rs::backoff::Backoff;
+use cc_switch_usb_rs::instance::{InstanceError, InstanceLock};
+use cc_switch_usb_rs::shutdown;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+// Set for the bridge the supervisor runs, which then runs in the foreground like any other.
+const SUPERVISED: &str = "CC_SWITCH_USB_SUPERVISED";
+
+pub fn is_supervised() -> bool {
+    std::env::var_os(SUPERVISED).is_some()
+}
+
+// Forks into the background and leaves the terminal. The parent prints the PID and exits; the
+// returned child has no stdin, stdout or stderr left.
+#[cfg(unix)]
+pub fn detach() -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    match unsafe { libc::fork() } {
+        -1 => return Err(io::Error::last_os_error()),
+        0 => {}
+        pid => {
+            println!("The bridge is running in the background (PID {})", pid);
+            std::process::exit(0);
+        }
+    }
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    for fd in 0..3 {
+        if unsafe { libc::dup2(null.as_raw_fd(), fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn detach() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "--daemon is only supported on Unix; run the bridge as a service instead",
+    ))
+}
+
+// Runs the bridge again with the same arguments until it exits on its own or is shut down,
+// restarting it after crashes and panics. Its stderr, where panics are printed, and the
+// supervisor's own notes are appended to `log`. Returns the exit code to exit with.
+pub fn supervise(pidfile: Option<PathBuf>, log: &Path) -> i32 {
+    // A bridge that stayed up this long is treated as healthy again, so the next crash is
+    // retried quickly.
+    const HEALTHY_AFTER: Duration = Duration::from_secs(300);
+    shutdown::install();
+    let _pidfile = match pidfile.map(|path| InstanceLock::acquire(path, false)) {
+        Some(Ok(pidfile)) => Some(pidfile),
+        Some(Err(InstanceError::AlreadyRunning(pid))) => {
+            note(
+                log,
+                &format!("Another supervisor is already running (PID {})", pid),
+            );
+            return 1;
+        }
+        Some(Err(err)) => {
+            note(log, &format!("Could not write the PID file: {:?}", err));
+            return 1;
+        }
+        None => None,
+    };
+    let mut backoff = Backoff::new(Duration::from_secs(1), Duration::from_secs(60));
+    loop {
+        let started = Instant::now();
+        let status = match spawn(log).and_then(wait) {
+            Ok(status) => status,
+            Err(err) => {
+                note(log, &format!("Could not run the bridge: {}", err));
+                return 1;
+            }
+        };
+        if status.success() || shutdown::requested() {
+            return status.code().unwrap_or(0);
+        }
+        if started.elapsed() >= HEALTHY_AFTER {
+            backoff.reset();
+        }
+        let delay = backoff.next_delay();
+        note(
+            log,
+            &format!(
+                "The bridge exited ({}), restarting it in {:.1} seconds",
+                status,
+                delay.as_secs_f64()
+            ),
+        );
+        let deadline = Instant::now() + delay;
+        while Instant::now() < deadline {
+            if shutdown::requested() {
+                return 0;
+            }
+            std::thread::sleep(shutdown::POLL);
+        }
+    }
+}
+
+fn spawn(log: &Path) -> io::Result<Child> {
+    let stderr = OpenOptions::new().create(true).append(true).open(log)?;
+    Command::new(std::env::current_exe()?)
+        .args(std::env::args_os().skip(1))
+        .env(SUPERVISED, "1")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(stderr)
+        .spawn()
+}
+
+// Passes a shutdown on to the bridge and waits for it to finish shutting down.
+fn wait(mut child: Child) -> io::Result<ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if shutdown::requested() {
+            terminate(&mut child)?;
+            return child.wait();
+        }
+        std::thread::sleep(shutdown::POLL);
+    }
+}
+
+#[cfg(unix)]
+fn terminate(child: &mut Child) -> io::Result<()> {
+    if unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn terminate(child: &mut Child) -> io::Result<()> {
+    child.kill()
+}
+
+// The log file may be rotated by the bridge at any time, so it is opened again for every note.
+fn note(log: &Path, message: &str) {
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(log) {
+        writeln!(file, "supervisor: {}", message).ok();
+    }
+}
+
+// A systemd unit that runs the bridge at boot with the same config file. systemd does the
+// supervising, so the bridge runs in the foreground.
+pub fn systemd_unit(config: Option<&Path>) -> io::Result<String> {
+    let mut exec = std::env::current_exe()?.display().to_string();
+    if let Some(config) = config {
+        exec += &format!(" --config {}", std::fs::canonicalize(config)?.display());
+    }
+    Ok(format!(
+        "[Unit]\n\
+         Description=Cold Clear bridge for the Nintendo Switch\n\
+         After=network.target\n\
+         \n\
+         [Service]\n\
+         ExecStart={}\n\
+         Restart=on-failure\n\
+         RestartSec=5\n\
+         \n\
+         [Install]\n\
+         WantedBy=multi-user.target\n",
+        exec
+    ))
+}
+
+// A socket unit to go with the service, named the same. systemd listens on `addr` itself and
+// starts the bridge, handing it the socket, once the first client connects.
+pub fn systemd_socket(addr: SocketAddr) -> String {
+    format!(
+        "# Save as cc-switch-usb-rs.socket next to cc-switch-usb-rs.service and enable it instead.\n\
+         [Unit]\n\
+         Description=Cold Clear bridge for the Nintendo Switch (TCP socket)\n\
+         \n\
+         [Socket]\n\
+         ListenStream={}\n\
+         \n\
+         [Install]\n\
+         WantedBy=sockets.target\n",
+        addr
+    )
+}
+
+// The same for launchd on macOS.
+pub fn launchd_plist(config: Option<&Path>) -> io::Result<String> {
+    let mut args = vec![std::env::current_exe()?.display().to_string()];
+    if let Some(config) = config {
+        args.push("--config".to_owned());
+        args.push(std::fs::canonicalize(config)?.display().to_string());
+    }
+    let args: String = args
+        .iter()
+        .map(|arg| format!("        <string>{}</string>\n", arg))
+        .collect();
+    Ok(format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>cc-switch-usb-rs</string>
+    <key>ProgramArguments</key>
+    <array>
+{}    </array>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+</dict>
+</plist>
+"#,
+        args
+    ))
+}
@@ -0,0 +1,129 @@
+use cc_switch_usb_rs::transport::{DeviceFilter, SwitchConnection};
+use rusb::UsbContext;
+use std::time::Duration;
+
+const STRING_TIMEOUT: Duration = Duration::from_millis(500);
+
+pub fn list(filter: &DeviceFilter) {
+    let devices = match rusb::Context::new().and_then(|context| context.devices()) {
+        Ok(devices) => devices,
+        Err(err) => {
+            println!("Could not list USB devices: {}", err);
+            return;
+        }
+    };
+    let mut candidates = 0;
+    let mut ready = 0;
+    for device in devices.iter() {
+        let (bus, address) = (device.bus_number(), device.address());
+        let device_desc = match device.device_descriptor() {
+            Ok(device_desc) => device_desc,
+            Err(err) => {
+                println!("  Bus {:03} Device {:03}: {}", bus, address, err);
+                continue;
+            }
+        };
+        let candidate = filter.matches(&device, &device_desc);
+        println!(
+            "{} Bus {:03} Device {:03}: ID {:04x}:{:04x} {}",
+            if candidate { "*" } else { " " },
+            bus,
+            address,
+            device_desc.vendor_id(),
+            device_desc.product_id(),
+            describe(&device, &device_desc)
+        );
+        if !candidate {
+            continue;
+        }
+        candidates += 1;
+        print_interfaces(&device);
+        match SwitchConnection::open(&device, &filter.interface) {
+            Ok(_) => {
+                ready += 1;
+                println!("    The bridge can connect to this device.");
+            }
+            Err(err) => println!("    The bridge can't connect: {}", err.remediation(filter)),
+        }
+    }
+    println!();
+    if candidates == 0 {
+        let serial = match &filter.serial {
+            Some(serial) => format!(" with serial {:?}", serial),
+            None => String::new(),
+        };
+        println!(
+            "No device matches {:04x}:{:04x}{}. Make sure the console is plugged in with a data \
+             cable and the homebrew is running.",
+            filter.vendor_id, filter.product_id, serial
+        );
+    } else {
+        println!(
+            "{} candidate device(s) (marked with *), {} ready to connect.",
+            candidates, ready
+        );
+    }
+}
+
+// Strings can only be read once the device is opened, which is also the first thing to fail
+// without permission.
+fn describe(device: &rusb::Device<rusb::Context>, device_desc: &rusb::DeviceDescriptor) -> String {
+    let handle = match device.open() {
+        Ok(handle) => handle,
+        Err(rusb::Error::Access) => return "(no permission to open)".to_owned(),
+        Err(err) => return format!("(could not open: {})", err),
+    };
+    let language = match handle.read_languages(STRING_TIMEOUT) {
+        Ok(languages) if !languages.is_empty() => languages[0],
+        _ => return String::new(),
+    };
+    let manufacturer = handle
+        .read_manufacturer_string(language, device_desc, STRING_TIMEOUT)
+        .unwrap_or_default();
+    let product = handle
+        .read_product_string(language, device_desc, STRING_TIMEOUT)
+        .unwrap_or_default();
+    let serial = handle
+        .read_serial_number_string(language, device_desc, STRING_TIMEOUT)
+        .unwrap_or_default();
+    let mut description = format!("{} {}", manufacturer, product).trim().to_owned();
+    if !serial.is_empty() {
+        description += &format!(" (serial {})", serial);
+    }
+    description
+}
+
+fn print_interfaces(device: &rusb::Device<rusb::Context>) {
+    // Unconfigured devices have no active configuration yet; the bridge selects the first one.
+    let config_desc = match device
+        .active_config_descriptor()
+        .or_else(|_| device.config_descriptor(0))
+    {
+        Ok(config_desc) => config_desc,
+        Err(err) => {
+            println!("    Could not read the configuration descriptor: {}", err);
+            return;
+        }
+    };
+    for interface in config_desc.interfaces() {
+        for interface_desc in interface.descriptors() {
+            println!(
+                "    Interface {} (alt {}): class {:02x} subclass {:02x} protocol {:02x}",
+                interface_desc.interface_number(),
+                interface_desc.setting_number(),
+                interface_desc.class_code(),
+                interface_desc.sub_class_code(),
+                interface_desc.protocol_code()
+            );
+            for endpoint_desc in interface_desc.endpoint_descriptors() {
+                println!(
+                    "      Endpoint {:02x}: {:?} {:?}, max packet size {}",
+                    endpoint_desc.address(),
+                    endpoint_desc.transfer_type(),
+                    endpoint_desc.direction(),
+                    endpoint_desc.max_packet_size()
+                );
+            }
+        }
+    }
+}
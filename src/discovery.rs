@@ -0,0 +1,76 @@
+use crate::build_info::{PROTOCOL_VERSION, VERSION};
+use crate::shutdown;
+use serde::Serialize;
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+// What the bridge says about itself, as JSON, so a console on the same network can connect over
+// TCP without anyone typing in an address. The console takes the address the datagram came from.
+#[derive(Serialize)]
+pub struct Announcement {
+    pub service: &'static str,
+    pub version: &'static str,
+    pub protocol_version: u32,
+    pub port: u16,
+}
+
+pub struct Discovery;
+
+impl Discovery {
+    pub const PORT: u16 = 37480;
+    // A datagram to `PORT` that starts with this is answered right away.
+    pub const PROBE: &'static [u8] = b"cc-switch-usb-rs?";
+    // Announcements are also broadcast this often, for consoles that only listen.
+    pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5);
+
+    // Answers probes and broadcasts announcements for a bridge listening on `tcp_port`, until the
+    // bridge shuts down.
+    pub fn spawn(tcp_port: u16) -> io::Result<()> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, Discovery::PORT))?;
+        socket.set_broadcast(true)?;
+        let announcement = serde_json::to_vec(&Announcement {
+            service: "cc-switch-usb-rs",
+            version: VERSION,
+            protocol_version: PROTOCOL_VERSION,
+            port: tcp_port,
+        })
+        .unwrap();
+        let broadcast = SocketAddr::from((Ipv4Addr::BROADCAST, Discovery::PORT));
+        std::thread::Builder::new()
+            .name("discovery".to_owned())
+            .spawn(move || {
+                let mut next_announcement = Instant::now();
+                let mut buf = [0; 64];
+                while !shutdown::requested() {
+                    let now = Instant::now();
+                    if now >= next_announcement {
+                        // Fails while the machine has no network, which is fine to wait out.
+                        if let Err(err) = socket.send_to(&announcement, broadcast) {
+                            debug!("Could not broadcast the announcement: {}", err);
+                        }
+                        next_announcement = now + Discovery::ANNOUNCE_INTERVAL;
+                    }
+                    let wait = (next_announcement - now).min(shutdown::POLL);
+                    socket.set_read_timeout(Some(wait)).ok();
+                    // The bridge hears its own announcements too; they aren't probes.
+                    match socket.recv_from(&mut buf) {
+                        Ok((len, from)) if buf[..len].starts_with(Discovery::PROBE) => {
+                            debug!("Discovery probe from {}", from);
+                            socket.send_to(&announcement, from).ok();
+                        }
+                        Ok(_) => {}
+                        Err(err)
+                            if err.kind() == ErrorKind::WouldBlock
+                                || err.kind() == ErrorKind::TimedOut => {}
+                        Err(err) => {
+                            warn!("Discovery stopped: {}", err);
+                            break;
+                        }
+                    }
+                }
+            })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use libtetris::Piece;
+use std::fmt;
+use std::io;
+
+mod tbp;
+
+pub use tbp::{start_message, tbp_move, TbpBot, TbpCommand};
+
+// Which bot plays. Cold clear runs in-process; anything else speaks the Tetris Bot Protocol
+// from a subprocess, one process per bot.
+#[derive(Clone, Debug)]
+pub enum Backend {
+    ColdClear,
+    Tbp(TbpCommand),
+}
+
+impl Default for Backend {
+    fn default() -> Backend {
+        Backend::ColdClear
+    }
+}
+
+#[derive(Debug)]
+pub enum EngineError {
+    Io(io::Error),
+    // The bot said something that isn't TBP, or refused to play.
+    Protocol(String),
+}
+
+impl From<io::Error> for EngineError {
+    fn from(err: io::Error) -> EngineError {
+        EngineError::Io(err)
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineError::Io(err) => write!(f, "{}", err),
+            EngineError::Protocol(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+// The calls the bridge makes on a bot, with the meaning cold clear's Interface gives them. A TBP
+// bot is told about the moves it delivers, so both follow their own moves the same way.
+pub enum Engine {
+    ColdClear(cold_clear::Interface),
+    Tbp(TbpBot),
+}
+
+impl From<cold_clear::Interface> for Engine {
+    fn from(interface: cold_clear::Interface) -> Engine {
+        Engine::ColdClear(interface)
+    }
+}
+
+impl Engine {
+    pub fn request_next_move(&mut self, incoming: u32) {
+        match self {
+            Engine::ColdClear(interface) => interface.request_next_move(incoming),
+            Engine::Tbp(bot) => bot.request_next_move(),
+        }
+    }
+    pub fn poll_next_move(
+        &mut self,
+    ) -> Result<(cold_clear::Move, cold_clear::Info), cold_clear::BotPollState> {
+        match self {
+            Engine::ColdClear(interface) => interface.poll_next_move(),
+            Engine::Tbp(bot) => bot.poll_next_move(),
+        }
+    }
+    pub fn block_next_move(&mut self) -> Option<(cold_clear::Move, cold_clear::Info)> {
+        match self {
+            Engine::ColdClear(interface) => interface.block_next_move(),
+            Engine::Tbp(bot) => bot.block_next_move(),
+        }
+    }
+    pub fn add_next_piece(&mut self, piece: Piece) {
+        match self {
+            Engine::ColdClear(interface) => interface.add_next_piece(piece),
+            Engine::Tbp(bot) => bot.add_next_piece(piece),
+        }
+    }
+    // cold clear searches until it is dropped, so only TBP bots can be paused. Answers whether the
+    // bot is paused.
+    pub fn pause(&mut self) -> bool {
+        match self {
+            Engine::ColdClear(_) => false,
+            Engine::Tbp(bot) => bot.pause(),
+        }
+    }
+    pub fn resume(&mut self) {
+        if let Engine::Tbp(bot) = self {
+            bot.resume();
+        }
+    }
+    pub fn reset(&mut self, field: [[bool; 10]; 40], b2b_active: bool, combo: u32) {
+        match self {
+            Engine::ColdClear(interface) => interface.reset(field, b2b_active, combo),
+            Engine::Tbp(bot) => {
+                let mut board = bot.board().clone();
+                board.set_field(field);
+                board.b2b_bonus = b2b_active;
+                board.combo = combo;
+                bot.restart(board);
+            }
+        }
+    }
+}
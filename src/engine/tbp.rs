@@ -0,0 +1,350 @@
+use super::EngineError;
+use libtetris::{
+    find_moves, Board, FallingPiece, MovementMode, Piece, PieceState, RotationState, TspinStatus,
+};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, TryRecvError};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+#[derive(Clone, Debug)]
+pub struct TbpCommand {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+}
+
+// A bot speaking the Tetris Bot Protocol over its stdin and stdout. The bridge keeps its own copy
+// of the game as the bot sees it, to turn the bot's placements into inputs and to restart it from
+// a new position, which TBP has no message for.
+pub struct TbpBot {
+    child: Option<Child>,
+    stdin: Option<ChildStdin>,
+    messages: Receiver<Value>,
+    board: Board,
+    mode: MovementMode,
+    requested: bool,
+    paused: bool,
+}
+
+impl TbpBot {
+    const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub fn launch(
+        command: &TbpCommand,
+        mode: MovementMode,
+        board: Board,
+    ) -> Result<TbpBot, EngineError> {
+        let mut child = Command::new(&command.program)
+            .args(&command.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let (send, messages) = channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+                match serde_json::from_str(&line) {
+                    Ok(message) => {
+                        if send.send(message).is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) => warn!("The TBP bot sent something that isn't JSON: {}", err),
+                }
+            }
+        });
+        std::thread::spawn(move || {
+            for line in BufReader::new(stderr).lines() {
+                match line {
+                    Ok(line) => debug!("TBP bot: {}", line),
+                    Err(_) => break,
+                }
+            }
+        });
+        let mut bot = TbpBot {
+            stdin: child.stdin.take(),
+            child: Some(child),
+            messages,
+            board: board.clone(),
+            mode,
+            requested: false,
+            paused: false,
+        };
+
+        let hello = bot.expect("info")?;
+        info!(
+            "Launched TBP bot {} {} by {}",
+            hello["name"].as_str().unwrap_or("(unnamed)"),
+            hello["version"].as_str().unwrap_or("(no version)"),
+            hello["author"].as_str().unwrap_or("(unknown)")
+        );
+        bot.send(json!({ "type": "rules" }));
+        bot.expect("ready")?;
+        bot.start(board);
+        Ok(bot)
+    }
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+    // Starts over from `board`, forgetting any move that was requested.
+    pub fn restart(&mut self, board: Board) {
+        self.send(json!({ "type": "stop" }));
+        while self.messages.try_recv().is_ok() {}
+        self.start(board);
+    }
+    pub fn request_next_move(&mut self) {
+        // TBP has no notion of incoming garbage, so asking again changes nothing.
+        if !self.requested {
+            self.send(json!({ "type": "suggest" }));
+            self.requested = true;
+        }
+    }
+    pub fn poll_next_move(
+        &mut self,
+    ) -> Result<(cold_clear::Move, cold_clear::Info), cold_clear::BotPollState> {
+        if !self.requested {
+            return Err(cold_clear::BotPollState::Waiting);
+        }
+        loop {
+            match self.messages.try_recv() {
+                Ok(message) if message["type"] == "suggestion" => {
+                    return self.play(&message).ok_or(cold_clear::BotPollState::Dead)
+                }
+                Ok(message) => self.unexpected(&message),
+                Err(TryRecvError::Empty) => return Err(cold_clear::BotPollState::Waiting),
+                Err(TryRecvError::Disconnected) => return Err(cold_clear::BotPollState::Dead),
+            }
+        }
+    }
+    pub fn block_next_move(&mut self) -> Option<(cold_clear::Move, cold_clear::Info)> {
+        if !self.requested {
+            return None;
+        }
+        loop {
+            let message = self.messages.recv().ok()?;
+            if message["type"] == "suggestion" {
+                return self.play(&message);
+            }
+            self.unexpected(&message);
+        }
+    }
+    pub fn add_next_piece(&mut self, piece: Piece) {
+        self.board.add_next_piece(piece);
+        self.send(json!({ "type": "new_piece", "piece": piece }));
+    }
+    // Stops the bot's process where it is. Anything sent to the bot continues it, so a paused bot
+    // never leaves a request hanging.
+    pub fn pause(&mut self) -> bool {
+        if let (false, Some(child)) = (self.paused, &self.child) {
+            self.paused = signal(child, true);
+        }
+        self.paused
+    }
+    pub fn resume(&mut self) {
+        if let (true, Some(child)) = (self.paused, &self.child) {
+            self.paused = !signal(child, false);
+        }
+    }
+
+    fn start(&mut self, board: Board) {
+        self.send(start_message(&board));
+        self.board = board;
+        self.requested = false;
+    }
+    // Takes the bot's first choice, tells the bot it was played, and works out how to get the
+    // piece there. None means the bot gave up.
+    fn play(&mut self, suggestion: &Value) -> Option<(cold_clear::Move, cold_clear::Info)> {
+        self.requested = false;
+        let chosen = suggestion["moves"].get(0)?;
+        let location = match location(chosen) {
+            Some(location) => location,
+            None => {
+                warn!("The TBP bot suggested an invalid move: {}", chosen);
+                return None;
+            }
+        };
+        let current = self.board.next_queue().next()?;
+        let hold = location.kind.0 != current;
+        let spawned = FallingPiece::spawn(location.kind.0, &self.board)?;
+        let placement = find_moves(&self.board, spawned, self.mode)
+            .into_iter()
+            .find(|placement| placement.location.same_location(&location));
+        let (inputs, location) = match placement {
+            Some(placement) => (
+                placement.inputs.movements.iter().copied().collect(),
+                placement.location,
+            ),
+            None => {
+                warn!("The TBP bot's move can't be reached: {}", chosen);
+                (vec![], location)
+            }
+        };
+        self.send(json!({ "type": "play", "move": chosen }));
+
+        let current = self.board.advance_queue();
+        if let (true, Some(current)) = (hold, current) {
+            if self.board.hold(current).is_none() {
+                self.board.advance_queue();
+            }
+        }
+        let lock = self.board.lock_piece(location);
+        let mv = cold_clear::Move {
+            inputs,
+            expected_location: location,
+            hold,
+        };
+        // A TBP bot doesn't say how it searched, only where the piece goes.
+        let info = cold_clear::Info {
+            nodes: 0,
+            depth: 0,
+            original_rank: 0,
+            plan: vec![(location, lock)],
+        };
+        Some((mv, info))
+    }
+    fn expect(&self, kind: &str) -> Result<Value, EngineError> {
+        let message = match self.messages.recv_timeout(TbpBot::STARTUP_TIMEOUT) {
+            Ok(message) => message,
+            Err(RecvTimeoutError::Timeout) => {
+                let reason = format!("the TBP bot didn't send `{}` in time", kind);
+                return Err(EngineError::Protocol(reason));
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(EngineError::Protocol("the TBP bot exited".to_owned()))
+            }
+        };
+        if message["type"] == kind {
+            return Ok(message);
+        }
+        let reason = match message["type"].as_str() {
+            Some("error") => format!(
+                "the TBP bot refused to play: {}",
+                message["reason"].as_str().unwrap_or("no reason given")
+            ),
+            _ => format!("the TBP bot sent {} instead of `{}`", message, kind),
+        };
+        Err(EngineError::Protocol(reason))
+    }
+    fn unexpected(&self, message: &Value) {
+        debug!("Ignoring a message from the TBP bot: {}", message);
+    }
+    // A bot that has gone away shows up as dead when asked for its move.
+    fn send(&mut self, message: Value) {
+        self.resume();
+        if let Some(stdin) = &mut self.stdin {
+            let mut line = message.to_string();
+            line.push('\n');
+            if let Err(err) = stdin
+                .write_all(line.as_bytes())
+                .and_then(|()| stdin.flush())
+            {
+                debug!("Could not write to the TBP bot: {}", err);
+            }
+        }
+    }
+}
+
+// Asks the bot to quit and closes its stdin, then leaves a thread to reap it.
+impl Drop for TbpBot {
+    fn drop(&mut self) {
+        self.send(json!({ "type": "quit" }));
+        self.stdin = None;
+        if let Some(mut child) = self.child.take() {
+            std::thread::spawn(move || child.wait());
+        }
+    }
+}
+
+// Stops or continues the process.
+#[cfg(unix)]
+fn signal(child: &Child, stop: bool) -> bool {
+    let signal = if stop { libc::SIGSTOP } else { libc::SIGCONT };
+    unsafe { libc::kill(child.id() as libc::pid_t, signal) == 0 }
+}
+
+#[cfg(not(unix))]
+fn signal(_child: &Child, _stop: bool) -> bool {
+    false
+}
+
+// The `start` message for a game at `board`. Only whether cells are filled is known, so they are
+// all garbage.
+pub fn start_message(board: &Board) -> Value {
+    let rows: Vec<Vec<Value>> = (0..40)
+        .map(|y| {
+            (0..10)
+                .map(|x| {
+                    if board.occupied(x, y) {
+                        json!("G")
+                    } else {
+                        Value::Null
+                    }
+                })
+                .collect()
+        })
+        .collect();
+    json!({
+        "type": "start",
+        "hold": board.hold_piece,
+        "queue": board.next_queue().collect::<Vec<_>>(),
+        "combo": board.combo,
+        "back_to_back": board.b2b_bonus,
+        "board": rows,
+    })
+}
+
+// A placement as a TBP move; the inverse of `location`.
+pub fn tbp_move(location: &FallingPiece) -> Value {
+    let orientation = match location.kind.1 {
+        RotationState::North => "north",
+        RotationState::East => "east",
+        RotationState::South => "south",
+        RotationState::West => "west",
+    };
+    let spin = match location.tspin {
+        TspinStatus::None => "none",
+        TspinStatus::Mini => "mini",
+        _ => "full",
+    };
+    json!({
+        "location": {
+            "type": location.kind.0,
+            "orientation": orientation,
+            "x": location.x,
+            "y": location.y,
+        },
+        "spin": spin,
+    })
+}
+
+fn location(chosen: &Value) -> Option<FallingPiece> {
+    let location = &chosen["location"];
+    let piece: Piece = serde_json::from_value(location["type"].clone()).ok()?;
+    let rotation = match location["orientation"].as_str()? {
+        "north" => RotationState::North,
+        "east" => RotationState::East,
+        "south" => RotationState::South,
+        "west" => RotationState::West,
+        _ => return None,
+    };
+    let tspin = match chosen["spin"].as_str().unwrap_or("none") {
+        "mini" => TspinStatus::Mini,
+        "full" => TspinStatus::Full,
+        _ => TspinStatus::None,
+    };
+    Some(FallingPiece {
+        kind: PieceState(piece, rotation),
+        x: location["x"].as_i64()? as i32,
+        y: location["y"].as_i64()? as i32,
+        tspin,
+    })
+}
@@ -0,0 +1,369 @@
+use libtetris::{Board, FallingPiece, Piece};
+use std::convert::TryInto;
+
+// Fumen (v115) as read by fumen.zui.jp and the community tools built on it. Only fields are
+// encoded: every page has the empty piece with line clears off, and the planned piece is drawn
+// into the field in its colour. Decoding also reads the page's piece, and locks it into the field
+// the next page starts from when the page says to.
+
+const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const WIDTH: usize = 10;
+// 23 rows of field plus the garbage row under it.
+const HEIGHT: usize = 24;
+const BLOCKS: usize = WIDTH * HEIGHT;
+const GRAY: u8 = 8;
+
+pub const VIEWER: &str = "https://fumen.zui.jp/?";
+pub const GRAY_BLOCK: u8 = GRAY;
+
+type Page = [u8; BLOCKS];
+
+// A decoded page: the field as drawn, by colour, bottom row first, without the garbage row under
+// it, and the cells of the page's piece if it has one.
+pub struct FumenPage {
+    pub rows: Vec<[u8; WIDTH]>,
+    pub piece: Option<(Piece, [(i32, i32); 4])>,
+}
+
+#[derive(Debug)]
+pub enum FumenError {
+    // Not a v115 fumen.
+    Version,
+    Malformed(&'static str),
+    // Mirroring and rising garbage change the field in ways a book can't follow.
+    Unsupported(&'static str),
+}
+
+impl std::fmt::Display for FumenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FumenError::Version => write!(f, "not a v115 fumen"),
+            FumenError::Malformed(what) => write!(f, "malformed fumen: {}", what),
+            FumenError::Unsupported(what) => write!(f, "{} isn't supported", what),
+        }
+    }
+}
+
+// One page per placement: the board as it is when the piece goes down, with the piece drawn in.
+pub fn plan(board: &Board, plan: &[FallingPiece]) -> String {
+    let mut board = board.clone();
+    let mut pages = Vec::with_capacity(plan.len());
+    for &piece in plan {
+        let mut page = field(&board);
+        for &(x, y, _) in piece.cells().iter() {
+            if let Some(block) = index(x, y) {
+                page[block] = colour(piece.kind.0);
+            }
+        }
+        pages.push(page);
+        board.lock_piece(piece);
+    }
+    if pages.is_empty() {
+        pages.push(field(&board));
+    }
+    encode(&pages)
+}
+
+fn field(board: &Board) -> Page {
+    let mut page = [0; BLOCKS];
+    for y in 0..HEIGHT as i32 - 1 {
+        for x in 0..WIDTH as i32 {
+            if board.occupied(x, y) {
+                page[index(x, y).unwrap()] = GRAY;
+            }
+        }
+    }
+    page
+}
+
+// Pages are stored from the top row down; the garbage row (y = -1) comes last.
+fn index(x: i32, y: i32) -> Option<usize> {
+    if x < 0 || x >= WIDTH as i32 || y < -1 || y >= HEIGHT as i32 - 1 {
+        return None;
+    }
+    Some((HEIGHT as i32 - 2 - y) as usize * WIDTH + x as usize)
+}
+
+fn colour(piece: Piece) -> u8 {
+    match piece {
+        Piece::I => 1,
+        Piece::L => 2,
+        Piece::O => 3,
+        Piece::Z => 4,
+        Piece::T => 5,
+        Piece::J => 6,
+        Piece::S => 7,
+    }
+}
+
+fn encode(pages: &[Page]) -> String {
+    let mut out = vec![];
+    let mut previous = [0; BLOCKS];
+    for (n, page) in pages.iter().enumerate() {
+        // Runs of the same difference from the previous page.
+        let mut run: Option<(u8, usize)> = None;
+        for (&now, &before) in page.iter().zip(previous.iter()) {
+            let diff = now + 8 - before;
+            run = match run {
+                Some((same, len)) if same == diff => Some((same, len + 1)),
+                Some((other, len)) => {
+                    push(&mut out, other as usize * BLOCKS + len - 1, 2);
+                    Some((diff, 1))
+                }
+                None => Some((diff, 1)),
+            };
+        }
+        if let Some((diff, len)) = run {
+            push(&mut out, diff as usize * BLOCKS + len - 1, 2);
+            // An unchanged page is followed by how many more unchanged pages come after it.
+            if diff == 8 && len == BLOCKS {
+                push(&mut out, 0, 1);
+            }
+        }
+        // No piece, no comment, guideline colours on the first page, and line clears off.
+        let colourize = (n == 0) as usize;
+        let flags = 1 << 4 | colourize << 2;
+        push(&mut out, flags * BLOCKS * 32, 3);
+        previous = *page;
+    }
+    format!("v115@{}", String::from_utf8(out).unwrap())
+}
+
+fn push(out: &mut Vec<u8>, mut value: usize, chars: usize) {
+    for _ in 0..chars {
+        out.push(CHARS[value % 64]);
+        value /= 64;
+    }
+}
+
+// Takes a fumen URL as well as the bare data.
+pub fn decode(fumen: &str) -> Result<Vec<FumenPage>, FumenError> {
+    let data = &fumen[fumen.find("v115@").ok_or(FumenError::Version)? + 5..];
+    let mut values = data
+        .bytes()
+        // Long fumens are sometimes broken up with question marks.
+        .filter(|&char| char != b'?' && !char.is_ascii_whitespace())
+        .map(|char| CHARS.iter().position(|&known| known == char))
+        .collect::<Option<Vec<_>>>()
+        .ok_or(FumenError::Malformed("unexpected character"))?
+        .into_iter()
+        .peekable();
+    let mut pages = vec![];
+    let mut previous: Page = [0; BLOCKS];
+    let mut repeat = 0;
+    loop {
+        let mut page = previous;
+        if repeat > 0 {
+            repeat -= 1;
+        } else {
+            let mut block = 0;
+            while block < BLOCKS {
+                let value = poll(&mut values, 2)?;
+                let (diff, len) = (value / BLOCKS, value % BLOCKS + 1);
+                if diff > 16 || block + len > BLOCKS {
+                    return Err(FumenError::Malformed("field runs past the end"));
+                }
+                for cell in &mut page[block..block + len] {
+                    *cell = (*cell as usize + diff)
+                        .checked_sub(8)
+                        .filter(|&colour| colour <= GRAY as usize)
+                        .ok_or(FumenError::Malformed("bad block"))?
+                        as u8;
+                }
+                if diff == 8 && len == BLOCKS {
+                    repeat = poll(&mut values, 1)?;
+                }
+                block += len;
+            }
+        }
+        let action = poll(&mut values, 3)?;
+        let flags = action / 32 / BLOCKS;
+        if flags & 0b11 != 0 {
+            return Err(FumenError::Unsupported("mirroring or rising garbage"));
+        }
+        if flags & 0b1000 != 0 {
+            // The comment's length, then four characters to every five values.
+            let len = poll(&mut values, 2)?;
+            poll(&mut values, 5 * ((len + 3) / 4))?;
+        }
+        let lock = flags & 0b10000 == 0;
+        let piece = match action % 8 {
+            0 => None,
+            kind => Some(piece_cells(
+                kind as u8,
+                action / 8 % 4,
+                action / 32 % BLOCKS,
+            )?),
+        };
+        previous = page;
+        if let (true, Some((piece, cells))) = (lock, piece) {
+            for &(x, y) in &cells {
+                previous[index(x, y).unwrap()] = colour(piece);
+            }
+            clear_lines(&mut previous);
+        }
+        pages.push(FumenPage {
+            rows: (0..HEIGHT as i32 - 1)
+                .map(|y| {
+                    let start = index(0, y).unwrap();
+                    page[start..start + WIDTH].try_into().unwrap()
+                })
+                .collect(),
+            piece,
+        });
+        if values.peek().is_none() {
+            return Ok(pages);
+        }
+    }
+}
+
+fn poll(values: &mut impl Iterator<Item = usize>, chars: usize) -> Result<usize, FumenError> {
+    let mut value = 0;
+    for n in 0..chars {
+        let char = values
+            .next()
+            .ok_or(FumenError::Malformed("data ends too soon"))?;
+        value += char * 64usize.pow(n as u32);
+    }
+    Ok(value)
+}
+
+// `rotation` is fumen's: 0 upside down, 1 clockwise, 2 spawn and 3 counterclockwise. Fumen puts
+// some pieces one cell off from where their rotation centre would be, so that every rotation
+// covers the same cells for the same location.
+fn piece_cells(
+    kind: u8,
+    rotation: usize,
+    location: usize,
+) -> Result<(Piece, [(i32, i32); 4]), FumenError> {
+    let piece = [
+        Piece::I,
+        Piece::L,
+        Piece::O,
+        Piece::Z,
+        Piece::T,
+        Piece::J,
+        Piece::S,
+    ][kind as usize - 1];
+    let mut x = (location % WIDTH) as i32;
+    let mut y = HEIGHT as i32 - 2 - (location / WIDTH) as i32;
+    match (piece, rotation) {
+        (Piece::O, 3) => {
+            x += 1;
+            y -= 1;
+        }
+        (Piece::O, 0) | (Piece::I, 0) | (Piece::Z, 3) => x += 1,
+        (Piece::O, 2) | (Piece::I, 3) | (Piece::S, 2) | (Piece::Z, 2) => y -= 1,
+        (Piece::S, 1) => x -= 1,
+        _ => {}
+    }
+    // As they spawn, pointing up.
+    let shape = match piece {
+        Piece::I => [(0, 0), (-1, 0), (1, 0), (2, 0)],
+        Piece::T => [(0, 0), (-1, 0), (1, 0), (0, 1)],
+        Piece::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
+        Piece::L => [(0, 0), (-1, 0), (1, 0), (1, 1)],
+        Piece::J => [(0, 0), (-1, 0), (1, 0), (-1, 1)],
+        Piece::S => [(0, 0), (-1, 0), (0, 1), (1, 1)],
+        Piece::Z => [(0, 0), (1, 0), (0, 1), (-1, 1)],
+    };
+    let mut cells = [(0, 0); 4];
+    for (cell, &(dx, dy)) in cells.iter_mut().zip(&shape) {
+        let (dx, dy) = match rotation {
+            0 => (-dx, -dy),
+            1 => (dy, -dx),
+            2 => (dx, dy),
+            _ => (-dy, dx),
+        };
+        *cell = (x + dx, y + dy);
+        if index(cell.0, cell.1).is_none() || cell.1 < 0 {
+            return Err(FumenError::Malformed("piece off the field"));
+        }
+    }
+    Ok((piece, cells))
+}
+
+// Full rows above the garbage row go, and the rows over them come down.
+fn clear_lines(page: &mut Page) {
+    let field = (HEIGHT - 1) * WIDTH;
+    let kept: Vec<[u8; WIDTH]> = page[..field]
+        .chunks(WIDTH)
+        .filter(|row| row.iter().any(|&block| block == 0))
+        .map(|row| row.try_into().unwrap())
+        .collect();
+    let cleared = HEIGHT - 1 - kept.len();
+    for (n, row) in std::iter::repeat([0; WIDTH])
+        .take(cleared)
+        .chain(kept)
+        .enumerate()
+    {
+        page[n * WIDTH..(n + 1) * WIDTH].copy_from_slice(&row);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A page with a locked piece on an unchanged field, the way fumen editors write them.
+    fn piece_page(out: &mut Vec<u8>, kind: usize, rotation: usize, location: usize) {
+        push(out, 8 * BLOCKS + BLOCKS - 1, 2);
+        push(out, 0, 1);
+        push(out, kind + rotation * 8 + location * 32, 3);
+    }
+
+    #[test]
+    fn plans_decode_to_what_was_drawn() {
+        let mut board = Board::new();
+        board.set_field({
+            let mut field = [[false; 10]; 40];
+            field[0] = [true, true, true, true, false, false, true, true, true, true];
+            field
+        });
+        let o = FallingPiece {
+            kind: libtetris::PieceState(Piece::O, libtetris::RotationState::North),
+            x: 4,
+            y: 0,
+            tspin: libtetris::TspinStatus::None,
+        };
+        let pages = decode(&format!("{}{}", VIEWER, plan(&board, &[o]))).unwrap();
+        assert_eq!(pages.len(), 1);
+        assert!(pages[0].piece.is_none());
+        let drawn: Vec<_> = pages[0].rows[..2]
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&block| block.to_string())
+                    .collect::<String>()
+            })
+            .collect();
+        assert_eq!(drawn, ["8888338888", "0000330000"]);
+    }
+
+    #[test]
+    fn locked_pieces_carry_over_and_clear_lines() {
+        let mut data = vec![];
+        // An I lying flat along the bottom left, spawn rotation, centred on x = 1.
+        piece_page(&mut data, 1, 2, 22 * WIDTH + 1);
+        // Another at x = 4..7, then an O standing in the last two columns, clearing the row.
+        piece_page(&mut data, 1, 2, 22 * WIDTH + 5);
+        piece_page(&mut data, 3, 2, 21 * WIDTH + 8);
+        piece_page(&mut data, 0, 0, 0);
+        let pages = decode(&format!("v115@{}", String::from_utf8(data).unwrap())).unwrap();
+        assert_eq!(pages.len(), 4);
+        assert_eq!(
+            pages[0].piece,
+            Some((Piece::I, [(1, 0), (0, 0), (2, 0), (3, 0)]))
+        );
+        assert_eq!(pages[1].rows[0], [1, 1, 1, 1, 0, 0, 0, 0, 0, 0]);
+        let (piece, mut cells) = pages[2].piece.unwrap();
+        cells.sort();
+        assert_eq!((piece, cells), (Piece::O, [(8, 0), (8, 1), (9, 0), (9, 1)]));
+        assert_eq!(pages[3].rows[0], [0, 0, 0, 0, 0, 0, 0, 0, 3, 3]);
+    }
+
+    #[test]
+    fn other_versions_are_refused() {
+        assert!(matches!(decode("v110@vhAAgH"), Err(FumenError::Version)));
+    }
+}
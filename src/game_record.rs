@@ -0,0 +1,191 @@
+use crate::build_info::BuildInfo;
+use crate::coach::CoachSummary;
+use crate::protocol::{FieldRows, MoveOutcome};
+use libtetris::{Board, FallingPiece, Piece};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+// Where finished games are written. Every game gets `<started>-<handle>.json` in its own format,
+// and with `ttr` also a `.ttr` file laid out like a TETR.IO replay.
+#[derive(Clone, Debug)]
+pub struct GameRecorder {
+    dir: PathBuf,
+    ttr: bool,
+}
+
+impl GameRecorder {
+    pub fn new(dir: PathBuf, ttr: bool) -> io::Result<GameRecorder> {
+        fs::create_dir_all(&dir)?;
+        Ok(GameRecorder { dir, ttr })
+    }
+    pub fn start(&self, board: &Board) -> GameRecord {
+        let mut record = GameRecord {
+            recorder: self.clone(),
+            start: Instant::now(),
+            build: BuildInfo::get(),
+            handle: 0,
+            label: None,
+            slot: None,
+            started: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |since| since.as_millis() as u64),
+            events: vec![],
+            coach: None,
+        };
+        record.push(GameEvent::Start {
+            field: FieldRows(board.get_field()),
+            hold: board.hold_piece,
+            queue: board.next_queue().collect(),
+            b2b_active: board.b2b_bonus,
+            combo: board.combo,
+        });
+        record
+    }
+}
+
+// `started` is milliseconds since the Unix epoch, and each event's `time` milliseconds since then.
+#[derive(Serialize)]
+pub struct GameRecord {
+    #[serde(skip)]
+    recorder: GameRecorder,
+    #[serde(skip)]
+    start: Instant,
+    // The build that played the game.
+    pub build: BuildInfo,
+    pub handle: u32,
+    pub label: Option<String>,
+    // The player slot the bot was launched into, if it was given one.
+    pub slot: Option<u8>,
+    pub started: u64,
+    pub events: Vec<TimedEvent>,
+    // How the human did, when the bot was coaching.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coach: Option<CoachSummary>,
+}
+
+#[derive(Serialize)]
+pub struct TimedEvent {
+    pub time: u64,
+    #[serde(flatten)]
+    pub event: GameEvent,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum GameEvent {
+    // The position the bot was launched from.
+    Start {
+        field: FieldRows,
+        hold: Option<Piece>,
+        queue: Vec<Piece>,
+        b2b_active: bool,
+        combo: u32,
+    },
+    Piece {
+        piece: Piece,
+    },
+    // `forced` placements were chosen by the console rather than the bot.
+    Place {
+        hold: bool,
+        location: FallingPiece,
+        forced: bool,
+        outcome: MoveOutcome,
+    },
+    // The garbage waiting to come in changed.
+    Incoming {
+        lines: u32,
+    },
+    // The console replaced the field, usually because garbage came up.
+    Reset {
+        field: FieldRows,
+        b2b_active: bool,
+        combo: u32,
+    },
+}
+
+impl GameRecord {
+    pub fn push(&mut self, event: GameEvent) {
+        self.events.push(TimedEvent {
+            time: self.start.elapsed().as_millis() as u64,
+            event,
+        });
+    }
+    // Games in which nothing was placed aren't worth keeping.
+    pub fn save(&self) {
+        if !self
+            .events
+            .iter()
+            .any(|timed| matches!(timed.event, GameEvent::Place { .. }))
+        {
+            return;
+        }
+        let path = self
+            .recorder
+            .dir
+            .join(format!("{}-{}.json", self.started, self.handle));
+        if let Err(err) = write_json(&path, &serde_json::to_value(self).unwrap()) {
+            warn!("Could not write the replay {}: {}", path.display(), err);
+            return;
+        }
+        if self.recorder.ttr {
+            let ttr = path.with_extension("ttr");
+            if let Err(err) = write_json(&ttr, &self.to_ttr()) {
+                warn!("Could not write the replay {}: {}", ttr.display(), err);
+            }
+        }
+        info!("Saved the replay {}", path.display());
+    }
+    // TETR.IO replays are keypresses by frame; the console only reports where pieces went, so
+    // placements appear as `place` events instead, alongside the usual `full`, `ige` and `end`.
+    fn to_ttr(&self) -> Value {
+        let frame = |time: u64| time * 60 / 1000;
+        let mut events = vec![];
+        let mut placed = 0;
+        for timed in &self.events {
+            let (kind, data) = match &timed.event {
+                GameEvent::Start { .. } => ("start", json!({})),
+                GameEvent::Piece { piece } => ("queue", json!({ "piece": piece })),
+                GameEvent::Place {
+                    hold,
+                    location,
+                    outcome,
+                    ..
+                } => {
+                    placed += 1;
+                    (
+                        "place",
+                        json!({ "hold": hold, "location": location, "outcome": outcome }),
+                    )
+                }
+                GameEvent::Incoming { lines } => {
+                    ("ige", json!({ "type": "garbage", "amount": lines }))
+                }
+                GameEvent::Reset { field, .. } => ("full", json!({ "board": field })),
+            };
+            events.push(json!({ "frame": frame(timed.time), "type": kind, "data": data }));
+        }
+        let end = frame(self.events.last().map_or(0, |timed| timed.time));
+        events.push(json!({ "frame": end, "type": "end", "data": {} }));
+        let username = match &self.label {
+            Some(label) => label.clone(),
+            None => format!("handle {}", self.handle),
+        };
+        json!({
+            "ismulti": false,
+            "endcontext": { "piecesplaced": placed },
+            "data": [{
+                "board": [{ "user": { "username": username }, "active": true }],
+                "replays": [{ "frames": end, "events": events }],
+            }],
+        })
+    }
+}
+
+fn write_json(path: &Path, value: &Value) -> io::Result<()> {
+    fs::write(path, serde_json::to_vec_pretty(value)?)
+}
@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GarbageRules {
+    // Maximum number of consecutive rows sharing a hole column; 1 never repeats a column.
+    #[serde(default)]
+    pub max_consecutive: Option<u32>,
+}
+
+// The game's garbage must be reproducible from the seed on every host, so this uses its own
+// fixed generator (SplitMix64) rather than depending on a particular rand version.
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.next() % bound
+    }
+}
+
+pub fn hole_columns(rows: u32, rules: &GarbageRules, seed: u64) -> Vec<usize> {
+    let mut rng = SplitMix64(seed);
+    let mut columns: Vec<usize> = Vec::with_capacity(rows as usize);
+    let mut run = 0;
+    for _ in 0..rows {
+        let previous = columns.last().copied();
+        let column = match (previous, rules.max_consecutive) {
+            (Some(previous), Some(max)) if run >= max => {
+                let column = rng.below(9) as usize;
+                if column >= previous {
+                    column + 1
+                } else {
+                    column
+                }
+            }
+            _ => rng.below(10) as usize,
+        };
+        if Some(column) == previous {
+            run += 1;
+        } else {
+            run = 1;
+        }
+        columns.push(column);
+    }
+    columns
+}
+
+pub fn generate_board(rows: u32, rules: &GarbageRules, seed: u64) -> [[bool; 10]; 40] {
+    let mut field = [[false; 10]; 40];
+    let rows = rows.min(40);
+    for (y, column) in hole_columns(rows, rules, seed).into_iter().enumerate() {
+        // Row 0 is the bottom of the field, so the first hole generated is the deepest.
+        let row = &mut field[rows as usize - 1 - y];
+        *row = [true; 10];
+        row[column] = false;
+    }
+    field
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEEDS: u64 = 300;
+
+    fn rules(max_consecutive: u32) -> GarbageRules {
+        GarbageRules {
+            max_consecutive: Some(max_consecutive),
+        }
+    }
+
+    fn longest_run(columns: &[usize]) -> u32 {
+        let mut longest = 0;
+        let mut run = 0;
+        for (i, column) in columns.iter().enumerate() {
+            run = if i > 0 && columns[i - 1] == *column {
+                run + 1
+            } else {
+                1
+            };
+            longest = longest.max(run);
+        }
+        longest
+    }
+
+    #[test]
+    fn max_consecutive_1_never_repeats_a_column() {
+        for seed in 0..SEEDS {
+            let columns = hole_columns(40, &rules(1), seed);
+            assert!(
+                columns.windows(2).all(|pair| pair[0] != pair[1]),
+                "seed {} repeated a column: {:?}",
+                seed,
+                columns
+            );
+        }
+    }
+
+    #[test]
+    fn runs_never_exceed_max_consecutive() {
+        for max in 1..=4 {
+            for seed in 0..SEEDS {
+                let columns = hole_columns(40, &rules(max), seed);
+                assert!(
+                    longest_run(&columns) <= max,
+                    "seed {} ran past {}: {:?}",
+                    seed,
+                    max,
+                    columns
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn holes_are_in_the_field() {
+        for seed in 0..SEEDS {
+            assert!(hole_columns(40, &GarbageRules::default(), seed)
+                .iter()
+                .all(|&column| column < 10));
+        }
+    }
+
+    #[test]
+    fn same_seed_same_board() {
+        for seed in 0..SEEDS {
+            for rules in &[GarbageRules::default(), rules(2)] {
+                assert_eq!(
+                    generate_board(20, rules, seed),
+                    generate_board(20, rules, seed)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn board_has_one_hole_per_garbage_row() {
+        for seed in 0..SEEDS {
+            let rows = (seed % 41) as u32;
+            let field = generate_board(rows, &rules(2), seed);
+            for (y, row) in field.iter().enumerate() {
+                let filled = row.iter().filter(|&&cell| cell).count();
+                let expected = if y < rows as usize { 9 } else { 0 };
+                assert_eq!(filled, expected, "seed {} row {}", seed, y);
+            }
+        }
+    }
+}
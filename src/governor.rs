@@ -0,0 +1,207 @@
+use std::io;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+// Turns the bots down while the host's CPUs stay overloaded, so that a spike from something else
+// on the machine costs them some strength rather than their deadlines, and back up once it has
+// passed. The level is a percentage that node budgets and search threads are scaled by; it moves
+// one step at a time, down after `SUSTAINED` busy samples in a row and up after `SETTLED` quiet
+// ones, and never below the configured minimum.
+#[derive(Clone, Debug)]
+pub struct Governor {
+    percent: Arc<AtomicU8>,
+}
+
+impl Governor {
+    pub const STEPS: [u8; 4] = [100, 75, 50, 25];
+    const INTERVAL: Duration = Duration::from_secs(1);
+    const OVERLOADED: f64 = 0.9;
+    const RELIEVED: f64 = 0.6;
+    const SUSTAINED: u32 = 5;
+    const SETTLED: u32 = 15;
+
+    // Fails where the CPU load can't be read.
+    pub fn spawn(min_percent: u8) -> io::Result<Governor> {
+        let mut last = cpu_times()?;
+        let percent = Arc::new(AtomicU8::new(100));
+        let governor = Governor {
+            percent: percent.clone(),
+        };
+        let mut hysteresis = Hysteresis {
+            step: 0,
+            busy: 0,
+            quiet: 0,
+            min_percent,
+        };
+        std::thread::Builder::new()
+            .name("governor".to_owned())
+            .spawn(move || loop {
+                std::thread::sleep(Governor::INTERVAL);
+                let now = match cpu_times() {
+                    Ok(now) => now,
+                    Err(err) => {
+                        warn!("Could not read the CPU load, the governor stops: {}", err);
+                        percent.store(100, Ordering::Relaxed);
+                        break;
+                    }
+                };
+                let total = now.1.saturating_sub(last.1);
+                let load = match total {
+                    0 => 0.0,
+                    total => now.0.saturating_sub(last.0) as f64 / total as f64,
+                };
+                last = now;
+                if let Some(level) = hysteresis.sample(load) {
+                    info!(
+                        "CPU {:.0}% busy, bots are now running at {}%",
+                        load * 100.0,
+                        level
+                    );
+                    percent.store(level, Ordering::Relaxed);
+                }
+            })?;
+        Ok(governor)
+    }
+    pub fn percent(&self) -> u8 {
+        self.percent.load(Ordering::Relaxed)
+    }
+}
+
+struct Hysteresis {
+    step: usize,
+    busy: u32,
+    quiet: u32,
+    min_percent: u8,
+}
+
+impl Hysteresis {
+    // Returns the new level when it changes.
+    fn sample(&mut self, load: f64) -> Option<u8> {
+        self.busy = if load >= Governor::OVERLOADED {
+            self.busy + 1
+        } else {
+            0
+        };
+        self.quiet = if load <= Governor::RELIEVED {
+            self.quiet + 1
+        } else {
+            0
+        };
+        let lower = Governor::STEPS
+            .get(self.step + 1)
+            .filter(|&&percent| percent >= self.min_percent);
+        if self.busy >= Governor::SUSTAINED && lower.is_some() {
+            self.step += 1;
+        } else if self.quiet >= Governor::SETTLED && self.step > 0 {
+            self.step -= 1;
+        } else {
+            return None;
+        }
+        self.busy = 0;
+        self.quiet = 0;
+        Some(Governor::STEPS[self.step])
+    }
+}
+
+// Busy and total jiffies across all CPUs since boot.
+#[cfg(target_os = "linux")]
+fn cpu_times() -> io::Result<(u64, u64)> {
+    let stat = std::fs::read_to_string("/proc/stat")?;
+    let times: Vec<u64> = stat
+        .lines()
+        .next()
+        .filter(|line| line.starts_with("cpu "))
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no cpu line in /proc/stat"))?
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|time| time.parse().ok())
+        .collect();
+    let total: u64 = times.iter().sum();
+    // Idle and waiting for IO.
+    let idle = times.iter().skip(3).take(2).sum::<u64>();
+    Ok((total - idle, total))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn cpu_times() -> io::Result<(u64, u64)> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "not supported on this OS",
+    ))
+}
+
+// The options a bot runs with at `percent` of what it asked for.
+pub fn scale(mut options: cold_clear::Options, percent: u8) -> cold_clear::Options {
+    let scale = |n: u32| (u64::from(n) * u64::from(percent) / 100).max(1) as u32;
+    options.min_nodes = scale(options.min_nodes);
+    options.max_nodes = scale(options.max_nodes).max(options.min_nodes);
+    options.threads = scale(options.threads);
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hysteresis(min_percent: u8) -> Hysteresis {
+        Hysteresis {
+            step: 0,
+            busy: 0,
+            quiet: 0,
+            min_percent,
+        }
+    }
+
+    #[test]
+    fn a_spike_does_not_turn_bots_down() {
+        let mut hysteresis = hysteresis(25);
+        for _ in 0..Governor::SUSTAINED - 1 {
+            assert_eq!(hysteresis.sample(1.0), None);
+        }
+        assert_eq!(hysteresis.sample(0.5), None);
+        assert_eq!(hysteresis.sample(1.0), None);
+    }
+
+    #[test]
+    fn steps_down_and_back_up() {
+        let mut hysteresis = hysteresis(25);
+        let levels: Vec<_> = (0..Governor::SUSTAINED * 2)
+            .filter_map(|_| hysteresis.sample(0.95))
+            .collect();
+        assert_eq!(levels, [75, 50]);
+        // In between the thresholds nothing changes.
+        for _ in 0..Governor::SETTLED * 2 {
+            assert_eq!(hysteresis.sample(0.75), None);
+        }
+        let levels: Vec<_> = (0..Governor::SETTLED * 3)
+            .filter_map(|_| hysteresis.sample(0.1))
+            .collect();
+        assert_eq!(levels, [75, 100]);
+    }
+
+    #[test]
+    fn stops_at_the_minimum() {
+        let mut hysteresis = hysteresis(50);
+        let levels: Vec<_> = (0..Governor::SUSTAINED * 4)
+            .filter_map(|_| hysteresis.sample(1.0))
+            .collect();
+        assert_eq!(levels, [75, 50]);
+    }
+
+    #[test]
+    fn scaling_keeps_at_least_one() {
+        let options = cold_clear::Options {
+            min_nodes: 0,
+            max_nodes: 1000,
+            threads: 1,
+            ..Default::default()
+        };
+        let scaled = scale(options, 25);
+        assert_eq!(
+            (scaled.min_nodes, scaled.max_nodes, scaled.threads),
+            (1, 250, 1)
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+#[derive(Debug)]
+pub enum InstanceError {
+    AlreadyRunning(u32),
+    TakeoverTimedOut(u32),
+    TakeoverUnsupported,
+    Io(io::Error),
+}
+
+impl From<io::Error> for InstanceError {
+    fn from(err: io::Error) -> InstanceError {
+        InstanceError::Io(err)
+    }
+}
+
+// The lock is held on the open file rather than by the file existing, so the OS lets go of it
+// when the process dies, however it dies, and a lock left behind by a crash is simply free.
+pub struct InstanceLock {
+    file: File,
+}
+
+impl InstanceLock {
+    pub const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(10);
+    pub fn default_path() -> PathBuf {
+        std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir)
+            .join("cc-switch-usb-rs.lock")
+    }
+    pub fn acquire(path: PathBuf, takeover: bool) -> Result<InstanceLock, InstanceError> {
+        InstanceLock::acquire_with(path, takeover, terminate)
+    }
+    fn acquire_with(
+        path: PathBuf,
+        takeover: bool,
+        mut terminate: impl FnMut(u32) -> Result<(), InstanceError>,
+    ) -> Result<InstanceLock, InstanceError> {
+        let deadline = Instant::now() + InstanceLock::TAKEOVER_TIMEOUT;
+        let mut signalled = false;
+        loop {
+            if let Some(mut file) = try_lock(&path)? {
+                if let Some(pid) = read_pid(&mut file) {
+                    info!("Reclaiming the lock left behind by PID {}", pid);
+                }
+                file.set_len(0)?;
+                file.seek(SeekFrom::Start(0))?;
+                write!(file, "{}", std::process::id())?;
+                file.flush()?;
+                return Ok(InstanceLock { file });
+            }
+            // A PID that doesn't parse yet belongs to an instance that has only just taken the
+            // lock.
+            let owner = File::open(&path)
+                .ok()
+                .and_then(|mut file| read_pid(&mut file));
+            match owner {
+                Some(pid) if !takeover => return Err(InstanceError::AlreadyRunning(pid)),
+                Some(pid) if !signalled => {
+                    info!("Asking the running instance (PID {}) to shut down...", pid);
+                    terminate(pid)?;
+                    signalled = true;
+                }
+                Some(pid) if Instant::now() > deadline => {
+                    return Err(InstanceError::TakeoverTimedOut(pid));
+                }
+                None if Instant::now() > deadline => {
+                    return Err(InstanceError::Io(io::Error::new(
+                        io::ErrorKind::Other,
+                        format!("{} is locked but names no process", path.display()),
+                    )));
+                }
+                _ => {}
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+    }
+}
+
+// The file itself stays, since removing it could pull it out from under an instance that has
+// just opened it to wait for the lock. Emptying it tells readers nobody is running.
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        self.file.set_len(0).ok();
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut pid = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut pid).ok()?;
+    pid.trim().parse().ok()
+}
+
+// Returns None while another instance holds the lock.
+#[cfg(unix)]
+fn try_lock(path: &Path) -> io::Result<Option<File>> {
+    use std::os::unix::io::AsRawFd;
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(Some(file));
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EWOULDBLOCK) => Ok(None),
+        _ => Err(err),
+    }
+}
+
+// Windows won't let anyone else open the file for writing while it is open here, and closes it
+// when the process exits.
+#[cfg(windows)]
+fn try_lock(path: &Path) -> io::Result<Option<File>> {
+    use std::os::windows::fs::OpenOptionsExt;
+    const FILE_SHARE_READ: u32 = 1;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    let opened = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .share_mode(FILE_SHARE_READ)
+        .open(path);
+    match opened {
+        Ok(file) => Ok(Some(file)),
+        Err(err) if err.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(unix)]
+fn terminate(pid: u32) -> Result<(), InstanceError> {
+    if unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn terminate(_pid: u32) -> Result<(), InstanceError> {
+    Err(InstanceError::TakeoverUnsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::sync::mpsc::channel;
+
+    fn lock_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "cc-switch-usb-rs-test-{}-{}.lock",
+            std::process::id(),
+            name
+        ));
+        fs::remove_file(&path).ok();
+        path
+    }
+
+    fn no_takeover(_pid: u32) -> Result<(), InstanceError> {
+        panic!("nothing should be asked to shut down");
+    }
+
+    #[test]
+    fn second_instance_is_refused() {
+        let path = lock_path("refused");
+        let _first = InstanceLock::acquire_with(path.clone(), false, no_takeover).unwrap();
+        match InstanceLock::acquire_with(path, false, no_takeover) {
+            Err(InstanceError::AlreadyRunning(pid)) => assert_eq!(pid, std::process::id()),
+            other => panic!("expected AlreadyRunning, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn released_lock_can_be_taken_again() {
+        let path = lock_path("released");
+        drop(InstanceLock::acquire_with(path.clone(), false, no_takeover).unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        InstanceLock::acquire_with(path, false, no_takeover).unwrap();
+    }
+
+    #[test]
+    fn stale_lock_is_reclaimed() {
+        let path = lock_path("stale");
+        // What a crashed instance leaves behind: its PID, with nobody holding the lock.
+        fs::write(&path, "4294967295").unwrap();
+        let _lock = InstanceLock::acquire_with(path.clone(), false, no_takeover).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            std::process::id().to_string()
+        );
+    }
+
+    #[test]
+    fn takeover_waits_for_the_old_instance() {
+        let path = lock_path("takeover");
+        let old = InstanceLock::acquire_with(path.clone(), false, no_takeover).unwrap();
+        let (asked, shut_down) = channel();
+        let new = std::thread::spawn(move || {
+            InstanceLock::acquire_with(path, true, |pid| {
+                asked.send(pid).unwrap();
+                Ok(())
+            })
+            .map(|_| ())
+        });
+        assert_eq!(shut_down.recv().unwrap(), std::process::id());
+        drop(old);
+        new.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,113 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct LatencyStats {
+    pub samples: u32,
+    pub min_ms: f64,
+    pub median_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+    pub usb_rtt_ms: Option<f64>,
+}
+
+pub struct LatencyWindow {
+    samples: VecDeque<Duration>,
+    capacity: usize,
+}
+
+impl LatencyWindow {
+    pub const CAPACITY: usize = 100;
+    pub fn new() -> LatencyWindow {
+        LatencyWindow::with_capacity(LatencyWindow::CAPACITY)
+    }
+    pub fn with_capacity(capacity: usize) -> LatencyWindow {
+        LatencyWindow {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+    pub fn record(&mut self, latency: Duration) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(latency);
+    }
+    pub fn median(&self) -> Option<Duration> {
+        let mut sorted: Vec<_> = self.samples.iter().copied().collect();
+        sorted.sort();
+        sorted.get(sorted.len() / 2).copied()
+    }
+    pub fn stats(&self) -> LatencyStats {
+        let mut sorted: Vec<_> = self.samples.iter().copied().collect();
+        sorted.sort();
+        let percentile = |p: usize| match sorted.len() {
+            0 => 0.0,
+            len => ms(sorted[(len - 1) * p / 100]),
+        };
+        LatencyStats {
+            samples: sorted.len() as u32,
+            min_ms: percentile(0),
+            median_ms: percentile(50),
+            p95_ms: percentile(95),
+            max_ms: percentile(100),
+            usb_rtt_ms: None,
+        }
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Milliseconds go through seconds as f64, which needn't come back exact.
+    fn assert_ms(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn stats_are_taken_over_the_window() {
+        let mut window = LatencyWindow::new();
+        for latency in (1..=100).rev() {
+            window.record(Duration::from_millis(latency));
+        }
+        let stats = window.stats();
+        assert_eq!(stats.samples, 100);
+        assert_ms(stats.min_ms, 1.0);
+        assert_ms(stats.median_ms, 50.0);
+        assert_ms(stats.p95_ms, 95.0);
+        assert_ms(stats.max_ms, 100.0);
+    }
+
+    #[test]
+    fn old_samples_fall_out_of_the_window() {
+        let mut window = LatencyWindow::with_capacity(3);
+        for latency in 1..=5 {
+            window.record(Duration::from_millis(latency));
+        }
+        let stats = window.stats();
+        assert_eq!(stats.samples, 3);
+        assert_ms(stats.min_ms, 3.0);
+        assert_ms(stats.max_ms, 5.0);
+        assert_eq!(window.median(), Some(Duration::from_millis(4)));
+    }
+
+    #[test]
+    fn an_empty_window_has_zeroed_stats() {
+        let window = LatencyWindow::new();
+        let stats = window.stats();
+        assert_eq!(stats.samples, 0);
+        assert_ms(stats.max_ms, 0.0);
+        assert_eq!(window.median(), None);
+    }
+}
@@ -0,0 +1,35 @@
+pub mod ab_test;
+pub mod audit;
+pub mod backoff;
+pub mod benchmark;
+pub mod books;
+pub mod build_info;
+pub mod coach;
+pub mod codec;
+pub mod config;
+pub mod discovery;
+pub mod engine;
+pub mod fumen;
+pub mod game_record;
+pub mod garbage;
+pub mod governor;
+pub mod instance;
+pub mod latency;
+pub mod monitor;
+pub mod pc;
+pub mod plugins;
+pub mod pool;
+pub mod presets;
+pub mod priority;
+pub mod protocol;
+pub mod reloadable;
+pub mod render;
+pub mod replay;
+pub mod resume;
+pub mod selftest;
+pub mod server;
+pub mod shutdown;
+pub mod simulate;
+pub mod transport;
+pub mod tune;
+pub mod versus;
@@ -0,0 +1,195 @@
+use cc_switch_usb_rs::monitor::Monitor;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing_subscriber::EnvFilter;
+
+pub struct FileOptions {
+    pub path: PathBuf,
+    pub max_bytes: u64,
+    pub max_age: Option<Duration>,
+    // Number of rotated files to keep next to the current one.
+    pub keep: u32,
+}
+
+impl FileOptions {
+    pub const DEFAULT_MAX_MB: u64 = 10;
+    pub const DEFAULT_KEEP: u32 = 5;
+}
+
+// Changes the log filter of the running bridge, taking the same filters as `init`.
+pub struct LogFilter(Box<dyn Fn(EnvFilter) -> Result<(), String> + Send + Sync>);
+
+impl LogFilter {
+    pub fn set(&self, filter: Option<&str>) -> Result<(), String> {
+        (self.0)(parse_filter(filter)?)
+    }
+}
+
+// `filter` takes the same directives as RUST_LOG, e.g. `debug` or
+// `info,cc_switch_usb_rs::transport=trace`. Without one, RUST_LOG is used, and without that,
+// everything at info and above. Without a file, logs go to the dashboard if there is one, and
+// otherwise to stderr, since stdout may be carrying the protocol.
+pub fn init(
+    filter: Option<&str>,
+    json: bool,
+    file: Option<FileOptions>,
+    dashboard: Option<Monitor>,
+) -> Result<LogFilter, String> {
+    let filter = parse_filter(filter)?;
+    let writer = match (file, dashboard) {
+        (Some(options), _) => {
+            let path = options.path.clone();
+            let file = RotatingFile::open(options)
+                .map_err(|err| format!("could not open {}: {}", path.display(), err))?;
+            LogWriter::File(Arc::new(Mutex::new(file)))
+        }
+        (None, Some(monitor)) => LogWriter::Dashboard(monitor),
+        (None, None) => LogWriter::Stderr,
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(writer.is_stderr())
+        .with_writer(move || writer.clone());
+    let reload = if json {
+        let builder = builder.json().with_filter_reloading();
+        let handle = builder.reload_handle();
+        builder.init();
+        LogFilter(Box::new(move |filter| {
+            handle.reload(filter).map_err(|err| err.to_string())
+        }))
+    } else {
+        let builder = builder.with_filter_reloading();
+        let handle = builder.reload_handle();
+        builder.init();
+        LogFilter(Box::new(move |filter| {
+            handle.reload(filter).map_err(|err| err.to_string())
+        }))
+    };
+    Ok(reload)
+}
+
+fn parse_filter(filter: Option<&str>) -> Result<EnvFilter, String> {
+    match filter {
+        Some(filter) => EnvFilter::try_new(filter)
+            .map_err(|err| format!("invalid log level `{}`: {}", filter, err)),
+        None => Ok(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"))),
+    }
+}
+
+#[derive(Clone)]
+enum LogWriter {
+    Stderr,
+    File(Arc<Mutex<RotatingFile>>),
+    Dashboard(Monitor),
+}
+
+impl LogWriter {
+    fn is_stderr(&self) -> bool {
+        matches!(self, LogWriter::Stderr)
+    }
+}
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            LogWriter::Stderr => io::stderr().write(buf),
+            LogWriter::File(file) => file.lock().unwrap().write(buf),
+            // Each event arrives in a single write.
+            LogWriter::Dashboard(monitor) => {
+                for line in String::from_utf8_lossy(buf).lines() {
+                    monitor.log(line.to_owned());
+                }
+                Ok(buf.len())
+            }
+        }
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            LogWriter::Stderr => io::stderr().flush(),
+            LogWriter::File(file) => file.lock().unwrap().flush(),
+            LogWriter::Dashboard(_) => Ok(()),
+        }
+    }
+}
+
+// Appends to `path` until it has grown past `max_bytes` or was opened more than `max_age` ago,
+// then renames it to `path.1`, shifting older logs up to `path.<keep>` and dropping the oldest.
+// Events are written whole, so rotation never splits a line.
+struct RotatingFile {
+    options: FileOptions,
+    file: File,
+    written: u64,
+    opened: Instant,
+}
+
+impl RotatingFile {
+    fn open(options: FileOptions) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&options.path)?;
+        Ok(RotatingFile {
+            written: file.metadata()?.len(),
+            options,
+            file,
+            opened: Instant::now(),
+        })
+    }
+    fn due(&self, len: usize) -> bool {
+        if self.written == 0 {
+            return false;
+        }
+        let too_big = self.written + len as u64 > self.options.max_bytes;
+        let too_old = match self.options.max_age {
+            Some(max_age) => self.opened.elapsed() >= max_age,
+            None => false,
+        };
+        too_big || too_old
+    }
+    fn rotate(&mut self) -> io::Result<()> {
+        let path = &self.options.path;
+        for n in (1..self.options.keep).rev() {
+            let from = rotated(path, n);
+            if from.exists() {
+                fs::rename(from, rotated(path, n + 1))?;
+            }
+        }
+        if self.options.keep > 0 {
+            fs::rename(path, rotated(path, 1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+        self.written = 0;
+        self.opened = Instant::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.due(buf.len()) {
+            // Better to keep appending to an oversized file than to lose the event.
+            if let Err(err) = self.rotate() {
+                eprintln!("Could not rotate {}: {}", self.options.path.display(), err);
+            }
+        }
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn rotated(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
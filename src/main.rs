@@ -1,223 +1,1106 @@
-use libtetris::*;
-use serde::{Deserialize, Serialize};
-use serde_big_array::big_array;
-use std::collections::HashMap;
+use cc_switch_usb_rs::ab_test::{AbReport, AbTest};
+use cc_switch_usb_rs::audit::AuditLog;
+use cc_switch_usb_rs::backoff::Backoff;
+use cc_switch_usb_rs::benchmark;
+use cc_switch_usb_rs::books::{BookBuilder, BookCache, Books};
+use cc_switch_usb_rs::build_info::BuildInfo;
+use cc_switch_usb_rs::config::ConfigFile;
+use cc_switch_usb_rs::discovery::Discovery;
+use cc_switch_usb_rs::engine::{Backend, TbpCommand};
+use cc_switch_usb_rs::game_record::GameRecorder;
+use cc_switch_usb_rs::governor::Governor;
+use cc_switch_usb_rs::instance::{InstanceError, InstanceLock};
+use cc_switch_usb_rs::monitor::Monitor;
+use cc_switch_usb_rs::plugins::Plugins;
+use cc_switch_usb_rs::presets::Presets;
+use cc_switch_usb_rs::priority::{self, ThreadPolicy};
+use cc_switch_usb_rs::protocol::EvaluatorChoice;
+use cc_switch_usb_rs::reloadable::Reloadable;
+use cc_switch_usb_rs::replay;
+use cc_switch_usb_rs::resume::SessionStore;
+use cc_switch_usb_rs::selftest;
+use cc_switch_usb_rs::server::{self, RateLimit, RateLimiter, SessionConfig, UsbConfig};
+use cc_switch_usb_rs::shutdown;
+use cc_switch_usb_rs::simulate;
+use cc_switch_usb_rs::transport::{self, Capture};
+use cc_switch_usb_rs::tune::{self, TuneOptions};
+use cc_switch_usb_rs::versus::{self, VersusRules};
+use std::net::{SocketAddr, TcpListener};
+use std::num::ParseIntError;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
+use structopt::StructOpt;
+use tracing::{error, info, warn};
 
-big_array! { BigArray; }
+mod daemon;
+mod devices;
+mod logging;
+mod overlay;
+mod reload;
+mod repl;
+mod setup;
+mod spectate;
+mod tui;
+mod web;
 
-#[derive(Serialize, Deserialize)]
-#[serde(tag = "command", content = "args")]
-enum Command {
-    Launch {
-        options: cold_clear::Options,
-        evaluator: cold_clear::evaluation::Standard,
+#[derive(StructOpt)]
+#[structopt(
+    about = "Bridges Cold Clear to a Nintendo Switch over USB",
+    global_settings = &[structopt::clap::AppSettings::DisableVersion]
+)]
+struct Opt {
+    /// Prints version information
+    #[structopt(short = "V", long)]
+    version: bool,
+    /// Prints build details along with --version
+    #[structopt(short, long)]
+    verbose: bool,
+    /// TOML file with settings to use when the corresponding flags aren't given
+    #[structopt(long)]
+    config: Option<PathBuf>,
+    /// Which logs to show, in RUST_LOG syntax (e.g. `debug`) [default: $RUST_LOG or info]
+    #[structopt(long)]
+    log_level: Option<String>,
+    /// Write logs as JSON lines
+    #[structopt(long)]
+    log_json: bool,
+    /// Write logs to this file instead of stderr, rotating it as it grows
+    #[structopt(long)]
+    log_file: Option<PathBuf>,
+    /// Size in megabytes at which the log file is rotated [default: 10]
+    #[structopt(long)]
+    log_max_size: Option<u64>,
+    /// Hours after which the log file is rotated regardless of its size
+    #[structopt(long)]
+    log_max_age: Option<u64>,
+    /// Number of rotated log files to keep [default: 5]
+    #[structopt(long)]
+    log_keep: Option<u32>,
+    /// Append every command and response to this file as JSON lines, for `replay`
+    #[structopt(long)]
+    audit_log: Option<PathBuf>,
+    /// CPUs to pin bot worker threads to (comma separated)
+    #[structopt(long, use_delimiter = true, parse(try_from_str = priority::parse_cpu))]
+    bot_cpus: Vec<usize>,
+    /// Nice value for bot worker threads
+    #[structopt(long, allow_hyphen_values = true)]
+    bot_nice: Option<i32>,
+    /// Search threads that all bots together may use; bots launched past it get fewer
+    #[structopt(long)]
+    bot_threads: Option<u32>,
+    /// Search threads any one bot may use
+    #[structopt(long)]
+    max_threads_per_bot: Option<u32>,
+    /// Turn the bots' node budgets and threads down while the host's CPUs stay overloaded (Linux only)
+    #[structopt(long)]
+    governor: bool,
+    /// The lowest percentage the governor may turn the bots down to [default: 25]
+    #[structopt(long)]
+    governor_min_percent: Option<u8>,
+    /// Nice value for the USB thread (negative values usually need elevated privileges)
+    #[structopt(long, allow_hyphen_values = true)]
+    usb_nice: Option<i32>,
+    /// Number of interfaces with the default options and evaluator to keep launched in advance [default: 0]
+    #[structopt(long)]
+    warm_pool: Option<usize>,
+    /// Shut down an already running bridge instead of exiting
+    #[structopt(long)]
+    takeover: bool,
+    /// Run in the background and restart the bridge whenever it crashes (Unix only; needs a log file)
+    #[structopt(long, conflicts_with_all = &["tui", "stdio"])]
+    daemon: bool,
+    /// Write the PID of the background process to this file, for stopping it with a signal
+    #[structopt(long, requires = "daemon")]
+    pidfile: Option<PathBuf>,
+    /// USB vendor ID of the switch, in hex [default: 057e]
+    #[structopt(long, parse(try_from_str = parse_hex_u16))]
+    vendor_id: Option<u16>,
+    /// USB product ID of the switch, in hex [default: 3000]
+    #[structopt(long, parse(try_from_str = parse_hex_u16))]
+    product_id: Option<u16>,
+    /// Only connect to the USB device with this serial number
+    #[structopt(long)]
+    serial: Option<String>,
+    /// Only use a USB interface with this class code (in hex)
+    #[structopt(long, parse(try_from_str = parse_hex_u8))]
+    interface_class: Option<u8>,
+    /// Only use a USB interface with this subclass code (in hex)
+    #[structopt(long, parse(try_from_str = parse_hex_u8))]
+    interface_subclass: Option<u8>,
+    /// Only use a USB interface with this protocol code (in hex)
+    #[structopt(long, parse(try_from_str = parse_hex_u8))]
+    interface_protocol: Option<u8>,
+    /// Only use a USB interface whose string descriptor is exactly this
+    #[structopt(long)]
+    interface_name: Option<String>,
+    /// Only claim interfaces that identify themselves as the cold clear client (needs a recent client)
+    #[structopt(long)]
+    identify: bool,
+    /// Write every USB bulk transfer to this file in pcap format, for Wireshark
+    #[structopt(long)]
+    capture: Option<PathBuf>,
+    /// Accept connections over TCP on this address instead of USB
+    #[structopt(long)]
+    listen: Option<SocketAddr>,
+    /// Read commands from stdin and write responses to stdout instead of using USB
+    #[structopt(long, conflicts_with = "listen")]
+    stdio: bool,
+    /// Serve consoles over USB as well as over TCP with --listen, each connection with its own handles
+    #[structopt(long, conflicts_with = "stdio")]
+    usb: bool,
+    /// Show a live dashboard in the terminal instead of logging to stderr
+    #[structopt(long, conflicts_with = "stdio")]
+    tui: bool,
+    /// Serve a page on this address that shows every bot's board and plan as it plays
+    #[structopt(long)]
+    web: Option<SocketAddr>,
+    /// Accept TBP bots on this address and play each one the moves of a console game, as a frontend would
+    #[structopt(long)]
+    tbp_spectate: Option<SocketAddr>,
+    /// Broadcast every bot's board, plan and stats as JSON over a read-only WebSocket on this address, for stream overlays
+    #[structopt(long)]
+    spectate: Option<SocketAddr>,
+    /// Milliseconds between snapshots sent to --spectate viewers [default: 100]
+    #[structopt(long)]
+    spectate_interval_ms: Option<u64>,
+    /// Let consoles on the local network find the bridge listening over TCP, by answering and broadcasting UDP announcements
+    #[structopt(long)]
+    discovery: bool,
+    /// Never compress frames, even if the console supports it
+    #[structopt(long)]
+    no_compression: bool,
+    /// Seconds without any traffic after which the console is considered dead (0 to wait forever) [default: 30]
+    #[structopt(long)]
+    idle_timeout: Option<u64>,
+    /// Seconds to keep a disconnected console's bots around in case it reconnects (0 to drop them immediately) [default: 60]
+    #[structopt(long)]
+    resume_grace: Option<u64>,
+    /// Maximum number of bots a session may have launched at once
+    #[structopt(long)]
+    max_bots: Option<usize>,
+    /// Evict the least recently used idle bot instead of refusing to launch past --max-bots
+    #[structopt(long)]
+    evict_idle: bool,
+    /// Commands a console may send per second on average, pings aside [default: 500]
+    #[structopt(long)]
+    commands_per_second: Option<f64>,
+    /// Commands a console may send at once before the per-second limit applies [default: 100]
+    #[structopt(long)]
+    command_burst: Option<f64>,
+    /// End the session of a console that keeps sending commands past the limit, dropping its bots
+    #[structopt(long)]
+    reset_when_flooding: bool,
+    /// Seconds a bot may take to answer a command before it is relaunched (0 to wait forever) [default: 60]
+    #[structopt(long)]
+    bot_deadline: Option<u64>,
+    /// Directory of evaluator presets (<name>.toml) that Launch can refer to by name
+    #[structopt(long)]
+    presets: Option<PathBuf>,
+    /// Directory of WASM evaluator plugins (<name>.wasm) that Launch can refer to by name
+    #[structopt(long)]
+    plugins: Option<PathBuf>,
+    /// Directory of opening books (<name>.json) that Launch can refer to by name
+    #[structopt(long)]
+    books: Option<PathBuf>,
+    /// Directory to keep parsed opening books in [default: ~/.cache/cc-switch-usb-rs/books]
+    #[structopt(long)]
+    book_cache: Option<PathBuf>,
+    /// Empty the opening book cache before loading the books
+    #[structopt(long)]
+    clear_book_cache: bool,
+    /// Run this Tetris Bot Protocol bot for every handle instead of cold clear
+    #[structopt(long)]
+    tbp: Option<PathBuf>,
+    /// An argument to pass to the TBP bot (may be repeated)
+    #[structopt(long = "tbp-arg", number_of_values = 1, allow_hyphen_values = true)]
+    tbp_args: Vec<String>,
+    /// Log each handle's board after every placement
+    #[structopt(long)]
+    render: bool,
+    /// Write a replay of every handle's game to this directory when the handle is dropped
+    #[structopt(long)]
+    record_games: Option<PathBuf>,
+    /// Also write each replay laid out like a TETR.IO replay (.ttr)
+    #[structopt(long)]
+    record_ttr: bool,
+    #[structopt(subcommand)]
+    subcommand: Option<Subcommand>,
+}
+
+#[derive(StructOpt)]
+enum Subcommand {
+    /// Type commands by hand and send them to a bridge, at --listen or started with --stdio
+    Repl,
+    /// List USB devices and explain why the bridge can or can't connect to candidate consoles
+    ListDevices,
+    /// Print a systemd unit that runs the bridge at boot with the same config file
+    Service {
+        /// Print a launchd job for macOS instead
+        #[structopt(long)]
+        launchd: bool,
+        /// Also print a socket unit, so systemd starts the bridge once something connects to this address over TCP
+        #[structopt(long, conflicts_with = "launchd")]
+        socket: Option<SocketAddr>,
+    },
+    /// Install the udev rule on Linux and check that the bridge can open the console
+    Setup {
+        /// Where to write the udev rule
+        #[structopt(long, default_value = "/etc/udev/rules.d/60-cc-switch-usb.rules")]
+        rules_file: PathBuf,
+        /// Write the udev rule without asking first
+        #[structopt(long)]
+        yes: bool,
+    },
+    /// Run a local bot on a generated queue and report how fast it plays on this machine
+    Benchmark {
+        /// Number of moves to play
+        #[structopt(long, default_value = "200")]
+        moves: u32,
+        /// Search threads, overriding the default options
+        #[structopt(long)]
+        threads: Option<u32>,
+        /// Seed for the piece queue
+        #[structopt(long, default_value = "0")]
+        seed: u64,
     },
-    Drop {
-        handle: u32,
+    /// Connect to a switch and measure the link by having it echo a battery of frames back
+    Selftest,
+    /// Summarize the results of the A/B test in the config file, with each profile's win rate
+    AbReport {
+        /// The results file [default: the one in the config file]
+        file: Option<PathBuf>,
     },
-    RequestNextMove {
-        handle: u32,
-        incoming: u32,
+    /// Write an opening book from openers drawn as fumens, one a line, each page placing a piece
+    BuildBook {
+        /// The book to write, e.g. in the books directory as <name>.json
+        out: PathBuf,
+        /// The fumens [default: read from stdin]
+        #[structopt(long)]
+        input: Option<PathBuf>,
     },
-    PollNextMove {
-        handle: u32,
+    /// Run the commands of a session recorded with --audit-log through local bots again
+    Replay {
+        /// The audit log
+        file: PathBuf,
+        /// Session token (in hex) of the session to replay [default: the first in the file]
+        #[structopt(long, parse(try_from_str = parse_hex_u64))]
+        session: Option<u64>,
+        /// Send the commands as fast as possible instead of with their recorded spacing
+        #[structopt(long)]
+        fast: bool,
     },
-    BlockNextMove {
-        handle: u32,
+    /// Play two local bots against each other, sending each one's attack to the other as garbage
+    Versus {
+        #[structopt(flatten)]
+        rules: RulesArgs,
+        /// Milliseconds to wait between moves, to make the game watchable
+        #[structopt(long, default_value = "0")]
+        pace: u64,
+        /// Evaluator preset for P1
+        #[structopt(long)]
+        p1_preset: Option<String>,
+        /// Evaluator preset for P2
+        #[structopt(long)]
+        p2_preset: Option<String>,
     },
-    AddNextPiece {
-        handle: u32,
-        piece: libtetris::Piece,
+    /// Play versus games between two evaluators and report how each one fares
+    Simulate {
+        /// Number of games to play
+        #[structopt(long, default_value = "100")]
+        games: u32,
+        #[structopt(flatten)]
+        rules: RulesArgs,
+        /// Evaluator preset for A [default: the default evaluator]
+        #[structopt(long)]
+        a: Option<String>,
+        /// Evaluator preset for B [default: the default evaluator]
+        #[structopt(long)]
+        b: Option<String>,
     },
-    Reset {
-        handle: u32,
-        #[serde(with = "BigArray")]
-        field: [[bool; 10]; 40],
-        b2b_active: bool,
-        combo: u32,
+    /// Search for better evaluator weights by playing candidates against the best so far
+    Tune {
+        /// Preset file to write the best weights to after every generation
+        #[structopt(long)]
+        out: PathBuf,
+        /// Evaluator preset to start from [default: the default evaluator]
+        #[structopt(long)]
+        start: Option<String>,
+        /// Number of generations
+        #[structopt(long, default_value = "20")]
+        generations: u32,
+        /// Candidates in each generation
+        #[structopt(long, default_value = "16")]
+        population: usize,
+        /// Best candidates that the next generation is drawn around
+        #[structopt(long, default_value = "4")]
+        elite: usize,
+        /// Games each candidate plays against the best so far
+        #[structopt(long, default_value = "10")]
+        games: u32,
+        #[structopt(flatten)]
+        rules: RulesArgs,
     },
-    DefaultOptions,
-    DefaultEvaluator,
 }
 
-fn main() {
-    fn command(conn: &mut SwitchConnection) -> Command {
-        let mut len = [0; 4];
-        conn.read_all(&mut len).unwrap();
-        let len = u32::from_le_bytes(len) as usize;
-        let mut buf = vec![0; len];
-        conn.read_all(&mut buf).unwrap();
-        serde_cbor::from_slice(&buf).unwrap()
-    }
-    fn result(conn: &mut SwitchConnection, msg: &impl Serialize) {
-        let buf = serde_cbor::to_vec(msg).unwrap();
-        conn.write_all(&(buf.len() as u32).to_be_bytes()).unwrap();
-        conn.write_all(&buf).unwrap();
-    }
-    loop {
-        match SwitchConnection::try_connect() {
-            Ok(mut conn) => {
-                println!("Successfully connected to the switch!");
-                let mut handle_counter: u32 = 0;
-                let mut handles = HashMap::new();
-                loop {
-                    match command(&mut conn) {
-                        Command::Launch { options, evaluator } => {
-                            let interface =
-                                cold_clear::Interface::launch(Board::new(), options, evaluator);
-                            handle_counter = handle_counter.wrapping_add(1);
-                            handles.insert(handle_counter, interface);
-                            result(&mut conn, &handle_counter);
-                        }
-                        Command::Drop { handle } => {
-                            handles.remove(&handle);
-                        }
-                        Command::RequestNextMove { handle, incoming } => {
-                            handles.get(&handle).unwrap().request_next_move(incoming);
-                        }
-                        Command::PollNextMove { handle } => {
-                            result(&mut conn, &handles.get(&handle).unwrap().poll_next_move());
-                        }
-                        Command::BlockNextMove { handle } => {
-                            result(&mut conn, &handles.get(&handle).unwrap().block_next_move());
-                        }
-                        Command::Reset {
-                            handle,
-                            field,
-                            b2b_active,
-                            combo,
-                        } => {
-                            handles
-                                .get(&handle)
-                                .unwrap()
-                                .reset(field, b2b_active, combo);
-                        }
-                        Command::AddNextPiece { handle, piece } => {
-                            handles.get(&handle).unwrap().add_next_piece(piece);
-                        }
-                        Command::DefaultOptions => {
-                            result(&mut conn, &cold_clear::Options::default());
-                        }
-                        Command::DefaultEvaluator => {
-                            result(&mut conn, &cold_clear::evaluation::Standard::default());
-                        }
-                    }
-                }
-            }
-            Err(err) => {
-                println!("Error: {:?}", err);
-                println!("Retrying in 5 seconds...");
-                std::thread::sleep(Duration::from_secs(5));
-            }
+#[derive(StructOpt)]
+struct RulesArgs {
+    /// Pieces each bot places before a game is called a draw
+    #[structopt(long, default_value = "1000")]
+    pieces: u32,
+    /// Seed for the piece queue and garbage holes
+    #[structopt(long, default_value = "0")]
+    seed: u64,
+    /// Pieces a bot places before garbage sent to it lands
+    #[structopt(long, default_value = "1")]
+    garbage_delay: u32,
+    /// Send every attack in full instead of cancelling the attacker's pending garbage first
+    #[structopt(long)]
+    no_cancel: bool,
+}
+
+impl RulesArgs {
+    fn rules(self, pace: Duration, presets: [Option<String>; 2]) -> VersusRules {
+        let [first, second] = presets;
+        let preset = |name: Option<String>| name.map(|name| EvaluatorChoice::Preset { name });
+        VersusRules {
+            garbage_delay: self.garbage_delay,
+            cancel: !self.no_cancel,
+            max_pieces: self.pieces,
+            seed: self.seed,
+            pace,
+            evaluators: [preset(first), preset(second)],
         }
     }
 }
 
-#[derive(Debug)]
-enum SwitchConnectionError {
-    SwitchNotFound,
-    NoInterface,
-    NoInterfaceDescriptor,
-    NoInEndpoint,
-    NoOutEndpoint,
-    RusbError(rusb::Error),
+fn parse_hex_u16(s: &str) -> Result<u16, ParseIntError> {
+    u16::from_str_radix(s.trim_start_matches("0x"), 16)
 }
 
-impl From<rusb::Error> for SwitchConnectionError {
-    fn from(err: rusb::Error) -> SwitchConnectionError {
-        SwitchConnectionError::RusbError(err)
-    }
+fn parse_hex_u64(s: &str) -> Result<u64, ParseIntError> {
+    u64::from_str_radix(s.trim_start_matches("0x"), 16)
 }
 
-struct SwitchConnection {
-    handle: rusb::DeviceHandle<rusb::GlobalContext>,
-    endpoint_in: u8,
-    endpoint_out: u8,
+fn parse_hex_u8(s: &str) -> Result<u8, ParseIntError> {
+    u8::from_str_radix(s.trim_start_matches("0x"), 16)
 }
 
-impl SwitchConnection {
-    pub const SWITCH_VENDOR_ID: u16 = 0x057E;
-    pub const SWITCH_PRODUCT_ID: u16 = 0x3000;
-    pub fn try_connect() -> Result<SwitchConnection, SwitchConnectionError> {
-        for device in rusb::devices()?.iter() {
-            let device_desc = device.device_descriptor()?;
-            if device_desc.vendor_id() == SwitchConnection::SWITCH_VENDOR_ID
-                && device_desc.product_id() == SwitchConnection::SWITCH_PRODUCT_ID
-            {
-                let mut handle = device.open()?;
-                handle.set_active_configuration(1)?;
-                if let Some(interface) = device.active_config_descriptor()?.interfaces().next() {
-                    if let Some(interface_desc) = interface.descriptors().next() {
-                        let mut endpoint_in = None;
-                        let mut endpoint_out = None;
-                        for endpoint_desc in interface_desc.endpoint_descriptors() {
-                            if endpoint_desc.transfer_type() == rusb::TransferType::Bulk {
-                                match endpoint_desc.direction() {
-                                    rusb::Direction::In => {
-                                        if endpoint_in.is_none() {
-                                            endpoint_in = Some(endpoint_desc.address())
-                                        }
-                                    }
-                                    rusb::Direction::Out => {
-                                        if endpoint_out.is_none() {
-                                            endpoint_out = Some(endpoint_desc.address())
-                                        }
-                                    }
-                                }
-                            }
-                            if endpoint_in.is_some() && endpoint_out.is_some() {
-                                handle.claim_interface(interface.number())?;
-                                return Ok(SwitchConnection {
-                                    handle,
-                                    endpoint_in: endpoint_in.unwrap(),
-                                    endpoint_out: endpoint_out.unwrap(),
-                                });
-                            }
-                        }
-                        return Err(if endpoint_in.is_none() {
-                            SwitchConnectionError::NoInEndpoint
-                        } else {
-                            SwitchConnectionError::NoOutEndpoint
-                        });
-                    } else {
-                        return Err(SwitchConnectionError::NoInterfaceDescriptor);
-                    }
-                } else {
-                    return Err(SwitchConnectionError::NoInterface);
+fn main() {
+    let opt = Opt::from_args();
+    if opt.version {
+        let info = BuildInfo::get();
+        if opt.verbose {
+            println!("{}", info);
+        } else {
+            println!("cc-switch-usb-rs {}", info.version);
+        }
+        return;
+    }
+    let file = match &opt.config {
+        Some(path) => match ConfigFile::load(path) {
+            Ok(file) => file,
+            Err(err) => {
+                eprintln!("Could not load {}: {:?}", path.display(), err);
+                std::process::exit(1);
+            }
+        },
+        None => ConfigFile::default(),
+    };
+    let log_level = opt.log_level.as_ref().or(file.log.level.as_ref());
+    let log_file = opt.log_file.clone().or_else(|| file.log.file.clone());
+    let log_file = log_file.map(|path| {
+        let max_mb = opt.log_max_size.or(file.log.max_size_mb);
+        logging::FileOptions {
+            path,
+            max_bytes: max_mb.unwrap_or(logging::FileOptions::DEFAULT_MAX_MB) << 20,
+            max_age: opt
+                .log_max_age
+                .or(file.log.max_age_hours)
+                .map(|hours| Duration::from_secs(hours * 3600)),
+            keep: opt
+                .log_keep
+                .or(file.log.keep)
+                .unwrap_or(logging::FileOptions::DEFAULT_KEEP),
+        }
+    });
+    let log_json = opt.log_json || file.log.json;
+    // Detached from the terminal, the log file is the only place anything can be seen.
+    if opt.daemon && !daemon::is_supervised() {
+        if opt.subcommand.is_some() {
+            eprintln!("Only the bridge itself can run as a daemon, not the subcommands.");
+            std::process::exit(1);
+        }
+        let log = match &log_file {
+            Some(options) => options.path.clone(),
+            None => {
+                eprintln!("--daemon needs a log file (--log-file or log.file in the config).");
+                std::process::exit(1);
+            }
+        };
+        if let Err(err) = daemon::detach() {
+            eprintln!("Could not run in the background: {}", err);
+            std::process::exit(1);
+        }
+        std::process::exit(daemon::supervise(opt.pidfile, &log));
+    }
+    // Flags win over the file, and a transport picked on the command line replaces the file's.
+    let stdio = opt.stdio || (opt.listen.is_none() && file.transport.stdio);
+    let listen = if opt.stdio {
+        None
+    } else {
+        opt.listen.or(file.transport.listen)
+    };
+    if opt.tui && stdio {
+        eprintln!("The dashboard needs the terminal, which --stdio uses for the protocol.");
+        std::process::exit(1);
+    }
+    // Dashboards and game records are for games being played: with a switch, or bot against bot.
+    let watched = matches!(opt.subcommand, None | Some(Subcommand::Versus { .. }));
+    let monitor = Monitor::default();
+    let dashboard = if opt.tui && watched {
+        Some(monitor.clone())
+    } else {
+        None
+    };
+    let log_filter = match logging::init(
+        log_level.map(String::as_str),
+        log_json,
+        log_file,
+        dashboard.clone(),
+    ) {
+        Ok(log_filter) => log_filter,
+        Err(err) => {
+            eprintln!("Could not set up logging: {}", err);
+            std::process::exit(1);
+        }
+    };
+    let max_bots = opt.max_bots.or(file.session.max_bots);
+    let evict_idle = opt.evict_idle || file.session.evict_idle;
+    if evict_idle && max_bots.is_none() {
+        error!("Evicting idle bots needs a bot limit (--max-bots).");
+        std::process::exit(1);
+    }
+    let bot_policy = ThreadPolicy::new(
+        if opt.bot_cpus.is_empty() {
+            file.threads.bot_cpus.clone().unwrap_or_default()
+        } else {
+            opt.bot_cpus
+        },
+        opt.bot_nice.or(file.threads.bot_nice),
+    );
+    if !bot_policy.is_default() {
+        info!(
+            "Bot threads: CPUs {:?}, nice {:?}",
+            bot_policy.cpus, bot_policy.nice
+        );
+    }
+    let presets = match opt.presets.as_ref().or(file.session.presets.as_ref()) {
+        Some(dir) => match Presets::load(dir) {
+            Ok(presets) => {
+                info!("Evaluator presets: {}", presets.names().join(", "));
+                presets
+            }
+            Err(err) => {
+                error!("Could not load the evaluator presets: {:?}", err);
+                std::process::exit(1);
+            }
+        },
+        None => Presets::default(),
+    };
+    let plugins = match opt.plugins.as_ref().or(file.session.plugins.as_ref()) {
+        Some(dir) => match Plugins::load(dir) {
+            Ok(plugins) => {
+                info!("Evaluator plugins: {}", plugins.names().join(", "));
+                plugins
+            }
+            Err(err) => {
+                error!("Could not load the evaluator plugins: {:?}", err);
+                std::process::exit(1);
+            }
+        },
+        None => Plugins::default(),
+    };
+    let book_cache_dir = opt
+        .book_cache
+        .clone()
+        .or_else(|| file.session.book_cache.clone())
+        .unwrap_or_else(BookCache::default_dir);
+    let books_dir = opt.books.as_ref().or(file.session.books.as_ref());
+    // Loading works without the cache, just more slowly.
+    let book_cache = match (books_dir.is_some() || opt.clear_book_cache)
+        .then(|| BookCache::new(book_cache_dir.clone()))
+    {
+        None => None,
+        Some(Ok(cache)) => Some(cache),
+        Some(Err(err)) => {
+            warn!(
+                "Could not create the opening book cache {}: {}",
+                book_cache_dir.display(),
+                err
+            );
+            None
+        }
+    };
+    if let (true, Some(cache)) = (opt.clear_book_cache, &book_cache) {
+        match cache.clear() {
+            Ok(removed) => info!("Cleared {} cached opening books", removed),
+            Err(err) => warn!("Could not clear the opening book cache: {}", err),
+        }
+    }
+    let books = match books_dir {
+        Some(dir) => match Books::load(dir, book_cache.as_ref()) {
+            Ok(books) => {
+                info!("Opening books: {}", books.names().join(", "));
+                books
+            }
+            Err(err) => {
+                error!("Could not load the opening books: {:?}", err);
+                std::process::exit(1);
+            }
+        },
+        None => Books::default(),
+    };
+    let idle_timeout = opt
+        .idle_timeout
+        .or(file.session.idle_timeout)
+        .unwrap_or(SessionConfig::DEFAULT_IDLE_TIMEOUT);
+    let resume_grace = opt.resume_grace.or(file.session.resume_grace).unwrap_or(60);
+    let bot_deadline = opt
+        .bot_deadline
+        .or(file.session.bot_deadline)
+        .unwrap_or(SessionConfig::DEFAULT_BOT_DEADLINE);
+    // Only sessions with a switch are recorded, not the subcommands.
+    let audit = match opt.audit_log.as_ref().or(file.log.audit.as_ref()) {
+        Some(path) if opt.subcommand.is_none() => match AuditLog::open(path) {
+            Ok(audit) => Some(audit),
+            Err(err) => {
+                error!("Could not open the audit log {}: {}", path.display(), err);
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+    let recorder = match opt
+        .record_games
+        .as_ref()
+        .or(file.session.record_games.as_ref())
+    {
+        Some(dir) if watched => {
+            match GameRecorder::new(dir.clone(), opt.record_ttr || file.session.record_ttr) {
+                Ok(recorder) => Some(recorder),
+                Err(err) => {
+                    error!("Could not create {}: {}", dir.display(), err);
+                    std::process::exit(1);
                 }
             }
         }
-        Err(SwitchConnectionError::SwitchNotFound)
+        _ => None,
+    };
+    // Like the audit log, only for sessions with a switch.
+    let ab_test = match (&file.ab_test.slot, &file.ab_test.results) {
+        (Some(slot), Some(path))
+            if opt.subcommand.is_none() && !file.ab_test.profiles.is_empty() =>
+        {
+            let profiles = [
+                file.ab_test.profiles[0].clone(),
+                file.ab_test.profiles[1].clone(),
+            ];
+            if let Some(err) = profiles.iter().find_map(|name| presets.get(name).err()) {
+                error!("A/B test: {}", err.message);
+                std::process::exit(1);
+            }
+            let games_in_a_row = file.ab_test.games_in_a_row.unwrap_or(1);
+            match AbTest::open(profiles, *slot, games_in_a_row, file.ab_test.blind, path) {
+                Ok(ab_test) => {
+                    info!("A/B test in slot {}", slot);
+                    Some(ab_test)
+                }
+                Err(err) => {
+                    error!("Could not open {}: {}", path.display(), err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        _ => None,
+    };
+    let backend = match opt.tbp.clone().or_else(|| file.engine.tbp.clone()) {
+        Some(program) => {
+            let args = if opt.tbp.is_some() {
+                opt.tbp_args.clone()
+            } else {
+                file.engine.tbp_args.clone()
+            };
+            info!("Bots: TBP bot {}", program.display());
+            Backend::Tbp(TbpCommand { program, args })
+        }
+        None => Backend::ColdClear,
+    };
+    let rate_limit = RateLimit {
+        per_second: opt
+            .commands_per_second
+            .or(file.session.commands_per_second)
+            .unwrap_or(RateLimiter::COMMANDS_PER_SECOND),
+        burst: opt
+            .command_burst
+            .or(file.session.command_burst)
+            .unwrap_or(RateLimiter::COMMAND_BURST),
+        reset_when_flooding: opt.reset_when_flooding || file.session.reset_when_flooding,
+    };
+    if !(rate_limit.per_second > 0.0 && rate_limit.burst >= 1.0) {
+        error!("The command rate must be positive and the burst at least 1.");
+        std::process::exit(1);
+    }
+    let governor = if opt.governor || file.threads.governor {
+        let min_percent = opt
+            .governor_min_percent
+            .or(file.threads.governor_min_percent)
+            .unwrap_or(25);
+        if !(1..=100).contains(&min_percent) {
+            error!("--governor-min-percent must be between 1 and 100");
+            std::process::exit(1);
+        }
+        match Governor::spawn(min_percent) {
+            Ok(governor) => Some(governor),
+            Err(err) => {
+                warn!(
+                    "The governor can't run here, bots won't be turned down: {}",
+                    err
+                );
+                None
+            }
+        }
+    } else {
+        None
+    };
+    // ConfigFile::load has already checked that these patch cleanly.
+    let config = SessionConfig {
+        bot_policy,
+        warm_pool: opt.warm_pool.or(file.session.warm_pool).unwrap_or(0),
+        idle_timeout: Reloadable::new(reload::timeout(idle_timeout)),
+        resume_grace: Duration::from_secs(resume_grace),
+        max_bots,
+        evict_idle,
+        bot_threads: opt.bot_threads.or(file.threads.bot_threads),
+        max_threads_per_bot: opt.max_threads_per_bot.or(file.threads.max_threads_per_bot),
+        bot_deadline: Reloadable::new(reload::timeout(bot_deadline)),
+        presets: Reloadable::new(presets),
+        plugins: Arc::new(plugins),
+        books: Arc::new(books),
+        default_options: file.default_options().unwrap(),
+        default_evaluator: file.default_evaluator().unwrap(),
+        compression: !opt.no_compression && file.transport.compression.unwrap_or(true),
+        audit,
+        monitor,
+        recorder,
+        render: opt.render || file.log.render,
+        backend,
+        rate_limit,
+        governor,
+        ab_test,
+    };
+    let mut web = reload::Dashboard::new("Dashboard", web::spawn, config.monitor.clone());
+    let mut tbp_spectate =
+        reload::Dashboard::new("TBP spectators", spectate::spawn, config.monitor.clone());
+    if let (Some(addr), true) = (opt.web.or(file.transport.web), watched) {
+        if let Err(err) = web.listen(Some(addr)) {
+            error!("Could not listen on {}: {}", addr, err);
+            std::process::exit(1);
+        }
+        info!("Dashboard on http://{}", addr);
     }
-    pub fn read(&mut self, buf: &mut [u8]) -> rusb::Result<usize> {
-        self.handle
-            .read_bulk(self.endpoint_in, buf, Duration::from_secs(0))
+    if let (Some(addr), true) = (opt.tbp_spectate.or(file.transport.tbp_spectate), watched) {
+        if let Err(err) = tbp_spectate.listen(Some(addr)) {
+            error!("Could not listen on {}: {}", addr, err);
+            std::process::exit(1);
+        }
+        info!("TBP spectators on {}", addr);
     }
-    pub fn read_all(&mut self, buf: &mut [u8]) -> Result<usize, (usize, rusb::Error)> {
-        let mut read: usize = 0;
-        while read < buf.len() {
-            match self.read(&mut buf[read..]) {
-                Ok(bytes) => read += bytes,
-                Err(rusb::Error::Timeout) => {}
-                Err(err) => return Err((read, err)),
+    if let (Some(addr), true) = (opt.spectate.or(file.transport.spectate), watched) {
+        let interval = opt
+            .spectate_interval_ms
+            .or(file.transport.spectate_interval_ms)
+            .unwrap_or(100);
+        if interval == 0 {
+            error!("--spectate-interval-ms must be at least 1");
+            std::process::exit(1);
+        }
+        match TcpListener::bind(addr) {
+            Ok(listener) => overlay::spawn(
+                listener,
+                config.monitor.clone(),
+                Duration::from_millis(interval),
+            ),
+            Err(err) => {
+                error!("Could not listen on {}: {}", addr, err);
+                std::process::exit(1);
             }
         }
-        Ok(read)
+        info!("Spectators on ws://{}", addr);
     }
-    pub fn write(&mut self, buf: &[u8]) -> rusb::Result<usize> {
-        self.handle
-            .write_bulk(self.endpoint_out, buf, Duration::from_secs(0))
+    let mut devices = file.device_filter();
+    devices.vendor_id = opt.vendor_id.unwrap_or(devices.vendor_id);
+    devices.product_id = opt.product_id.unwrap_or(devices.product_id);
+    if opt.serial.is_some() {
+        devices.serial = opt.serial;
     }
-    pub fn write_all(&mut self, buf: &[u8]) -> Result<usize, (usize, rusb::Error)> {
-        let mut written: usize = 0;
-        while written < buf.len() {
-            match self.write(&buf[written..]) {
-                Ok(bytes) => written += bytes,
-                Err(rusb::Error::Timeout) => {}
-                Err(err) => return Err((written, err)),
+    devices.interface.class = opt.interface_class.or(devices.interface.class);
+    devices.interface.subclass = opt.interface_subclass.or(devices.interface.subclass);
+    devices.interface.protocol = opt.interface_protocol.or(devices.interface.protocol);
+    if opt.interface_name.is_some() {
+        devices.interface.name = opt.interface_name;
+    }
+    devices.interface.identify |= opt.identify;
+    let capture = match opt.capture.as_ref().or(file.usb.capture.as_ref()) {
+        Some(path) => match Capture::create(path) {
+            Ok(capture) => Some(capture),
+            Err(err) => {
+                error!(
+                    "Could not create the capture file {}: {}",
+                    path.display(),
+                    err
+                );
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+    let usb = UsbConfig {
+        devices,
+        retry_min: file
+            .usb
+            .retry_min
+            .map_or(Backoff::DEFAULT_MIN, Duration::from_secs),
+        retry_max: file
+            .usb
+            .retry_max
+            .map_or(Backoff::DEFAULT_MAX, Duration::from_secs),
+        timeouts: Reloadable::new(file.usb_timeouts()),
+        capture,
+    };
+    // Only the bridge itself follows changes to the file, not the subcommands.
+    if let (Some(path), None) = (&opt.config, &opt.subcommand) {
+        let targets = reload::Targets {
+            log_filter: if opt.log_level.is_none() {
+                Some(log_filter)
+            } else {
+                None
+            },
+            idle_timeout: if opt.idle_timeout.is_none() {
+                Some(config.idle_timeout.clone())
+            } else {
+                None
+            },
+            bot_deadline: if opt.bot_deadline.is_none() {
+                Some(config.bot_deadline.clone())
+            } else {
+                None
+            },
+            usb_timeouts: usb.timeouts.clone(),
+            presets: if opt.presets.is_none() {
+                Some(config.presets.clone())
+            } else {
+                None
+            },
+            web: if opt.web.is_none() { Some(web) } else { None },
+            tbp_spectate: if opt.tbp_spectate.is_none() {
+                Some(tbp_spectate)
+            } else {
+                None
+            },
+        };
+        reload::watch(path.clone(), file.clone(), targets);
+    }
+    match opt.subcommand {
+        Some(Subcommand::Repl) => {
+            let target = match (listen, stdio) {
+                (Some(addr), _) => repl::Target::Tcp(transport::loopback(addr)),
+                (None, true) => repl::Target::Stdio {
+                    config: opt.config.clone(),
+                },
+                (None, false) => {
+                    eprintln!(
+                        "The repl connects to a bridge: give the --listen address of a running \
+                         one, or --stdio to start one."
+                    );
+                    std::process::exit(1);
+                }
+            };
+            repl::run(&config, target)
+        }
+        Some(Subcommand::ListDevices) => devices::list(&usb.devices),
+        Some(Subcommand::Service { launchd, socket }) => {
+            let config = opt.config.as_deref();
+            let definition = match (launchd, socket) {
+                (true, _) => daemon::launchd_plist(config),
+                (false, Some(addr)) => daemon::systemd_unit(config)
+                    .map(|unit| unit + "\n" + &daemon::systemd_socket(addr)),
+                (false, None) => daemon::systemd_unit(config),
+            };
+            match definition {
+                Ok(definition) => print!("{}", definition),
+                Err(err) => {
+                    error!("Could not generate the service definition: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Subcommand::Setup { rules_file, yes }) => {
+            if !setup::run(&usb.devices, &rules_file, yes) {
+                std::process::exit(1);
+            }
+        }
+        Some(Subcommand::Benchmark {
+            moves,
+            threads,
+            seed,
+        }) => {
+            let mut options = config.default_options;
+            if let Some(threads) = threads {
+                options.threads = threads;
+            }
+            info!("Playing {} moves...", moves);
+            let report = benchmark::run(
+                options,
+                config.default_evaluator,
+                &config.bot_policy,
+                moves,
+                seed,
+            );
+            println!("{}", report);
+        }
+        Some(Subcommand::Selftest) => match selftest::run_usb(&usb) {
+            Ok(report) => {
+                println!("{}", report);
+                if report.framing_errors > 0 {
+                    std::process::exit(1);
+                }
+            }
+            Err(err) => {
+                error!("Self-test failed: {:?}", err);
+                std::process::exit(1);
+            }
+        },
+        Some(Subcommand::AbReport { file: path }) => {
+            let path = match path.or_else(|| file.ab_test.results.clone()) {
+                Some(path) => path,
+                None => {
+                    error!("There is no A/B test in the config file; give its results file.");
+                    std::process::exit(1);
+                }
+            };
+            match AbReport::load(&path) {
+                Ok(report) => println!("{}", report),
+                Err(err) => {
+                    error!("Could not read {}: {}", path.display(), err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Subcommand::BuildBook { out, input }) => {
+            let text = match &input {
+                Some(input) => std::fs::read_to_string(input),
+                None => {
+                    let mut text = String::new();
+                    std::io::Read::read_to_string(&mut std::io::stdin(), &mut text).map(|_| text)
+                }
+            };
+            let text = match text {
+                Ok(text) => text,
+                Err(err) => {
+                    error!("Could not read the openers: {}", err);
+                    std::process::exit(1);
+                }
+            };
+            let mut builder = BookBuilder::new(config.default_options.mode);
+            for (index, line) in text.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                builder.add(&format!("line {}", index + 1), line);
+            }
+            for problem in &builder.problems {
+                warn!("{}", problem);
+            }
+            if let Err(err) = builder.write(&out) {
+                error!("Could not write {}: {}", out.display(), err);
+                std::process::exit(1);
+            }
+            println!("Wrote {} positions to {}", builder.len(), out.display());
+        }
+        Some(Subcommand::Replay {
+            file,
+            session,
+            fast,
+        }) => match replay::run(&config, &file, session, !fast) {
+            Ok(report) => {
+                for response in &report.responses {
+                    println!("{}", response);
+                }
+                println!("{}", report);
+            }
+            Err(err) => {
+                error!("Replay failed: {:?}", err);
+                std::process::exit(1);
+            }
+        },
+        Some(Subcommand::Versus {
+            rules,
+            pace,
+            p1_preset,
+            p2_preset,
+        }) => {
+            let tui = dashboard.is_some();
+            if let Some(monitor) = dashboard {
+                tui::spawn(monitor, || std::process::exit(0));
+            }
+            let rules = rules.rules(Duration::from_millis(pace), [p1_preset, p2_preset]);
+            match versus::run(&config, &rules) {
+                // The dashboard has the terminal until it is closed, which exits the bridge.
+                Ok(report) if tui => {
+                    info!("{}", report);
+                    loop {
+                        std::thread::park();
+                    }
+                }
+                Ok(report) => println!("{}", report),
+                Err(err) => {
+                    error!("Versus failed: {:?}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Subcommand::Simulate { games, rules, a, b }) => {
+            let rules = rules.rules(Duration::from_secs(0), [a, b]);
+            match simulate::run(&config, &rules, games) {
+                Ok(report) => println!("{}", report),
+                Err(err) => {
+                    error!("Simulation failed: {:?}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Subcommand::Tune {
+            out,
+            start,
+            generations,
+            population,
+            elite,
+            games,
+            rules,
+        }) => {
+            let start = match start {
+                Some(name) => match config.presets.get().get(&name) {
+                    Ok(evaluator) => evaluator,
+                    Err(err) => {
+                        error!("{}", err.message);
+                        std::process::exit(1);
+                    }
+                },
+                None => config.default_evaluator.clone(),
+            };
+            let options = TuneOptions {
+                generations,
+                population,
+                elite,
+                games,
+                start,
+                out,
+                rules: rules.rules(Duration::from_secs(0), [None, None]),
+            };
+            match tune::run(&config, &options) {
+                Ok(_) => println!("Wrote the best weights to {}", options.out.display()),
+                Err(err) => {
+                    error!("Tuning failed: {:?}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None if stdio => server::serve_stdio(config),
+        None => {
+            let _lock = match InstanceLock::acquire(InstanceLock::default_path(), opt.takeover) {
+                Ok(lock) => lock,
+                Err(InstanceError::AlreadyRunning(pid)) => {
+                    error!(
+                        "Another instance of the bridge is already running (PID {}).",
+                        pid
+                    );
+                    error!("Pass --takeover to shut it down and take its place.");
+                    std::process::exit(1);
+                }
+                Err(err) => {
+                    error!("Could not acquire the instance lock: {:?}", err);
+                    std::process::exit(1);
+                }
+            };
+            shutdown::install();
+            if let Some(monitor) = dashboard {
+                tui::spawn(monitor, shutdown::request);
+            }
+            let usb_policy = ThreadPolicy::new(vec![], opt.usb_nice.or(file.threads.usb_nice));
+            if !usb_policy.is_default() {
+                info!("USB thread: nice {:?}", usb_policy.nice);
+                usb_policy.apply_to_current_thread();
+            }
+            // A socket passed in by systemd wins over --listen, which is then only the address
+            // the socket unit is meant to listen on.
+            let listener = match (transport::systemd_listener(), listen) {
+                (Some(listener), _) => {
+                    info!("Using the socket passed in by systemd");
+                    Some(listener)
+                }
+                (None, Some(addr)) => match TcpListener::bind(addr) {
+                    Ok(listener) => Some(listener),
+                    Err(err) => {
+                        error!("Could not listen on {}: {}", addr, err);
+                        std::process::exit(1);
+                    }
+                },
+                (None, None) => None,
+            };
+            if opt.discovery || file.transport.discovery {
+                let port = listener
+                    .as_ref()
+                    .and_then(|listener| listener.local_addr().ok())
+                    .map(|addr| addr.port());
+                match port.map(Discovery::spawn) {
+                    Some(Ok(())) => info!("Discoverable on UDP port {}", Discovery::PORT),
+                    Some(Err(err)) => {
+                        error!("Could not start discovery: {}", err);
+                        std::process::exit(1);
+                    }
+                    None => {
+                        error!("Discovery is for the TCP transport, which needs --listen.");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            // Every connection runs its own session whichever way it came in, so serving both
+            // only takes running both loops. They share one store of parked sessions, which keeps
+            // each transport's sessions to itself.
+            let sessions = SessionStore::new(config.resume_grace);
+            match listener {
+                Some(listener) if opt.usb || file.transport.usb => {
+                    let (tcp_config, tcp_sessions) = (config.clone(), sessions.clone());
+                    let tcp = std::thread::Builder::new()
+                        .name("tcp".to_owned())
+                        .spawn(move || server::serve_tcp(listener, tcp_config, tcp_sessions))
+                        .unwrap();
+                    server::serve(config, usb, sessions);
+                    if let Ok(Err(err)) = tcp.join() {
+                        error!("Error: {:?}", err);
+                        std::process::exit(1);
+                    }
+                }
+                Some(listener) => {
+                    if let Err(err) = server::serve_tcp(listener, config, sessions) {
+                        error!("Error: {:?}", err);
+                        std::process::exit(1);
+                    }
+                }
+                None => server::serve(config, usb, sessions),
             }
         }
-        Ok(written)
     }
 }
@@ -0,0 +1,174 @@
+use libtetris::{Board, FallingPiece, Piece};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+// Live state for the dashboard. Sessions, bots and USB connections publish to it whether or not
+// anything is watching; publishing is a lock and a copy.
+#[derive(Clone, Debug, Default)]
+pub struct Monitor {
+    state: Arc<Mutex<MonitorState>>,
+    traffic: Traffic,
+    feed: Feed,
+}
+
+#[derive(Debug, Default)]
+struct MonitorState {
+    next_connection: u64,
+    connections: BTreeMap<u64, (String, Instant)>,
+    bots: Vec<Weak<Mutex<BotStatus>>>,
+    log: VecDeque<String>,
+}
+
+// What a bot thread publishes about itself. The bot and its worker hold the only strong
+// references, so a bot disappears from the dashboard when it is dropped.
+#[derive(Clone, Debug)]
+pub struct BotStatus {
+    pub handle: u32,
+    pub label: Option<String>,
+    pub board: Board,
+    pub last_think: Option<Duration>,
+    pub last_nodes: Option<u32>,
+    pub pieces_placed: u32,
+    // The placements after the last move, in the order the bot means to make them.
+    pub plan: Vec<FallingPiece>,
+    pub last_placed: Option<FallingPiece>,
+    pub fumen: Option<String>,
+}
+
+// Bytes moved over USB, counted by every connection.
+#[derive(Clone, Debug, Default)]
+pub struct Traffic {
+    read: Arc<AtomicU64>,
+    written: Arc<AtomicU64>,
+}
+
+impl Traffic {
+    pub fn add_read(&self, bytes: usize) {
+        self.read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    pub fn add_written(&self, bytes: usize) {
+        self.written.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+// What happens on each bot's board, as it happens, for spectators that mirror the game rather than
+// sample it. Every event carries the board as it is afterwards.
+#[derive(Clone, Debug, Default)]
+pub struct Feed {
+    subscribers: Arc<Mutex<Vec<Sender<FeedEvent>>>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FeedEvent {
+    pub handle: u32,
+    pub kind: FeedKind,
+    pub board: Board,
+}
+
+#[derive(Clone, Debug)]
+pub enum FeedKind {
+    // The bot was launched, or relaunched after a crash.
+    Start,
+    Piece(Piece),
+    Place { hold: bool, location: FallingPiece },
+    Reset,
+    // The handle was dropped.
+    Gone,
+}
+
+impl Feed {
+    pub fn subscribe(&self) -> Receiver<FeedEvent> {
+        let (send, receive) = channel();
+        self.subscribers.lock().unwrap().push(send);
+        receive
+    }
+    pub fn publish(&self, event: FeedEvent) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if !subscribers.is_empty() {
+            subscribers.retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        }
+    }
+}
+
+pub struct Snapshot {
+    // Name and how long ago it connected.
+    pub connections: Vec<(String, Duration)>,
+    pub bots: Vec<BotStatus>,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    pub log: Vec<String>,
+}
+
+// Removes the connection from the dashboard when dropped.
+pub struct ConnectionGuard {
+    monitor: Monitor,
+    id: u64,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.monitor
+            .state
+            .lock()
+            .unwrap()
+            .connections
+            .remove(&self.id);
+    }
+}
+
+impl Monitor {
+    const LOG_LINES: usize = 200;
+
+    pub fn connection(&self, name: String) -> ConnectionGuard {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_connection;
+        state.next_connection += 1;
+        state.connections.insert(id, (name, Instant::now()));
+        ConnectionGuard {
+            monitor: self.clone(),
+            id,
+        }
+    }
+    pub fn add_bot(&self, status: &Arc<Mutex<BotStatus>>) {
+        let mut state = self.state.lock().unwrap();
+        state.bots.retain(|bot| bot.strong_count() > 0);
+        state.bots.push(Arc::downgrade(status));
+    }
+    pub fn traffic(&self) -> Traffic {
+        self.traffic.clone()
+    }
+    pub fn feed(&self) -> Feed {
+        self.feed.clone()
+    }
+    pub fn log(&self, line: String) {
+        let mut state = self.state.lock().unwrap();
+        if state.log.len() == Monitor::LOG_LINES {
+            state.log.pop_front();
+        }
+        state.log.push_back(line);
+    }
+    pub fn snapshot(&self) -> Snapshot {
+        let state = self.state.lock().unwrap();
+        let mut bots: Vec<_> = state
+            .bots
+            .iter()
+            .filter_map(Weak::upgrade)
+            .map(|bot| bot.lock().unwrap().clone())
+            .collect();
+        bots.sort_by_key(|bot| bot.handle);
+        Snapshot {
+            connections: state
+                .connections
+                .values()
+                .map(|(name, since)| (name.clone(), since.elapsed()))
+                .collect(),
+            bots,
+            bytes_read: self.traffic.read.load(Ordering::Relaxed),
+            bytes_written: self.traffic.written.load(Ordering::Relaxed),
+            log: state.log.iter().cloned().collect(),
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use crate::web;
+use cc_switch_usb_rs::monitor::Monitor;
+use serde_json::json;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, warn};
+
+// A viewer that misses this many snapshots in a row is disconnected.
+const MAX_SKIPPED: u32 = 50;
+// Writes to a viewer that take longer than this fail, which disconnects it.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Viewer {
+    snapshots: SyncSender<Arc<String>>,
+    skipped: u32,
+    // Hasn't been sent anything yet, so gets the current snapshot even if nothing changed.
+    fresh: bool,
+}
+
+// Broadcasts the dashboard's snapshot of every bot to stream overlays over a WebSocket, at most
+// once per `interval` and only when something changed. The socket only ever sends; nothing a
+// viewer sends reaches the bots. Each viewer has a queue of one snapshot, so a slow viewer skips
+// snapshots instead of holding up the others, and is dropped if it keeps falling behind.
+pub fn spawn(listener: TcpListener, monitor: Monitor, interval: Duration) {
+    let viewers = Arc::new(Mutex::new(Vec::<Viewer>::new()));
+    let broadcast_to = viewers.clone();
+    std::thread::Builder::new()
+        .name("spectate".to_owned())
+        .spawn(move || broadcast(&monitor, &broadcast_to, interval))
+        .unwrap();
+    std::thread::Builder::new()
+        .name("spectate-accept".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        warn!("Could not accept a spectator: {}", err);
+                        continue;
+                    }
+                };
+                let (snapshots, receive) = sync_channel(1);
+                viewers.lock().unwrap().push(Viewer {
+                    snapshots,
+                    skipped: 0,
+                    fresh: true,
+                });
+                std::thread::spawn(move || {
+                    if let Err(err) = serve(stream, receive) {
+                        debug!("Spectator disconnected: {}", err);
+                    }
+                });
+            }
+        })
+        .unwrap();
+}
+
+fn broadcast(monitor: &Monitor, viewers: &Mutex<Vec<Viewer>>, interval: Duration) {
+    let mut last = Arc::new(String::new());
+    loop {
+        std::thread::sleep(interval);
+        let mut viewers = viewers.lock().unwrap();
+        if viewers.is_empty() {
+            continue;
+        }
+        let snapshot = monitor.snapshot();
+        let update = json!({
+            "bots": snapshot.bots.iter().map(web::bot_to_json).collect::<Vec<_>>(),
+        })
+        .to_string();
+        let changed = update != *last;
+        if changed {
+            last = Arc::new(update);
+        }
+        for viewer in viewers.iter_mut().filter(|viewer| changed || viewer.fresh) {
+            viewer.fresh = false;
+            viewer.skipped = match viewer.snapshots.try_send(last.clone()) {
+                Ok(()) => 0,
+                Err(TrySendError::Full(_)) => viewer.skipped + 1,
+                Err(TrySendError::Disconnected(_)) => MAX_SKIPPED,
+            };
+            if viewer.skipped == MAX_SKIPPED {
+                debug!("Dropping a spectator that has gone or can't keep up");
+            }
+        }
+        viewers.retain(|viewer| viewer.skipped < MAX_SKIPPED);
+    }
+}
+
+fn serve(stream: TcpStream, snapshots: Receiver<Arc<String>>) -> Result<(), String> {
+    stream
+        .set_write_timeout(Some(WRITE_TIMEOUT))
+        .map_err(|err| err.to_string())?;
+    let mut socket = tungstenite::accept(stream).map_err(|err| err.to_string())?;
+    for snapshot in snapshots {
+        socket
+            .write_message(tungstenite::Message::Text(snapshot.as_ref().clone()))
+            .map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
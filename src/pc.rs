@@ -0,0 +1,256 @@
+use libtetris::{find_moves, Board, FallingPiece, MovementMode, Piece, PieceMovement};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// A perfect clear finder for practice hints, separate from the bots: a depth-first search over
+// every reachable placement of the current or hold piece. Placements must stay below the height
+// being cleared, and a position is dropped as soon as some stretch of the field between two full
+// columns has an empty cell count that pieces can't fill.
+pub struct PcSolver {
+    mode: MovementMode,
+    max_solutions: usize,
+    nodes: u32,
+    solutions: Vec<Vec<cold_clear::Move>>,
+    cancel: Arc<AtomicBool>,
+}
+
+struct Step {
+    hold: bool,
+    location: FallingPiece,
+    inputs: Vec<PieceMovement>,
+}
+
+impl PcSolver {
+    const MAX_HEIGHT: i32 = 4;
+    const NODE_LIMIT: u32 = 500_000;
+
+    pub fn new(mode: MovementMode, max_solutions: usize) -> PcSolver {
+        PcSolver {
+            mode,
+            max_solutions,
+            nodes: 0,
+            solutions: vec![],
+            cancel: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // Setting the flag stops the search, which then answers with what it has found so far.
+    pub fn cancelled_by(mut self, cancel: Arc<AtomicBool>) -> PcSolver {
+        self.cancel = cancel;
+        self
+    }
+
+    fn exhausted(&self) -> bool {
+        self.nodes >= PcSolver::NODE_LIMIT || self.cancel.load(Ordering::Relaxed)
+    }
+
+    // Solutions come in the order they are found, trying the fewest lines first. Fewer than
+    // asked for (possibly none) means the search ran out of pieces, hit the node limit or was
+    // cancelled.
+    pub fn solve(
+        mut self,
+        field: [[bool; 10]; 40],
+        hold: Option<Piece>,
+        queue: &[Piece],
+    ) -> Vec<Vec<cold_clear::Move>> {
+        let mut board = Board::new();
+        board.set_field(field);
+        board.hold_piece = hold;
+        for &piece in queue {
+            board.add_next_piece(piece);
+        }
+        let filled = (0..40)
+            .flat_map(|y| (0..10).map(move |x| (x, y)))
+            .filter(|&(x, y)| board.occupied(x, y))
+            .count() as i32;
+        let top = (0..40)
+            .rev()
+            .find(|&y| (0..10).any(|x| board.occupied(x, y)))
+            .map_or(0, |y| y + 1);
+        for height in top.max(1)..=PcSolver::MAX_HEIGHT {
+            let empty = height * 10 - filled;
+            if empty % 4 != 0 || empty / 4 > queue.len() as i32 {
+                continue;
+            }
+            self.search(&board, height, &mut vec![]);
+            if !self.solutions.is_empty() || self.exhausted() {
+                break;
+            }
+        }
+        self.solutions
+    }
+
+    fn search(&mut self, board: &Board, height: i32, path: &mut Vec<Step>) {
+        let mut queue = board.next_queue();
+        let current = match queue.next() {
+            Some(current) => current,
+            None => return,
+        };
+        let mut candidates = vec![(current, false)];
+        match board.hold_piece.or_else(|| queue.next()) {
+            Some(held) if held != current => candidates.push((held, true)),
+            _ => {}
+        }
+        for (piece, hold) in candidates {
+            let spawned = match FallingPiece::spawn(piece, board) {
+                Some(spawned) => spawned,
+                None => continue,
+            };
+            let mut seen = vec![];
+            for placement in find_moves(board, spawned, self.mode) {
+                if self.solutions.len() >= self.max_solutions || self.exhausted() {
+                    return;
+                }
+                let mut cells: Vec<(i32, i32)> = placement
+                    .location
+                    .cells()
+                    .iter()
+                    .map(|&(x, y, _)| (x, y))
+                    .collect();
+                if cells.iter().any(|&(_, y)| y >= height) {
+                    continue;
+                }
+                cells.sort();
+                if seen.contains(&cells) {
+                    continue;
+                }
+                seen.push(cells);
+                self.nodes += 1;
+
+                let mut next = board.clone();
+                let current = next.advance_queue();
+                if let (true, Some(current)) = (hold, current) {
+                    if next.hold(current).is_none() {
+                        next.advance_queue();
+                    }
+                }
+                let lock = next.lock_piece(placement.location);
+                path.push(Step {
+                    hold,
+                    location: placement.location,
+                    inputs: placement.inputs.movements.iter().copied().collect(),
+                });
+                if lock.perfect_clear {
+                    self.solutions.push(
+                        path.iter()
+                            .map(|step| cold_clear::Move {
+                                inputs: step.inputs.iter().copied().collect(),
+                                expected_location: step.location,
+                                hold: step.hold,
+                            })
+                            .collect(),
+                    );
+                } else {
+                    let height = height - lock.cleared_lines.len() as i32;
+                    if fillable(&next, height) {
+                        self.search(&next, height, path);
+                    }
+                }
+                path.pop();
+            }
+        }
+    }
+}
+
+// Whether every stretch of columns between full ones has room for a whole number of pieces.
+// How many placements of a bot's plan, counting the first, it takes to perfect clear from `board`,
+// when the plan gets there with pieces already in the queue. Plans that lean on pieces the bot has
+// only guessed at don't count.
+pub fn pieces_to_pc(board: &Board, plan: &[FallingPiece]) -> Option<u32> {
+    let mut board = board.clone();
+    for (index, &location) in plan.iter().enumerate() {
+        let current = board.advance_queue()?;
+        if location.kind.0 != current {
+            let held = match board.hold(current) {
+                Some(held) => held,
+                None => board.advance_queue()?,
+            };
+            if held != location.kind.0 {
+                return None;
+            }
+        }
+        if board.lock_piece(location).perfect_clear {
+            return Some(index as u32 + 1);
+        }
+    }
+    None
+}
+
+fn fillable(board: &Board, height: i32) -> bool {
+    let mut empty = 0;
+    for x in 0..10 {
+        let column = (0..height).filter(|&y| !board.occupied(x, y)).count();
+        if column == 0 {
+            if empty % 4 != 0 {
+                return false;
+            }
+            empty = 0;
+        }
+        empty += column;
+    }
+    empty % 4 == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    // Two rows full but for two columns on each side, which two O pieces clear.
+    fn two_from_pc(queue: &[Piece]) -> Board {
+        let mut field = [[false; 10]; 40];
+        for row in &mut field[..2] {
+            for cell in &mut row[2..8] {
+                *cell = true;
+            }
+        }
+        let mut board = Board::new();
+        board.set_field(field);
+        for &piece in queue {
+            board.add_next_piece(piece);
+        }
+        board
+    }
+
+    // The O placement filling the two columns from `x`.
+    fn o_at(board: &Board, x: i32) -> FallingPiece {
+        let cells: HashSet<_> = [(x, 0), (x + 1, 0), (x, 1), (x + 1, 1)]
+            .iter()
+            .copied()
+            .collect();
+        let spawned = FallingPiece::spawn(Piece::O, board).unwrap();
+        find_moves(board, spawned, MovementMode::ZeroG)
+            .into_iter()
+            .map(|placement| placement.location)
+            .find(|location| location.cells().iter().copied().collect::<HashSet<_>>() == cells)
+            .unwrap()
+    }
+
+    fn plan(board: &Board) -> Vec<FallingPiece> {
+        let first = o_at(board, 0);
+        let mut after = board.clone();
+        after.lock_piece(first);
+        vec![first, o_at(&after, 8)]
+    }
+
+    #[test]
+    fn counts_the_pieces_to_a_known_pc() {
+        let board = two_from_pc(&[Piece::O, Piece::O]);
+        assert_eq!(pieces_to_pc(&board, &plan(&board)), Some(2));
+        assert_eq!(pieces_to_pc(&board, &plan(&board)[..1]), None);
+    }
+
+    #[test]
+    fn ignores_a_pc_past_the_known_queue() {
+        let board = two_from_pc(&[Piece::O]);
+        assert_eq!(pieces_to_pc(&board, &plan(&board)), None);
+    }
+
+    #[test]
+    fn follows_hold() {
+        let board = two_from_pc(&[Piece::T, Piece::O, Piece::O]);
+        assert_eq!(pieces_to_pc(&board, &plan(&board)), Some(2));
+        let board = two_from_pc(&[Piece::T, Piece::O]);
+        assert_eq!(pieces_to_pc(&board, &plan(&board)), None);
+    }
+}
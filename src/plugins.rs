@@ -0,0 +1,261 @@
+use crate::protocol::{CommandError, ErrorCode};
+use cold_clear::evaluation::{Evaluator, Standard};
+use libtetris::{Board, LockResult, Piece};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+use wasmtime::{
+    Config, Engine, Instance, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc,
+};
+
+#[derive(Debug)]
+pub enum PluginError {
+    Io(PathBuf, std::io::Error),
+    Invalid(PathBuf, String),
+}
+
+// (field, lines cleared, garbage sent, perfect clear, b2b, combo or -1, move time, placed piece)
+type EvaluateArgs = (i32, i32, i32, i32, i32, i32, i32, i32);
+
+// Evaluators compiled to WASM, loaded from `<name>.wasm` files. A plugin exports its `memory`,
+// `field() -> i32`, the address of 400 bytes the bridge fills with the field (ten bytes a row,
+// bottom row first, 1 for filled), and `evaluate(...) -> i32`, called with the address and the
+// rest of `EvaluateArgs` after every simulated placement. What it returns is added to the score
+// of the launch's standard evaluator; with every weight at zero, the plugin is the evaluator.
+// Each call gets `Plugin::FUEL` to run on and an instance can grow to `Plugin::MEMORY`, so a
+// plugin that loops or allocates without end costs its score rather than the bot.
+#[derive(Default)]
+pub struct Plugins {
+    plugins: HashMap<String, Arc<Plugin>>,
+}
+
+impl fmt::Debug for Plugins {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+impl Plugins {
+    pub fn load(dir: &Path) -> Result<Plugins, PluginError> {
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)
+            .map_err(|err| PluginError::Invalid(dir.to_owned(), err.to_string()))?;
+        let mut plugins = HashMap::new();
+        let entries = dir
+            .read_dir()
+            .map_err(|err| PluginError::Io(dir.to_owned(), err))?;
+        for entry in entries {
+            let path = entry
+                .map_err(|err| PluginError::Io(dir.to_owned(), err))?
+                .path();
+            if path.extension().map_or(true, |ext| ext != "wasm") {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+            let invalid = |err: anyhow::Error| PluginError::Invalid(path.clone(), err.to_string());
+            let module = Module::from_file(&engine, &path).map_err(invalid)?;
+            let plugin = Plugin {
+                name: name.clone(),
+                engine: engine.clone(),
+                module,
+                instances: Mutex::new(vec![]),
+                out_of_fuel: AtomicBool::new(false),
+            };
+            // Instantiated and run once now so a broken plugin is reported at startup.
+            let mut instance = plugin.instantiate().map_err(invalid)?;
+            instance
+                .evaluate(&Board::new(), (0, 0, 0, 0, -1, 0, 0))
+                .map_err(invalid)?;
+            plugin.instances.lock().unwrap().push(instance);
+            plugins.insert(name, Arc::new(plugin));
+        }
+        Ok(Plugins { plugins })
+    }
+    pub fn get(&self, name: &str) -> Result<Arc<Plugin>, CommandError> {
+        self.plugins.get(name).cloned().ok_or_else(|| {
+            CommandError::new(
+                ErrorCode::InvalidArgument,
+                format!("there is no evaluator plugin named {:?}", name),
+            )
+        })
+    }
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.plugins.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+// Instances are kept in a pool, since cold clear evaluates from several threads at once and a
+// WASM instance can only run one call at a time.
+pub struct Plugin {
+    name: String,
+    engine: Engine,
+    module: Module,
+    instances: Mutex<Vec<PluginInstance>>,
+    // Set once running out of fuel has been logged, since it tends to happen on every call.
+    out_of_fuel: AtomicBool,
+}
+
+impl fmt::Debug for Plugin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Plugin({})", self.name)
+    }
+}
+
+impl Plugin {
+    // Roughly a million WASM instructions per evaluation.
+    const FUEL: u64 = 1_000_000;
+    const MEMORY: usize = 16 << 20;
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    fn instantiate(&self) -> anyhow::Result<PluginInstance> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(Plugin::MEMORY)
+            .instances(1)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        let instance = Instance::new(&mut store, &self.module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| anyhow::anyhow!("the plugin doesn't export its memory"))?;
+        let field = instance.get_typed_func::<(), i32, _>(&mut store, "field")?;
+        let evaluate = instance.get_typed_func::<EvaluateArgs, i32, _>(&mut store, "evaluate")?;
+        Ok(PluginInstance {
+            store,
+            memory,
+            field,
+            evaluate,
+            fuel_added: 0,
+        })
+    }
+    // A plugin that fails scores nothing, rather than taking the bot down with it.
+    fn evaluate(&self, board: &Board, lock: &LockResult, move_time: u32, placed: Piece) -> i32 {
+        let pooled = self.instances.lock().unwrap().pop();
+        let mut instance = match pooled.map_or_else(|| self.instantiate(), Ok) {
+            Ok(instance) => instance,
+            Err(err) => {
+                warn!("Could not instantiate the plugin {}: {}", self.name, err);
+                return 0;
+            }
+        };
+        let args = (
+            lock.cleared_lines.len() as i32,
+            lock.garbage_sent as i32,
+            lock.perfect_clear as i32,
+            lock.b2b as i32,
+            lock.combo.map_or(-1, |combo| combo as i32),
+            move_time as i32,
+            placed as i32,
+        );
+        match instance.evaluate(board, args) {
+            Ok(score) => {
+                self.instances.lock().unwrap().push(instance);
+                score
+            }
+            // The instance was stopped partway through, so it isn't reused.
+            Err(_) if instance.fuel_left() == 0 => {
+                if !self.out_of_fuel.swap(true, Ordering::Relaxed) {
+                    warn!(
+                        "The plugin {} ran out of fuel, which scores nothing",
+                        self.name
+                    );
+                }
+                0
+            }
+            Err(err) => {
+                warn!("The plugin {} failed: {}", self.name, err);
+                0
+            }
+        }
+    }
+}
+
+struct PluginInstance {
+    store: Store<StoreLimits>,
+    memory: Memory,
+    field: TypedFunc<(), i32>,
+    evaluate: TypedFunc<EvaluateArgs, i32>,
+    fuel_added: u64,
+}
+
+impl PluginInstance {
+    fn fuel_left(&self) -> u64 {
+        self.fuel_added - self.store.fuel_consumed().unwrap_or(0)
+    }
+    fn evaluate(
+        &mut self,
+        board: &Board,
+        (lines, garbage, perfect_clear, b2b, combo, move_time, placed): (
+            i32,
+            i32,
+            i32,
+            i32,
+            i32,
+            i32,
+            i32,
+        ),
+    ) -> anyhow::Result<i32> {
+        // Topped up rather than added to, so unused fuel doesn't carry over to the next call.
+        let top_up = Plugin::FUEL - self.fuel_left();
+        self.store.add_fuel(top_up)?;
+        self.fuel_added += top_up;
+        let mut field = [0u8; 400];
+        for y in 0..40 {
+            for x in 0..10 {
+                field[y * 10 + x] = board.occupied(x as i32, y as i32) as u8;
+            }
+        }
+        let address = self.field.call(&mut self.store, ())?;
+        self.memory
+            .write(&mut self.store, address as usize, &field)?;
+        let args = (
+            address,
+            lines,
+            garbage,
+            perfect_clear,
+            b2b,
+            combo,
+            move_time,
+            placed,
+        );
+        Ok(self.evaluate.call(&mut self.store, args)?)
+    }
+}
+
+// The standard evaluator with a plugin's score added on top.
+#[derive(Clone)]
+pub struct PluginEvaluator {
+    pub standard: Standard,
+    pub plugin: Arc<Plugin>,
+}
+
+impl Evaluator for PluginEvaluator {
+    type Value = <Standard as Evaluator>::Value;
+    type Reward = <Standard as Evaluator>::Reward;
+
+    fn name(&self) -> String {
+        format!("{} + {}", self.standard.name(), self.plugin.name)
+    }
+    fn evaluate(
+        &self,
+        lock: &LockResult,
+        board: &Board,
+        move_time: u32,
+        placed: Piece,
+    ) -> (Self::Value, Self::Reward) {
+        let (mut value, reward) = self.standard.evaluate(lock, board, move_time, placed);
+        value.value += self.plugin.evaluate(board, lock, move_time, placed);
+        (value, reward)
+    }
+}
@@ -0,0 +1,202 @@
+use crate::priority::ThreadPolicy;
+use libtetris::Board;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use tracing::debug;
+
+// Interfaces launched ahead of time with the default options and evaluator. A background thread
+// keeps up to `size` interfaces ready, fewer when `fit` leaves less room; dropping the pool stops
+// it and drops whatever it was holding.
+pub struct WarmPool {
+    key: Vec<u8>,
+    threads: u32,
+    shared: Arc<Shared>,
+    hits: u64,
+    misses: u64,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+struct State {
+    ready: VecDeque<cold_clear::Interface>,
+    // Whether the producer is launching one more, which counts as held.
+    launching: bool,
+    size: usize,
+    room: usize,
+    stopped: bool,
+}
+
+impl State {
+    fn held(&self) -> usize {
+        self.ready.len() + usize::from(self.launching)
+    }
+}
+
+// Launches with the pool's options and evaluator that found an interface ready, and that didn't.
+// Launches with anything else never use the pool and aren't counted.
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct WarmPoolStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl WarmPool {
+    pub fn new(
+        size: usize,
+        policy: ThreadPolicy,
+        options: cold_clear::Options,
+        evaluator: cold_clear::evaluation::Standard,
+    ) -> WarmPool {
+        let key = WarmPool::key(&options, &evaluator);
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                ready: VecDeque::new(),
+                launching: false,
+                size,
+                room: size,
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        });
+        let producer = shared.clone();
+        std::thread::spawn(move || {
+            policy.apply_to_current_thread();
+            loop {
+                {
+                    let mut state = producer.state.lock().unwrap();
+                    while !state.stopped && state.held() >= state.room {
+                        state = producer.changed.wait(state).unwrap();
+                    }
+                    if state.stopped {
+                        break;
+                    }
+                    state.launching = true;
+                }
+                let interface =
+                    cold_clear::Interface::launch(Board::new(), options, evaluator.clone());
+                let mut state = producer.state.lock().unwrap();
+                state.launching = false;
+                // The room may have shrunk while it was launching.
+                if !state.stopped && state.held() < state.room {
+                    state.ready.push_back(interface);
+                }
+            }
+        });
+        WarmPool {
+            key,
+            threads: options.threads,
+            shared,
+            hits: 0,
+            misses: 0,
+        }
+    }
+    fn key(options: &cold_clear::Options, evaluator: &cold_clear::evaluation::Standard) -> Vec<u8> {
+        serde_cbor::to_vec(&(options, evaluator)).unwrap()
+    }
+    // The search threads each pooled interface has.
+    pub fn threads(&self) -> u32 {
+        self.threads
+    }
+    // Holds no more than `room` interfaces, letting go of any over that.
+    pub fn fit(&self, room: usize) {
+        let surplus: Vec<_> = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.room = room.min(state.size);
+            let keep = state.room.saturating_sub(usize::from(state.launching));
+            let keep = keep.min(state.ready.len());
+            state.ready.drain(keep..).collect()
+        };
+        if !surplus.is_empty() {
+            debug!(
+                "Warm pool let go of {} interfaces to make room",
+                surplus.len()
+            );
+        }
+        self.shared.changed.notify_all();
+    }
+    pub fn take(
+        &mut self,
+        options: &cold_clear::Options,
+        evaluator: &cold_clear::evaluation::Standard,
+    ) -> Option<cold_clear::Interface> {
+        if WarmPool::key(options, evaluator) != self.key {
+            return None;
+        }
+        let interface = self.shared.state.lock().unwrap().ready.pop_front();
+        self.shared.changed.notify_all();
+        if interface.is_some() {
+            self.hits += 1;
+        } else {
+            self.misses += 1;
+        }
+        debug!(
+            "Warm pool {} (hits: {}, misses: {})",
+            if interface.is_some() { "hit" } else { "miss" },
+            self.hits,
+            self.misses
+        );
+        interface
+    }
+    pub fn stats(&self) -> WarmPoolStats {
+        WarmPoolStats {
+            hits: self.hits,
+            misses: self.misses,
+        }
+    }
+}
+
+impl Drop for WarmPool {
+    fn drop(&mut self) {
+        let ready = {
+            let mut state = self.shared.state.lock().unwrap();
+            state.stopped = true;
+            std::mem::take(&mut state.ready)
+        };
+        self.shared.changed.notify_all();
+        drop(ready);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn held(pool: &WarmPool) -> usize {
+        pool.shared.state.lock().unwrap().held()
+    }
+
+    fn settle(pool: &WarmPool, held_now: usize) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while held(pool) != held_now {
+            assert!(
+                Instant::now() < deadline,
+                "the pool never held {}",
+                held_now
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn pool_only_holds_what_there_is_room_for() {
+        let options = cold_clear::Options::default();
+        let evaluator = cold_clear::evaluation::Standard::default();
+        let mut pool = WarmPool::new(3, ThreadPolicy::default(), options, evaluator.clone());
+        settle(&pool, 3);
+        pool.fit(1);
+        assert_eq!(held(&pool), 1);
+        assert!(pool.take(&options, &evaluator).is_some());
+        settle(&pool, 1);
+        // One being launched is let go of once it is ready.
+        pool.fit(0);
+        settle(&pool, 0);
+        assert!(pool.take(&options, &evaluator).is_none());
+        pool.fit(2);
+        settle(&pool, 2);
+    }
+}
@@ -0,0 +1,90 @@
+use crate::protocol::{CommandError, ErrorCode};
+use cold_clear::evaluation::Standard;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_cbor::Value;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+pub enum PresetError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(PathBuf, String),
+}
+
+// Evaluators by name, loaded from `<name>.toml` files. A preset only needs to list the weights it
+// changes from the default evaluator.
+#[derive(Debug, Default)]
+pub struct Presets {
+    evaluators: HashMap<String, Standard>,
+}
+
+impl Presets {
+    pub fn load(dir: &Path) -> Result<Presets, PresetError> {
+        let mut evaluators = HashMap::new();
+        let entries = dir
+            .read_dir()
+            .map_err(|err| PresetError::Io(dir.to_owned(), err))?;
+        for entry in entries {
+            let path = entry
+                .map_err(|err| PresetError::Io(dir.to_owned(), err))?
+                .path();
+            if path.extension().map_or(true, |ext| ext != "toml") {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+            let text =
+                std::fs::read_to_string(&path).map_err(|err| PresetError::Io(path.clone(), err))?;
+            let weights: toml::Value =
+                toml::from_str(&text).map_err(|err| PresetError::Parse(path.clone(), err))?;
+            let weights = serde_cbor::value::to_value(&weights)
+                .map_err(|err| PresetError::Invalid(path.clone(), err.to_string()))?;
+            let evaluator = patch(&Standard::default(), weights)
+                .map_err(|err| PresetError::Invalid(path.clone(), err.message))?;
+            evaluators.insert(name, evaluator);
+        }
+        Ok(Presets { evaluators })
+    }
+    pub fn get(&self, name: &str) -> Result<Standard, CommandError> {
+        self.evaluators.get(name).cloned().ok_or_else(|| {
+            CommandError::new(
+                ErrorCode::InvalidArgument,
+                format!("there is no evaluator preset named {:?}", name),
+            )
+        })
+    }
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.evaluators.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+// `changes` is a map of fields to new values; the other fields are kept. Used for evaluator weights
+// as well as options.
+pub fn patch<T: Serialize + DeserializeOwned>(
+    current: &T,
+    changes: Value,
+) -> Result<T, CommandError> {
+    let invalid = |message: String| CommandError::new(ErrorCode::InvalidArgument, message);
+    let patch = match changes {
+        Value::Map(patch) => patch,
+        _ => return Err(invalid("expected a map of fields to values".to_owned())),
+    };
+    let mut fields = match serde_cbor::value::to_value(current) {
+        Ok(Value::Map(fields)) => fields,
+        _ => unreachable!("options and evaluators serialize as maps"),
+    };
+    for (name, value) in patch {
+        if !fields.contains_key(&name) {
+            return Err(invalid(format!("there is no field {:?}", name)));
+        }
+        fields.insert(name, value);
+    }
+    serde_cbor::value::from_value(Value::Map(fields)).map_err(|err| invalid(err.to_string()))
+}
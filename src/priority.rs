@@ -0,0 +1,170 @@
+use serde::Serialize;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+// Threads inherit their creator's CPU affinity and (on Linux) nice value, so cold clear's worker
+// threads are configured by launching interfaces from a helper thread that has the policy applied.
+#[derive(Clone, Debug, Default)]
+pub struct ThreadPolicy {
+    pub cpus: Vec<usize>,
+    pub nice: Option<i32>,
+    // What the OS reported back the last time the policy was applied, which can differ from what
+    // was asked for: CPUs outside the process's own set are dropped, and raising priority needs
+    // privileges.
+    effective: Arc<Mutex<Option<Effective>>>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Effective {
+    pub cpus: Vec<usize>,
+    pub nice: i32,
+}
+
+impl ThreadPolicy {
+    // CPU_SETSIZE; CPUs past it can't be put in an affinity mask.
+    pub const MAX_CPUS: usize = 1024;
+
+    pub fn new(cpus: Vec<usize>, nice: Option<i32>) -> ThreadPolicy {
+        ThreadPolicy {
+            cpus,
+            nice,
+            effective: Arc::default(),
+        }
+    }
+    pub fn is_default(&self) -> bool {
+        self.cpus.is_empty() && self.nice.is_none()
+    }
+    pub fn effective(&self) -> Option<Effective> {
+        self.effective.lock().unwrap().clone()
+    }
+    pub fn apply_to_current_thread(&self) {
+        if !self.cpus.is_empty() {
+            if let Err(err) = set_affinity(&self.cpus) {
+                warn!("Could not set CPU affinity to {:?}: {}", self.cpus, err);
+            }
+        }
+        if let Some(nice) = self.nice {
+            if let Err(err) = set_nice(nice) {
+                warn!("Could not set thread priority to {}: {}", nice, err);
+            }
+        }
+        let effective = match (affinity(), nice()) {
+            (Ok(cpus), Ok(nice)) => Some(Effective { cpus, nice }),
+            _ => None,
+        };
+        *self.effective.lock().unwrap() = effective;
+    }
+    pub fn run<T: Send + 'static>(&self, f: impl FnOnce() -> T + Send + 'static) -> T {
+        if self.is_default() {
+            return f();
+        }
+        let policy = self.clone();
+        std::thread::spawn(move || {
+            policy.apply_to_current_thread();
+            f()
+        })
+        .join()
+        .unwrap()
+    }
+}
+
+pub fn parse_cpu(cpu: &str) -> Result<usize, String> {
+    let cpu: usize = cpu.parse().map_err(|err| format!("{}", err))?;
+    if cpu >= ThreadPolicy::MAX_CPUS {
+        return Err(format!(
+            "CPUs are numbered below {}",
+            ThreadPolicy::MAX_CPUS
+        ));
+    }
+    Ok(cpu)
+}
+
+#[cfg(target_os = "linux")]
+fn set_affinity(cpus: &[usize]) -> io::Result<()> {
+    if let Some(cpu) = cpus.iter().find(|&&cpu| cpu >= ThreadPolicy::MAX_CPUS) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("CPU {} is out of range", cpu),
+        ));
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+// On Linux the nice value is per-thread, so this only affects the calling thread and any threads
+// it spawns afterwards.
+#[cfg(target_os = "linux")]
+fn set_nice(nice: i32) -> io::Result<()> {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn affinity() -> io::Result<Vec<usize>> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((0..ThreadPolicy::MAX_CPUS)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+            .collect())
+    }
+}
+
+// -1 is a valid nice value, so errors can only be told apart by errno.
+#[cfg(target_os = "linux")]
+fn nice() -> io::Result<i32> {
+    unsafe {
+        *libc::__errno_location() = 0;
+        let nice = libc::getpriority(libc::PRIO_PROCESS, 0);
+        let err = io::Error::last_os_error();
+        if nice == -1 && err.raw_os_error() != Some(0) {
+            return Err(err);
+        }
+        Ok(nice)
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn affinity() -> io::Result<Vec<usize>> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "not supported on this OS",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn nice() -> io::Result<i32> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "not supported on this OS",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_affinity(_cpus: &[usize]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "not supported on this OS",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_nice(_nice: i32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "not supported on this OS",
+    ))
+}
@@ -0,0 +1,643 @@
+use crate::build_info::BuildInfo;
+use crate::codec::Codec;
+use crate::garbage::GarbageRules;
+use crate::latency::LatencyStats;
+use crate::pool::WarmPoolStats;
+use crate::priority::Effective;
+use crate::transport::UsbStatsReport;
+use serde::{Deserialize, Serialize};
+use serde_big_array::big_array;
+use strum::{EnumVariantNames, VariantNames};
+
+pub const PROTOCOL_VERSION: u32 = 7;
+
+big_array! { BigArray; }
+
+#[derive(Serialize, Clone)]
+pub struct FieldRows(#[serde(with = "BigArray")] pub [[bool; 10]; 40]);
+
+// `info` carries the rest of the bot's plan along with search statistics, for overlays.
+// `pc_pieces_remaining` is set when the plan perfect clears using only known pieces, and counts
+// this move.
+#[derive(Serialize)]
+pub struct MoveResult {
+    #[serde(rename = "move")]
+    pub mv: cold_clear::Move,
+    pub outcome: Option<MoveOutcome>,
+    pub info: cold_clear::Info,
+    pub pc_pieces_remaining: Option<u32>,
+}
+
+// What the move does when it locks, so the console can update its HUD without simulating it.
+// `b2b` is whether the clear continues a back-to-back chain and `combo` is None when no lines
+// are cleared.
+#[derive(Serialize)]
+pub struct MoveOutcome {
+    pub lines_cleared: u32,
+    pub attack: u32,
+    pub placement: libtetris::PlacementKind,
+    pub perfect_clear: bool,
+    pub b2b: bool,
+    pub combo: Option<u32>,
+}
+
+impl MoveOutcome {
+    pub fn from_lock(lock: &libtetris::LockResult) -> MoveOutcome {
+        MoveOutcome {
+            lines_cleared: lock.cleared_lines.len() as u32,
+            attack: lock.garbage_sent,
+            placement: lock.placement_kind,
+            perfect_clear: lock.perfect_clear,
+            b2b: lock.b2b,
+            combo: lock.combo,
+        }
+    }
+}
+
+// A mid-game position for a bot to start from; `queue` is the known next pieces in order.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BoardState {
+    #[serde(with = "BigArray")]
+    pub field: [[bool; 10]; 40],
+    #[serde(default)]
+    pub hold: Option<libtetris::Piece>,
+    #[serde(default)]
+    pub queue: Vec<libtetris::Piece>,
+    pub b2b_active: bool,
+    pub combo: u32,
+}
+
+impl BoardState {
+    pub fn to_board(&self) -> libtetris::Board {
+        let mut board = libtetris::Board::new();
+        board.set_field(self.field);
+        board.hold_piece = self.hold;
+        board.b2b_bonus = self.b2b_active;
+        board.combo = self.combo;
+        for &piece in &self.queue {
+            board.add_next_piece(piece);
+        }
+        board
+    }
+}
+
+// The evaluators a bot can be launched with. `Standard` is cold clear's own; presets and plugins
+// are loaded by the bridge, and ServerInfo lists their names. A plugin is added on top of `base`,
+// or the default evaluator without one.
+#[derive(Serialize, Deserialize, Clone, Debug, EnumVariantNames)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum EvaluatorChoice {
+    Standard {
+        weights: cold_clear::evaluation::Standard,
+    },
+    Preset {
+        name: String,
+    },
+    Plugin {
+        name: String,
+        #[serde(default)]
+        base: Option<cold_clear::evaluation::Standard>,
+    },
+}
+
+// Before there was a choice, Launch took the standard evaluator's weights as they are.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum EvaluatorSpec {
+    Choice(EvaluatorChoice),
+    Weights(cold_clear::evaluation::Standard),
+}
+
+impl EvaluatorSpec {
+    pub fn into_choice(self) -> EvaluatorChoice {
+        match self {
+            EvaluatorSpec::Choice(choice) => choice,
+            EvaluatorSpec::Weights(weights) => EvaluatorChoice::Standard { weights },
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, EnumVariantNames)]
+#[serde(tag = "command", content = "args")]
+pub enum Command {
+    // Without an evaluator, the named preset is used, or the default evaluator without either.
+    // `preset` and `plugin` are the older way of choosing those and are ignored when `evaluator`
+    // is given.
+    Launch {
+        options: cold_clear::Options,
+        #[serde(default)]
+        evaluator: Option<EvaluatorSpec>,
+        #[serde(default)]
+        preset: Option<String>,
+        #[serde(default)]
+        slot: Option<u8>,
+        #[serde(default)]
+        board: Option<BoardState>,
+        // Shown next to the handle in logs and ListHandles, e.g. "P1".
+        #[serde(default)]
+        label: Option<String>,
+        // The name of a WASM evaluator plugin whose score is added to the evaluator's.
+        #[serde(default)]
+        plugin: Option<String>,
+        // The bot watches a human play instead: placements are reported with ReportPlacement.
+        #[serde(default)]
+        coach: bool,
+        // The name of an opening book to play from while the position is in it. Launch fails if
+        // there is no such book; ServerInfo lists the ones there are.
+        #[serde(default)]
+        book: Option<String>,
+    },
+    Drop {
+        handle: u32,
+    },
+    // With a budget, the bot searches for that many milliseconds before it is asked for its move,
+    // rather than making it as soon as it has searched its minimum.
+    RequestNextMove {
+        handle: u32,
+        incoming: u32,
+        #[serde(default)]
+        budget_ms: Option<u32>,
+    },
+    PollNextMove {
+        handle: u32,
+    },
+    BlockNextMove {
+        handle: u32,
+    },
+    AddNextPiece {
+        handle: u32,
+        piece: libtetris::Piece,
+    },
+    Reset {
+        handle: u32,
+        #[serde(with = "BigArray")]
+        field: [[bool; 10]; 40],
+        b2b_active: bool,
+        combo: u32,
+    },
+    DefaultOptions,
+    DefaultEvaluator,
+    QuerySlot {
+        slot: u8,
+    },
+    GenerateGarbageBoard {
+        rows: u32,
+        #[serde(default)]
+        rules: GarbageRules,
+        seed: u64,
+    },
+    QueryLatency {
+        handle: u32,
+    },
+    Ping {
+        client_time: u64,
+    },
+    // Like Ping, with the bridge's clock in microseconds for clock sync. The console reports the
+    // round trip it measured on the previous echo; the bridge keeps statistics on those, returns
+    // them with every echo and in QueryLatency, and takes the median off think-time budgets.
+    EchoTimestamp {
+        client_time: u64,
+        #[serde(default)]
+        last_round_trip_us: Option<u64>,
+    },
+    // Measures the round trip to the console: the bridge sends `samples` CalibrationProbe
+    // notifications one at a time, each once the last has been echoed, and answers with the
+    // statistics when they are all back. The figures leave out the time the bridge spends on a
+    // frame, and the console's own if it reports it as `console_us`. They are also kept for
+    // think-time budgets and shown in ServerInfo.
+    Calibrate {
+        samples: u32,
+    },
+    CalibrationEcho {
+        seq: u32,
+        #[serde(default)]
+        console_us: Option<u64>,
+    },
+    // Relaunches the bot under the same handle, with its original options and evaluator unless
+    // new ones are given. Either way the bot starts a new game just as a launched one would.
+    ResetBot {
+        handle: u32,
+        #[serde(default)]
+        options: Option<cold_clear::Options>,
+        #[serde(default)]
+        evaluator: Option<cold_clear::evaluation::Standard>,
+    },
+    // Relaunches the bot from the given position, keeping its handle, options and evaluator.
+    SyncBoard {
+        handle: u32,
+        board: BoardState,
+    },
+    // Like Reset, but meant for when the piece didn't land where the bot said: a move that was
+    // requested and not yet delivered is requested again for the corrected field.
+    RecoverMisdrop {
+        handle: u32,
+        #[serde(with = "BigArray")]
+        field: [[bool; 10]; 40],
+        b2b_active: bool,
+        combo: u32,
+    },
+    // Replaces the incoming garbage of the outstanding move request, if there is one.
+    UpdateIncoming {
+        handle: u32,
+        incoming: u32,
+    },
+    // Tells the bot the current piece was placed at `location` (holding first if `hold` is set),
+    // whoever chose it. Any outstanding move request is dropped.
+    ForceMove {
+        handle: u32,
+        #[serde(default)]
+        hold: bool,
+        location: libtetris::FallingPiece,
+    },
+    CancelNextMove {
+        handle: u32,
+    },
+    // Whether the bot won the game it was playing for the A/B test. Sent before the bot is reset
+    // or dropped for the next game, which is when the game is recorded.
+    ReportResult {
+        handle: u32,
+        won: bool,
+    },
+    // For a bot launched as a coach: the human placed the current piece at `location`. Answers
+    // with the placement the bot would have made, what the human's cost by the bot's evaluation
+    // and how bad that is, then moves the bot's board along with the human's placement.
+    ReportPlacement {
+        handle: u32,
+        #[serde(default)]
+        hold: bool,
+        location: libtetris::FallingPiece,
+    },
+    // How a coached game has gone so far: the average cost per piece and the worst placements,
+    // with the board each was made on.
+    CoachSummary {
+        handle: u32,
+    },
+    // Stops the bot from using the CPU while the game is paused or in a menu, keeping its search.
+    // Answers whether the bot was paused, which only TBP bots on unix can be. The bot resumes on
+    // ResumeThinking or when it is next sent anything.
+    PauseThinking {
+        handle: u32,
+    },
+    ResumeThinking {
+        handle: u32,
+    },
+    // Launches a throwaway bot on the given position and answers with its move, for tools that
+    // don't keep a bot around. The defaults are used unless options or an evaluator are given.
+    // It counts as a bot while it thinks, and a few at most run at once; past that it is refused
+    // with Busy.
+    Suggest {
+        board: BoardState,
+        #[serde(default)]
+        incoming: u32,
+        #[serde(default)]
+        options: Option<cold_clear::Options>,
+        #[serde(default)]
+        evaluator: Option<cold_clear::evaluation::Standard>,
+    },
+    // Looks for perfect clears on the field using the queue in order and the hold piece, for
+    // practice hints; no bot is involved. Answers with up to `max_solutions` move sequences,
+    // fewest lines first, or an empty list if none was found within the search limits. Searches
+    // share Suggest's limits.
+    SolvePerfectClear {
+        #[serde(with = "BigArray")]
+        field: [[bool; 10]; 40],
+        queue: Vec<libtetris::Piece>,
+        #[serde(default)]
+        hold: Option<libtetris::Piece>,
+        #[serde(default)]
+        max_solutions: Option<u32>,
+    },
+    ListHandles,
+    ServerInfo,
+    GetStats {
+        handle: u32,
+    },
+    // cold clear only takes options at launch, so the bot is relaunched from its current board.
+    // An outstanding move request is carried over.
+    SetOptions {
+        handle: u32,
+        options: cold_clear::Options,
+    },
+    // `weights` is a map of evaluator fields to new values; fields that aren't mentioned keep
+    // their current value. The bot is relaunched like with SetOptions.
+    UpdateEvaluator {
+        handle: u32,
+        weights: serde_cbor::Value,
+    },
+    // Runs the commands in order and answers once, with a list of their responses in the same
+    // order, when the last of them has finished. Batches can't be nested.
+    Batch {
+        commands: Vec<Command>,
+    },
+}
+
+// The console picks request IDs and may have several requests in flight; every response carries
+// the ID of the request it answers.
+#[derive(Deserialize)]
+pub struct Request {
+    pub request_id: u32,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+#[derive(Deserialize)]
+struct RequestHeader {
+    request_id: u32,
+    command: String,
+}
+
+impl Request {
+    // The header is decoded first so that a command from newer homebrew that this bridge doesn't
+    // know about can still be answered under its request ID.
+    pub fn decode(codec: Codec, buf: &[u8]) -> Result<Request, (Option<u32>, CommandError)> {
+        let header: RequestHeader = codec.decode(buf).map_err(|err| {
+            (
+                None,
+                CommandError::new(ErrorCode::DecodeFailed, err.to_string()),
+            )
+        })?;
+        if !Command::VARIANTS.contains(&header.command.as_str()) {
+            return Err((
+                Some(header.request_id),
+                CommandError::new(
+                    ErrorCode::UnsupportedCommand,
+                    format!(
+                        "this bridge doesn't support the command `{}`",
+                        header.command
+                    ),
+                ),
+            ));
+        }
+        codec.decode(buf).map_err(|err| {
+            (
+                Some(header.request_id),
+                CommandError::new(ErrorCode::DecodeFailed, err.to_string()),
+            )
+        })
+    }
+}
+
+// The `last_` fields describe the search behind the most recently delivered move. Pieces placed
+// counts forced moves too.
+#[derive(Serialize, Default)]
+pub struct BotStats {
+    pub last_nodes: Option<u32>,
+    pub last_depth: Option<u32>,
+    pub last_think_ms: Option<f64>,
+    pub total_nodes: u64,
+    pub pieces_placed: u32,
+    // The percentage of its node budget and threads the governor has left the bot, when it has
+    // turned it down.
+    pub throttle_percent: Option<u8>,
+    // The CPUs and nice value the bot's threads actually got, when a policy was applied to them.
+    pub thread_policy: Option<Effective>,
+}
+
+#[derive(Serialize)]
+pub struct HandleInfo {
+    pub handle: u32,
+    pub label: Option<String>,
+    pub slot: Option<u8>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+pub enum ErrorCode {
+    InvalidHandle,
+    DecodeFailed,
+    Busy,
+    VersionMismatch,
+    UnsupportedCommand,
+    TooManyBots,
+    BotRelaunched,
+    InvalidArgument,
+    // Sent without a request ID when frames from the switch went missing.
+    FramesLost,
+    // The bot couldn't be started, e.g. because the TBP bot executable is missing.
+    LaunchFailed,
+    // Sent without a request ID right before the bridge exits.
+    ShuttingDown,
+    // Something went wrong in the bridge, e.g. the answer to a command couldn't be encoded.
+    Internal,
+}
+
+// Sent without a request ID, as `Ok`, when something happens to a bot that the console didn't ask
+// for.
+#[derive(Serialize, Debug)]
+pub enum Notification {
+    // The bridge dropped the handle: another bot was launched into its slot (`replaced_by`), or it
+    // was evicted to make room for one.
+    HandleDropped {
+        handle: u32,
+        replaced_by: Option<u32>,
+    },
+    // Part of a calibration: the console answers with CalibrationEcho as soon as it can.
+    CalibrationProbe {
+        seq: u32,
+    },
+    // The result of the calibration the console asked for in its hello.
+    Calibrated {
+        round_trip: LatencyStats,
+    },
+    // The bot's plan started or stopped leading to a perfect clear within the known queue.
+    // `pieces_remaining` counts the move that came with it, and is 0 when not active.
+    PcStatus {
+        handle: u32,
+        active: bool,
+        pieces_remaining: u32,
+    },
+}
+
+#[derive(Serialize)]
+pub enum Response<T> {
+    Ok(T),
+    Err(ErrorCode, String),
+}
+
+// `request_id` is None when the request couldn't be decoded far enough to find its ID.
+#[derive(Serialize)]
+pub struct Reply<T> {
+    pub request_id: Option<u32>,
+    pub response: T,
+}
+
+#[derive(Debug)]
+pub struct CommandError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> CommandError {
+        CommandError {
+            code,
+            message: message.into(),
+        }
+    }
+    pub fn invalid_handle(handle: u32) -> CommandError {
+        CommandError::new(
+            ErrorCode::InvalidHandle,
+            format!("there is no bot with handle {}", handle),
+        )
+    }
+    pub fn launch_failed(err: impl std::fmt::Display) -> CommandError {
+        CommandError::new(
+            ErrorCode::LaunchFailed,
+            format!("the bot could not be launched: {}", err),
+        )
+    }
+}
+
+#[derive(Serialize)]
+pub struct Hello {
+    pub protocol_version: u32,
+    pub commands: &'static [&'static str],
+    pub build: BuildInfo,
+    pub codecs: &'static [&'static str],
+}
+
+// `client_time` is echoed back so the console can measure the round trip with its own clock.
+// `bridge_time` is milliseconds since the Unix epoch when the ping was handled.
+#[derive(Serialize)]
+pub struct Pong {
+    pub client_time: u64,
+    pub bridge_time: u64,
+}
+
+// `reused` is whether the bot was started over on the threads it already had, which it is when
+// it keeps the options and evaluator it was running with.
+#[derive(Serialize)]
+pub struct BotReset {
+    pub reused: bool,
+}
+
+// `round_trip` summarizes the round trips the console has reported.
+#[derive(Serialize)]
+pub struct Echo {
+    pub client_time: u64,
+    pub bridge_time_us: u64,
+    pub round_trip: LatencyStats,
+}
+
+#[derive(Serialize)]
+pub struct ServerInfo {
+    pub build: BuildInfo,
+    pub commands: &'static [&'static str],
+    pub capabilities: Vec<&'static str>,
+    pub max_bots: Option<usize>,
+    pub evaluators: &'static [&'static str],
+    pub presets: Vec<String>,
+    pub plugins: Vec<String>,
+    pub books: Vec<String>,
+    // Transfers on the connection this session is using, when that is USB.
+    pub usb: Option<UsbStatsReport>,
+    // Commands this session sent past the rate limit, which were answered with Busy.
+    pub rejected_commands: u32,
+    // The latest calibration's round trips.
+    pub calibration: Option<LatencyStats>,
+    // How often Launch found a warm interface waiting, when there is a warm pool.
+    pub warm_pool: Option<WarmPoolStats>,
+    // The governor's current level, when it is switched on.
+    pub throttle_percent: Option<u8>,
+}
+
+#[derive(Deserialize)]
+pub struct ClientHello {
+    pub protocol_version: u32,
+    // The token from an earlier session's `Welcome`, to get that session's handles back.
+    #[serde(default)]
+    pub resume: Option<u64>,
+    // Whether the switch can read compressed frames.
+    #[serde(default)]
+    pub compression: bool,
+    // Codecs the switch can use after the handshake, most preferred first.
+    #[serde(default)]
+    pub codecs: Vec<String>,
+    // Calibrate with this many samples right after the handshake. A resumed session that was
+    // calibrated before is calibrated again without being asked, as the port may have changed.
+    #[serde(default)]
+    pub calibrate: Option<u32>,
+}
+
+#[derive(Serialize)]
+pub struct Welcome {
+    pub session_token: u64,
+    pub resumed: bool,
+    // Instead of sending commands, the switch echoes every frame it receives back unchanged.
+    pub selftest: bool,
+    // Set if both sides support compression. Large frames after the welcome may be compressed.
+    pub compression: bool,
+    // Used by both sides for every message after this one.
+    pub codec: Codec,
+}
+
+// Sent by the bridge in self-test mode only.
+#[derive(Serialize)]
+pub struct Probe {
+    pub seq: u32,
+    pub payload: serde_cbor::Value,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const CODECS: [Codec; 3] = [Codec::Cbor, Codec::Json, Codec::Bincode];
+
+    fn decode(
+        codec: Codec,
+        request: serde_json::Value,
+    ) -> Result<Request, (Option<u32>, CommandError)> {
+        Request::decode(codec, &codec.encode(&request).unwrap())
+    }
+
+    #[test]
+    fn known_commands_decode() {
+        for &codec in &CODECS {
+            let request = json!({ "request_id": 3, "command": "Drop", "args": { "handle": 7 } });
+            match decode(codec, request) {
+                Ok(Request {
+                    request_id: 3,
+                    command: Command::Drop { handle: 7 },
+                }) => {}
+                _ => panic!("{:?} didn't decode Drop", codec),
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_commands_keep_their_request_id() {
+        for &codec in &CODECS {
+            let request = json!({ "request_id": 9, "command": "Teleport", "args": { "to": 1 } });
+            match decode(codec, request) {
+                Err((Some(9), err)) => {
+                    assert!(matches!(err.code, ErrorCode::UnsupportedCommand));
+                    assert!(err.message.contains("Teleport"), "{}", err.message);
+                }
+                _ => panic!("{:?} didn't reject Teleport", codec),
+            }
+        }
+    }
+
+    #[test]
+    fn bad_arguments_keep_their_request_id() {
+        for &codec in &CODECS {
+            let request = json!({ "request_id": 4, "command": "Drop", "args": {} });
+            match decode(codec, request) {
+                Err((Some(4), err)) => assert!(matches!(err.code, ErrorCode::DecodeFailed)),
+                _ => panic!("{:?} accepted Drop without a handle", codec),
+            }
+        }
+    }
+
+    #[test]
+    fn unreadable_requests_have_no_request_id() {
+        for &codec in &CODECS {
+            match Request::decode(codec, &[0xff, 0x00]) {
+                Err((None, err)) => assert!(matches!(err.code, ErrorCode::DecodeFailed)),
+                _ => panic!("{:?} decoded garbage", codec),
+            }
+        }
+    }
+}
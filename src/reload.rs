@@ -0,0 +1,186 @@
+use crate::logging::LogFilter;
+use cc_switch_usb_rs::config::ConfigFile;
+use cc_switch_usb_rs::monitor::Monitor;
+use cc_switch_usb_rs::presets::Presets;
+use cc_switch_usb_rs::reloadable::Reloadable;
+use cc_switch_usb_rs::server::SessionConfig;
+use cc_switch_usb_rs::transport::{loopback, UsbTimeouts};
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{info, warn};
+
+// A web or TBP dashboard that can be moved to another address, or stopped, while the bridge
+// runs. Connections that are already open stay open.
+pub struct Dashboard {
+    name: &'static str,
+    spawn: fn(TcpListener, Monitor, Arc<AtomicBool>),
+    monitor: Monitor,
+    running: Option<(SocketAddr, Arc<AtomicBool>)>,
+}
+
+impl Dashboard {
+    pub fn new(
+        name: &'static str,
+        spawn: fn(TcpListener, Monitor, Arc<AtomicBool>),
+        monitor: Monitor,
+    ) -> Dashboard {
+        Dashboard {
+            name,
+            spawn,
+            monitor,
+            running: None,
+        }
+    }
+    // The new address is bound before the old one is let go, so a failed move leaves the
+    // dashboard where it was. Returns whether anything changed.
+    pub fn listen(&mut self, addr: Option<SocketAddr>) -> io::Result<bool> {
+        if self.running.as_ref().map(|&(running, _)| running) == addr {
+            return Ok(false);
+        }
+        let listener = addr.map(TcpListener::bind).transpose()?;
+        if let Some((old, closed)) = self.running.take() {
+            closed.store(true, Ordering::Relaxed);
+            // The accept loop only sees the flag once a connection comes in.
+            TcpStream::connect(loopback(old)).ok();
+            info!("{} on {} stopped", self.name, old);
+        }
+        if let (Some(addr), Some(listener)) = (addr, listener) {
+            let closed = Arc::new(AtomicBool::new(false));
+            (self.spawn)(listener, self.monitor.clone(), closed.clone());
+            self.running = Some((addr, closed));
+        }
+        Ok(true)
+    }
+}
+
+// What a reload may change. Settings given on the command line win over the file, so they are
+// left out (None) and keep their value.
+pub struct Targets {
+    pub log_filter: Option<LogFilter>,
+    pub idle_timeout: Option<Reloadable<Option<Duration>>>,
+    pub bot_deadline: Option<Reloadable<Option<Duration>>>,
+    pub usb_timeouts: Reloadable<UsbTimeouts>,
+    pub presets: Option<Reloadable<Presets>>,
+    pub web: Option<Dashboard>,
+    pub tbp_spectate: Option<Dashboard>,
+}
+
+impl Targets {
+    // Only what changed is applied. A setting that fails to apply keeps its previous value.
+    fn apply(&mut self, old: &ConfigFile, new: &ConfigFile) {
+        if let (Some(log_filter), true) = (&self.log_filter, old.log.level != new.log.level) {
+            match log_filter.set(new.log.level.as_deref()) {
+                Ok(()) => info!(
+                    "Log level: {}",
+                    new.log.level.as_deref().unwrap_or("default")
+                ),
+                Err(err) => warn!("Could not change the log level: {}", err),
+            }
+        }
+        if let Some(idle_timeout) = &self.idle_timeout {
+            let secs = new.session.idle_timeout;
+            if secs != old.session.idle_timeout {
+                idle_timeout.set(timeout(secs.unwrap_or(SessionConfig::DEFAULT_IDLE_TIMEOUT)));
+                info!("Idle timeout for new sessions: {:?}", idle_timeout);
+            }
+        }
+        if let Some(bot_deadline) = &self.bot_deadline {
+            let secs = new.session.bot_deadline;
+            if secs != old.session.bot_deadline {
+                bot_deadline.set(timeout(secs.unwrap_or(SessionConfig::DEFAULT_BOT_DEADLINE)));
+                info!("Bot deadline for new bots: {:?}", bot_deadline);
+            }
+        }
+        let usb_timeouts = new.usb_timeouts();
+        if (usb_timeouts.transfer, usb_timeouts.write_deadline)
+            != (
+                self.usb_timeouts.get().transfer,
+                self.usb_timeouts.get().write_deadline,
+            )
+        {
+            self.usb_timeouts.set(usb_timeouts);
+            info!("USB timeouts for new connections: {:?}", usb_timeouts);
+        }
+        // Reloaded on every change to the file, so touching it also picks up edited presets.
+        if let Some(presets) = &self.presets {
+            let loaded = match &new.session.presets {
+                Some(dir) => Presets::load(dir),
+                None => Ok(Presets::default()),
+            };
+            match loaded {
+                Ok(loaded) => {
+                    info!("Evaluator presets: {}", loaded.names().join(", "));
+                    presets.set(loaded);
+                }
+                Err(err) => warn!("Could not reload the evaluator presets: {:?}", err),
+            }
+        }
+        let dashboards = vec![
+            (&mut self.web, new.transport.web),
+            (&mut self.tbp_spectate, new.transport.tbp_spectate),
+        ];
+        for (dashboard, addr) in dashboards {
+            let dashboard = match dashboard {
+                Some(dashboard) => dashboard,
+                None => continue,
+            };
+            match (dashboard.listen(addr), addr) {
+                (Ok(true), Some(addr)) => info!("{} on {}", dashboard.name, addr),
+                (Ok(_), _) => {}
+                (Err(err), _) => warn!(
+                    "Could not move the {} to {:?}: {}",
+                    dashboard.name, addr, err
+                ),
+            }
+        }
+    }
+}
+
+// Checks the file for changes every second and applies the reloadable settings. A file that
+// doesn't load is reported and otherwise ignored, leaving the bridge as it was.
+pub fn watch(path: PathBuf, mut file: ConfigFile, mut targets: Targets) {
+    const POLL: Duration = Duration::from_secs(1);
+    let modified = |path: &PathBuf| -> Option<SystemTime> {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    std::thread::Builder::new()
+        .name("reload".to_owned())
+        .spawn(move || {
+            let mut last = modified(&path);
+            loop {
+                std::thread::sleep(POLL);
+                let now = modified(&path);
+                if now == last {
+                    continue;
+                }
+                last = now;
+                match ConfigFile::load(&path) {
+                    Ok(new) => {
+                        info!("Reloading {}", path.display());
+                        targets.apply(&file, &new);
+                        file = new;
+                    }
+                    Err(err) => warn!(
+                        "Could not reload {}, keeping the current settings: {:?}",
+                        path.display(),
+                        err
+                    ),
+                }
+            }
+        })
+        .unwrap();
+}
+
+// 0 means no timeout at all.
+pub fn timeout(secs: u64) -> Option<Duration> {
+    match secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
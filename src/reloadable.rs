@@ -0,0 +1,37 @@
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+// A setting that can be changed while the bridge runs, shared by every clone. Readers take the
+// value as it is when they read it, so sessions, connections and bots that are already running
+// keep what they started with and only the next ones see the change.
+pub struct Reloadable<T>(Arc<RwLock<Arc<T>>>);
+
+impl<T> Reloadable<T> {
+    pub fn new(value: T) -> Reloadable<T> {
+        Reloadable(Arc::new(RwLock::new(Arc::new(value))))
+    }
+    pub fn get(&self) -> Arc<T> {
+        self.0.read().unwrap().clone()
+    }
+    pub fn set(&self, value: T) {
+        *self.0.write().unwrap() = Arc::new(value);
+    }
+}
+
+impl<T> Clone for Reloadable<T> {
+    fn clone(&self) -> Reloadable<T> {
+        Reloadable(self.0.clone())
+    }
+}
+
+impl<T: Default> Default for Reloadable<T> {
+    fn default() -> Reloadable<T> {
+        Reloadable::new(T::default())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Reloadable<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.get().fmt(f)
+    }
+}
@@ -0,0 +1,39 @@
+use libtetris::{Board, FallingPiece};
+
+// Draws the board for a terminal, from the highest filled row (and at least a few rows) down,
+// with the piece just placed picked out so it can be checked against the console.
+pub fn board(board: &Board, placed: Option<&FallingPiece>) -> String {
+    const MIN_ROWS: i32 = 4;
+    let placed: Vec<(i32, i32)> = placed.map_or(vec![], |piece| {
+        piece.cells().iter().map(|&(x, y, _)| (x, y)).collect()
+    });
+    let top = (0..40)
+        .rev()
+        .find(|&y| (0..10).any(|x| board.occupied(x, y)))
+        .map_or(0, |y| y + 1)
+        .max(MIN_ROWS);
+    let mut out = String::new();
+    for y in (0..top).rev() {
+        out.push('|');
+        for x in 0..10 {
+            out.push_str(if placed.contains(&(x, y)) {
+                "▓▓"
+            } else if board.occupied(x, y) {
+                "██"
+            } else {
+                " ·"
+            });
+        }
+        out.push_str("|\n");
+    }
+    out.push_str("+--------------------+\n");
+    let hold = board
+        .hold_piece
+        .map_or("-".to_owned(), |piece| format!("{:?}", piece));
+    let queue: Vec<String> = board
+        .next_queue()
+        .map(|piece| format!("{:?}", piece))
+        .collect();
+    out.push_str(&format!("hold {}  next {}", hold, queue.join(" ")));
+    out
+}
@@ -0,0 +1,336 @@
+use cc_switch_usb_rs::protocol::{Command, PROTOCOL_VERSION};
+use cc_switch_usb_rs::server::SessionConfig;
+use cc_switch_usb_rs::transport::{Outbox, Transport, TransportError, TransportWriter};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use serde::Serialize;
+use serde_cbor::Value;
+use serde_json::{json, Map, Value as Json};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, ChildStdin, Stdio};
+use std::sync::mpsc::channel;
+use std::time::Duration;
+use strum::VariantNames;
+
+struct ReplHelper;
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let prefix = &line[..pos];
+        if prefix.contains(char::is_whitespace) {
+            return Ok((pos, vec![]));
+        }
+        let prefix = prefix.to_lowercase();
+        let candidates = Command::VARIANTS
+            .iter()
+            .chain(["help", "quit"].iter())
+            .filter(|name| name.to_lowercase().starts_with(&prefix))
+            .map(|name| name.to_string())
+            .collect();
+        Ok((0, candidates))
+    }
+}
+
+impl Hinter for ReplHelper {}
+impl Highlighter for ReplHelper {}
+impl Validator for ReplHelper {}
+impl Helper for ReplHelper {}
+
+// Where the repl finds a bridge: a running one's --listen socket, or one it starts on stdio.
+pub enum Target {
+    Tcp(SocketAddr),
+    Stdio { config: Option<PathBuf> },
+}
+
+// The repl's end of the connection. Replies are read here and commands go out through an outbox,
+// numbered the way the console numbers its frames.
+struct Connection {
+    reader: Box<dyn Read + Send>,
+    writer: Option<Box<dyn TransportWriter>>,
+    unread: Vec<u8>,
+}
+
+impl Transport for Connection {
+    fn kind(&self) -> &'static str {
+        "repl"
+    }
+    fn read_all(&mut self, buf: &mut [u8]) -> Result<(), TransportError> {
+        Ok(self.reader.read_exact(buf)?)
+    }
+    fn read_some(&mut self, buf: &mut [u8]) -> Result<usize, TransportError> {
+        match self.reader.read(buf)? {
+            0 => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            read => Ok(read),
+        }
+    }
+    fn unread(&mut self) -> &mut Vec<u8> {
+        &mut self.unread
+    }
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), TransportError> {
+        match &mut self.writer {
+            Some(writer) => writer.write_all(buf),
+            None => Err(io::Error::new(io::ErrorKind::Other, "the writer was handed out").into()),
+        }
+    }
+    fn set_idle_timeout(&mut self, _timeout: Option<Duration>) -> Result<(), TransportError> {
+        Ok(())
+    }
+    fn writer(&mut self) -> Result<Box<dyn TransportWriter>, TransportError> {
+        self.writer
+            .take()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "the writer was handed out").into())
+    }
+}
+
+struct Pipe(ChildStdin);
+
+impl TransportWriter for Pipe {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), TransportError> {
+        self.0.write_all(buf)?;
+        Ok(self.0.flush()?)
+    }
+}
+
+#[derive(Serialize)]
+struct ClientHello {
+    protocol_version: u32,
+}
+
+// Pings go out under this request ID to keep the session from timing out while nobody types,
+// and their replies aren't shown.
+const KEEPALIVE_ID: u32 = u32::MAX;
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+fn connect(target: Target) -> io::Result<(Connection, Option<Child>)> {
+    match target {
+        Target::Tcp(addr) => {
+            let stream = TcpStream::connect(addr)?;
+            stream.set_nodelay(true)?;
+            let conn = Connection {
+                reader: Box::new(stream.try_clone()?),
+                writer: Some(Box::new(stream)),
+                unread: vec![],
+            };
+            Ok((conn, None))
+        }
+        Target::Stdio { config } => {
+            let mut bridge = std::process::Command::new(std::env::current_exe()?);
+            bridge.arg("--stdio").args(&["--log-level", "warn"]);
+            if let Some(config) = config {
+                bridge.arg("--config").arg(config);
+            }
+            let mut child = bridge
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()?;
+            let conn = Connection {
+                reader: Box::new(child.stdout.take().unwrap()),
+                writer: Some(Box::new(Pipe(child.stdin.take().unwrap()))),
+                unread: vec![],
+            };
+            Ok((conn, Some(child)))
+        }
+    }
+}
+
+fn field<'a>(message: &'a Value, name: &str) -> Option<&'a Value> {
+    match message {
+        Value::Map(map) => map.get(&Value::Text(name.to_owned())),
+        _ => None,
+    }
+}
+
+fn print(label: &str, value: Option<&Value>) {
+    match serde_json::to_string_pretty(&value) {
+        Ok(json) => println!("{}{}", label, json),
+        Err(err) => println!("Could not print the {}: {}", label, err),
+    }
+}
+
+// Talks to the bridge the way the console does, so whatever it is serving can be poked at by
+// hand. Each command waits for its reply; notifications are printed whenever they arrive.
+pub fn run(config: &SessionConfig, target: Target) {
+    let (mut conn, mut child) = match connect(target) {
+        Ok(connected) => connected,
+        Err(err) => {
+            println!("Could not reach the bridge: {}", err);
+            return;
+        }
+    };
+    let outbox = match conn.writer() {
+        Ok(writer) => Outbox::new(writer),
+        Err(err) => {
+            println!("Error: {:?}", err);
+            return;
+        }
+    };
+    if let Err(err) = handshake(&mut conn, &outbox) {
+        println!("{}", err);
+        return;
+    }
+
+    let (answered, answers) = channel();
+    std::thread::spawn(move || loop {
+        let message: Value = match conn.receive() {
+            Ok(message) => message,
+            Err(err) => {
+                println!("Disconnected: {:?}", err);
+                break;
+            }
+        };
+        let request_id = match field(&message, "request_id") {
+            Some(&Value::Integer(id)) => Some(id as u32),
+            _ => None,
+        };
+        match request_id {
+            Some(KEEPALIVE_ID) => continue,
+            Some(_) => print("", field(&message, "response")),
+            None => print("Notification: ", field(&message, "response")),
+        }
+        if answered.send(request_id).is_err() {
+            break;
+        }
+    });
+    let keepalive = outbox.clone();
+    std::thread::spawn(move || loop {
+        std::thread::sleep(KEEPALIVE_INTERVAL);
+        keepalive.send(&json!({
+            "request_id": KEEPALIVE_ID,
+            "command": "Ping",
+            "args": { "client_time": 0 },
+        }));
+    });
+
+    let mut editor = Editor::<ReplHelper>::new();
+    editor.set_helper(Some(ReplHelper));
+    println!("Type `help` for a list of commands.");
+    let mut next_id = 1;
+    loop {
+        let line = match editor.readline("> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                println!("Error: {:?}", err);
+                break;
+            }
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line);
+        let command = match line {
+            "help" => {
+                help();
+                continue;
+            }
+            "quit" | "exit" => break,
+            _ => match parse(line, config) {
+                Ok(command) => command,
+                Err(err) => {
+                    println!("Error: {}", err);
+                    continue;
+                }
+            },
+        };
+        let mut request = serde_json::to_value(&command).unwrap();
+        request["request_id"] = Json::from(next_id);
+        if let Err(err) = outbox.try_send(&request) {
+            println!("Could not encode the command: {}", err);
+            continue;
+        }
+        if let Some(err) = outbox.take_error() {
+            println!("Could not send the command: {:?}", err);
+            break;
+        }
+        // Notifications can come in before the reply.
+        if !answers.iter().any(|request_id| request_id == Some(next_id)) {
+            break;
+        }
+        next_id += 1;
+    }
+    if let Some(child) = &mut child {
+        child.kill().ok();
+        child.wait().ok();
+    }
+}
+
+fn handshake(conn: &mut Connection, outbox: &Outbox) -> Result<(), String> {
+    let hello: Value = conn.receive().map_err(|err| format!("Error: {:?}", err))?;
+    outbox.send(&ClientHello {
+        protocol_version: PROTOCOL_VERSION,
+    });
+    if let Some(err) = outbox.take_error() {
+        return Err(format!("Error: {:?}", err));
+    }
+    let welcome: Value = conn.receive().map_err(|err| format!("Error: {:?}", err))?;
+    match field(&welcome, "Ok").and_then(|welcome| field(welcome, "session_token")) {
+        Some(&Value::Integer(token)) => {
+            print("Connected to ", field(&hello, "build"));
+            println!("Session {:016x}", token);
+            Ok(())
+        }
+        _ => {
+            print("The bridge turned the repl away: ", Some(&welcome));
+            Err("Could not start a session".to_owned())
+        }
+    }
+}
+
+fn help() {
+    println!("Commands are written as `Name key=value ...`, or as raw JSON.");
+    println!("Values are parsed as JSON, falling back to a string (e.g. `piece=L`).");
+    println!("Launch uses the default options and evaluator unless they (or a preset) are given.");
+    println!();
+    for name in Command::VARIANTS {
+        println!("  {}", name);
+    }
+}
+
+fn parse(line: &str, config: &SessionConfig) -> Result<Command, String> {
+    if line.starts_with('{') {
+        return serde_json::from_str(line).map_err(|err| err.to_string());
+    }
+    let mut words = line.split_whitespace();
+    let name = words.next().unwrap();
+    let name = Command::VARIANTS
+        .iter()
+        .find(|variant| variant.eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("unknown command `{}`", name))?;
+
+    let mut args = Map::new();
+    for word in words {
+        let mut parts = word.splitn(2, '=');
+        let key = parts.next().unwrap();
+        let value = parts
+            .next()
+            .ok_or_else(|| format!("expected `key=value`, found `{}`", word))?;
+        let value = serde_json::from_str(value).unwrap_or_else(|_| Json::from(value));
+        args.insert(key.to_owned(), value);
+    }
+    if *name == "Launch" {
+        if !args.contains_key("options") {
+            let options = serde_json::to_value(config.default_options).unwrap();
+            args.insert("options".to_owned(), options);
+        }
+    }
+
+    let mut command = Map::new();
+    command.insert("command".to_owned(), Json::from(*name));
+    if !args.is_empty() {
+        command.insert("args".to_owned(), Json::Object(args));
+    }
+    serde_json::from_value(Json::Object(command)).map_err(|err| err.to_string())
+}
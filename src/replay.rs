@@ -0,0 +1,287 @@
+use crate::audit::AuditEntry;
+use crate::build_info::GIT_HASH;
+use crate::protocol::PROTOCOL_VERSION;
+use crate::resume::SessionStore;
+use crate::server::{run_session, SessionConfig, SessionError};
+use crate::transport::{decode_frame, encode_frame, Transport, TransportError, TransportWriter};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    // The line number, counting from 1.
+    Parse(usize, serde_json::Error),
+    NoCommands,
+    Session(SessionError),
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> ReplayError {
+        ReplayError::Io(err)
+    }
+}
+
+pub struct ReplayReport {
+    pub session: u64,
+    pub commands: usize,
+    // In the order they were sent.
+    pub responses: Vec<Value>,
+    // Requests whose responses don't match the recorded ones.
+    pub differing: Vec<u32>,
+    pub unanswered: usize,
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Replayed {} commands from session {:016x}: {} responses",
+            self.commands,
+            self.session,
+            self.responses.len()
+        )?;
+        if self.unanswered > 0 {
+            write!(f, ", {} requests got no response", self.unanswered)?;
+        }
+        if self.differing.is_empty() {
+            write!(f, ", all matching the recording")
+        } else {
+            write!(
+                f,
+                ", requests {:?} answered differently than in the recording",
+                self.differing
+            )
+        }
+    }
+}
+
+// Runs the commands of one recorded session (the first in the file unless `session` is given)
+// through a real session, as if a switch were sending them. With `realtime`, commands are spaced
+// out like they originally were, which matters when the switch was polling for moves.
+pub fn run(
+    config: &SessionConfig,
+    path: &Path,
+    session: Option<u64>,
+    realtime: bool,
+) -> Result<ReplayReport, ReplayError> {
+    let (session, entries) = load(path, session)?;
+    // Logs from before builds were recorded don't say.
+    let recorded_by = entries.iter().find_map(|entry| entry.build.as_ref());
+    if let Some(build) = recorded_by.filter(|build| build["git_hash"] != GIT_HASH) {
+        warn!(
+            "The session was recorded by build {} {}, so answers may differ for that reason alone",
+            build["version"].as_str().unwrap_or("?"),
+            build["git_hash"].as_str().unwrap_or("?")
+        );
+    }
+    let mut commands = vec![];
+    let mut recorded: BTreeMap<u32, Vec<Value>> = BTreeMap::new();
+    for entry in entries {
+        match (entry.request_id, entry.command, entry.response) {
+            (Some(request_id), Some(command), _) => {
+                commands.push((entry.time, request_id, command))
+            }
+            (Some(request_id), None, Some(response)) => {
+                recorded.entry(request_id).or_default().push(response)
+            }
+            _ => {}
+        }
+    }
+    let start = match commands.first() {
+        Some(&(time, _, _)) => time,
+        None => return Err(ReplayError::NoCommands),
+    };
+
+    let hello = serde_json::json!({ "protocol_version": PROTOCOL_VERSION });
+    let mut frames = VecDeque::new();
+    frames.push_back((
+        Duration::from_secs(0),
+        encode_frame(0, &to_cbor(&hello), false),
+    ));
+    for (seq, (time, request_id, command)) in commands.iter().enumerate() {
+        let mut request = serde_json::Map::new();
+        request.insert("request_id".to_owned(), Value::from(*request_id));
+        if let Value::Object(command) = command {
+            request.extend(command.clone());
+        }
+        let at = Duration::from_millis(time.saturating_sub(start));
+        let frame = encode_frame(seq as u32 + 1, &to_cbor(&Value::Object(request)), false);
+        frames.push_back((at, frame));
+    }
+    let replies = Arc::new((Mutex::new(vec![]), Condvar::new()));
+    let mut conn = ReplayTransport {
+        frames,
+        current: vec![],
+        pos: 0,
+        started: Instant::now(),
+        realtime,
+        expected: commands.len(),
+        replies: replies.clone(),
+        unread: vec![],
+    };
+    // Nothing to resume into, so resuming is off.
+    let sessions = SessionStore::new(Duration::from_secs(0));
+    match run_session(&mut conn, config, &sessions) {
+        SessionError::Transport(TransportError::Io(err))
+            if err.kind() == io::ErrorKind::UnexpectedEof => {}
+        err => return Err(ReplayError::Session(err)),
+    }
+
+    let responses = replies.0.lock().unwrap().clone();
+    let mut replayed: BTreeMap<u32, Vec<Value>> = BTreeMap::new();
+    for reply in &responses {
+        if let (Some(request_id), Some(response)) =
+            (reply["request_id"].as_u64(), reply.get("response"))
+        {
+            replayed
+                .entry(request_id as u32)
+                .or_default()
+                .push(response.clone());
+        }
+    }
+    let differing = commands
+        .iter()
+        .map(|&(_, request_id, _)| request_id)
+        .filter(|request_id| recorded.get(request_id) != replayed.get(request_id))
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let unanswered = commands.len().saturating_sub(answered(&responses));
+    Ok(ReplayReport {
+        session,
+        commands: commands.len(),
+        responses,
+        differing,
+        unanswered,
+    })
+}
+
+fn load(path: &Path, session: Option<u64>) -> Result<(u64, Vec<AuditEntry>), ReplayError> {
+    let mut entries = vec![];
+    for (i, line) in BufReader::new(std::fs::File::open(path)?)
+        .lines()
+        .enumerate()
+    {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: AuditEntry =
+            serde_json::from_str(&line).map_err(|err| ReplayError::Parse(i + 1, err))?;
+        entries.push(entry);
+    }
+    let session = match session.or_else(|| entries.first().map(|entry| entry.session)) {
+        Some(session) => session,
+        None => return Err(ReplayError::NoCommands),
+    };
+    entries.retain(|entry| entry.session == session);
+    Ok((session, entries))
+}
+
+fn to_cbor(value: &Value) -> Vec<u8> {
+    serde_cbor::to_vec(value).unwrap()
+}
+
+fn answered(replies: &[Value]) -> usize {
+    replies
+        .iter()
+        .filter(|reply| !reply["request_id"].is_null())
+        .count()
+}
+
+// Plays the part of the switch: reads come from the recording, and every reply the bridge writes
+// is kept. Once the recording runs out, reads wait for the outstanding replies and then report
+// the end of the stream, which ends the session.
+struct ReplayTransport {
+    frames: VecDeque<(Duration, Vec<u8>)>,
+    current: Vec<u8>,
+    pos: usize,
+    started: Instant,
+    realtime: bool,
+    expected: usize,
+    replies: Arc<(Mutex<Vec<Value>>, Condvar)>,
+    unread: Vec<u8>,
+}
+
+impl ReplayTransport {
+    const REPLY_TIMEOUT: Duration = Duration::from_secs(60);
+}
+
+impl Transport for ReplayTransport {
+    fn kind(&self) -> &'static str {
+        "replay"
+    }
+    fn read_all(&mut self, buf: &mut [u8]) -> Result<(), TransportError> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            if self.pos == self.current.len() {
+                let (at, frame) = match self.frames.pop_front() {
+                    Some(next) => next,
+                    None => {
+                        let (replies, replied) = &*self.replies;
+                        let expected = self.expected;
+                        let replies = replies.lock().unwrap();
+                        let _ = replied
+                            .wait_timeout_while(
+                                replies,
+                                ReplayTransport::REPLY_TIMEOUT,
+                                |replies| answered(replies) < expected,
+                            )
+                            .unwrap();
+                        let eof =
+                            io::Error::new(io::ErrorKind::UnexpectedEof, "end of the recording");
+                        return Err(eof.into());
+                    }
+                };
+                if self.realtime {
+                    if let Some(wait) = at.checked_sub(self.started.elapsed()) {
+                        std::thread::sleep(wait);
+                    }
+                }
+                self.current = frame;
+                self.pos = 0;
+            }
+            let len = (buf.len() - filled).min(self.current.len() - self.pos);
+            buf[filled..filled + len].copy_from_slice(&self.current[self.pos..self.pos + len]);
+            filled += len;
+            self.pos += len;
+        }
+        Ok(())
+    }
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), TransportError> {
+        ReplayWriter(self.replies.clone()).write_all(buf)
+    }
+    fn unread(&mut self) -> &mut Vec<u8> {
+        &mut self.unread
+    }
+    fn set_idle_timeout(&mut self, _timeout: Option<Duration>) -> Result<(), TransportError> {
+        Ok(())
+    }
+    fn writer(&mut self) -> Result<Box<dyn TransportWriter>, TransportError> {
+        Ok(Box::new(ReplayWriter(self.replies.clone())))
+    }
+}
+
+struct ReplayWriter(Arc<(Mutex<Vec<Value>>, Condvar)>);
+
+impl TransportWriter for ReplayWriter {
+    // The outbox writes one whole frame at a time. The hello and welcome have no request ID and
+    // aren't kept.
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), TransportError> {
+        let reply = decode_frame(buf)
+            .and_then(|frame| serde_cbor::from_slice::<Value>(&frame.payload).ok());
+        if let Some(reply) = reply.filter(|reply| reply.get("request_id").is_some()) {
+            let (replies, replied) = &*self.0;
+            replies.lock().unwrap().push(reply);
+            replied.notify_all();
+        }
+        Ok(())
+    }
+}
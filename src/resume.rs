@@ -0,0 +1,93 @@
+use crate::server::Bots;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+struct Parked {
+    bots: Bots,
+    transport: &'static str,
+    parked_at: Instant,
+}
+
+// Bots of sessions whose connection was lost, kept around for `grace` so a
+// console that reconnects with the session's token gets its handles back, searches and all.
+// Each transport has its own sessions: a token presented over TCP can't take over bots a USB
+// console left behind, and the other way round.
+#[derive(Clone)]
+pub struct SessionStore {
+    grace: Duration,
+    parked: Arc<Mutex<HashMap<u64, Parked>>>,
+}
+
+impl SessionStore {
+    pub fn new(grace: Duration) -> SessionStore {
+        SessionStore {
+            grace,
+            parked: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+    // Tokens only need to be hard to guess by accident, so std's randomly keyed hasher is enough.
+    pub fn new_token(&self) -> u64 {
+        let mut hasher = RandomState::new().build_hasher();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        hasher.write_u128(now.as_nanos());
+        hasher.finish()
+    }
+    pub fn park(&self, token: u64, transport: &'static str, mut bots: Bots) {
+        if self.grace == Duration::from_secs(0) {
+            return;
+        }
+        bots.detach();
+        let parked_at = Instant::now();
+        self.parked.lock().unwrap().insert(
+            token,
+            Parked {
+                bots,
+                transport,
+                parked_at,
+            },
+        );
+        info!(
+            "Keeping session {:016x} for {} seconds in case the switch reconnects",
+            token,
+            self.grace.as_secs()
+        );
+        // Whatever the bots are still thinking about keeps the CPU busy, so expired sessions are
+        // dropped on time rather than whenever the next console connects.
+        let store = self.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(store.grace);
+            let mut parked = store.parked.lock().unwrap();
+            if parked.get(&token).map(|session| session.parked_at) == Some(parked_at) {
+                parked.remove(&token);
+                info!("Session {:016x} expired", token);
+            }
+        });
+    }
+    // Drops every parked session, which saves their bots' games.
+    pub fn clear(&self) {
+        self.parked.lock().unwrap().clear();
+    }
+    pub fn resume(&self, token: u64, transport: &str) -> Option<Bots> {
+        let mut parked = self.parked.lock().unwrap();
+        if parked.get(&token)?.transport != transport {
+            warn!(
+                "Refused to resume session {:016x} over {}, as it was on another transport",
+                token, transport
+            );
+            return None;
+        }
+        let session = parked.remove(&token)?;
+        drop(parked);
+        if session.parked_at.elapsed() > self.grace {
+            return None;
+        }
+        info!("Resumed session {:016x}", token);
+        Some(session.bots)
+    }
+}
@@ -0,0 +1,189 @@
+use crate::backoff::Backoff;
+use crate::build_info::BuildInfo;
+use crate::codec::Codec;
+use crate::latency::{LatencyStats, LatencyWindow};
+use crate::protocol::{ClientHello, Command, Hello, Probe, Response, Welcome, PROTOCOL_VERSION};
+use crate::server::{reject_version, SessionError, UsbConfig};
+use crate::transport::{Outbox, SwitchConnection, Transport};
+use std::time::{Duration, Instant};
+use strum::VariantNames;
+use tracing::{info, warn};
+
+pub struct SelfTestReport {
+    pub round_trips: LatencyStats,
+    // (payload size, round trip)
+    pub large: Vec<(usize, Duration)>,
+    pub burst_count: u32,
+    pub burst_elapsed: Duration,
+    // Echoes that didn't match the frame that was sent.
+    pub framing_errors: u32,
+}
+
+impl std::fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "echo:           {} round trips, min {:.1}ms, median {:.1}ms, p95 {:.1}ms, max {:.1}ms",
+            self.round_trips.samples,
+            self.round_trips.min_ms,
+            self.round_trips.median_ms,
+            self.round_trips.p95_ms,
+            self.round_trips.max_ms
+        )?;
+        for &(size, elapsed) in &self.large {
+            // The payload crosses the cable twice.
+            let throughput = 2.0 * size as f64 / elapsed.as_secs_f64() / 1024.0;
+            writeln!(
+                f,
+                "large payload:  {} KiB in {:.1}ms ({:.0} KiB/s)",
+                size / 1024,
+                elapsed.as_secs_f64() * 1000.0,
+                throughput
+            )?;
+        }
+        writeln!(
+            f,
+            "rapid fire:     {} frames in {:.1}ms ({:.0} frames/s)",
+            self.burst_count,
+            self.burst_elapsed.as_secs_f64() * 1000.0,
+            f64::from(self.burst_count) / self.burst_elapsed.as_secs_f64()
+        )?;
+        write!(f, "framing errors: {}", self.framing_errors)
+    }
+}
+
+struct Prober {
+    seq: u32,
+    framing_errors: u32,
+}
+
+impl Prober {
+    const ROUND_TRIPS: u32 = 200;
+    const LARGE_SIZES: [usize; 4] = [1 << 10, 1 << 14, 1 << 16, 1 << 20];
+    const BURST: u32 = 1000;
+
+    fn frame(&mut self, size: usize) -> Vec<u8> {
+        self.seq += 1;
+        let seq = self.seq;
+        let payload = (0..size).map(|i| (i as u32 ^ seq) as u8).collect();
+        serde_cbor::to_vec(&Probe {
+            seq,
+            payload: serde_cbor::Value::Bytes(payload),
+        })
+        .unwrap()
+    }
+    fn send(&self, outbox: &Outbox, frame: &[u8]) -> Result<(), SessionError> {
+        outbox.send_payload(frame);
+        match outbox.take_error() {
+            Some(err) => Err(err.into()),
+            None => Ok(()),
+        }
+    }
+    fn check(&mut self, conn: &mut impl Transport, sent: &[u8]) -> Result<(), SessionError> {
+        if conn.receive_frame()?.payload != sent {
+            self.framing_errors += 1;
+        }
+        Ok(())
+    }
+    fn round_trip(
+        &mut self,
+        conn: &mut impl Transport,
+        outbox: &Outbox,
+        size: usize,
+    ) -> Result<Duration, SessionError> {
+        let frame = self.frame(size);
+        let sent_at = Instant::now();
+        self.send(outbox, &frame)?;
+        self.check(conn, &frame)?;
+        Ok(sent_at.elapsed())
+    }
+}
+
+// The same handshake as a normal session, except that the welcome asks the switch to echo.
+pub fn run(conn: &mut impl Transport) -> Result<SelfTestReport, SessionError> {
+    const ECHO_TIMEOUT: Duration = Duration::from_secs(5);
+    let outbox = Outbox::new(conn.writer()?);
+    outbox.send(&Hello {
+        protocol_version: PROTOCOL_VERSION,
+        commands: Command::VARIANTS,
+        build: BuildInfo::get(),
+        codecs: Codec::VARIANTS,
+    });
+    let client: ClientHello = conn.receive()?;
+    if client.protocol_version != PROTOCOL_VERSION {
+        return Err(reject_version(&outbox, client.protocol_version));
+    }
+    outbox.send(&Response::Ok(Welcome {
+        session_token: 0,
+        resumed: false,
+        selftest: true,
+        compression: false,
+        codec: Codec::Cbor,
+    }));
+    conn.set_idle_timeout(Some(ECHO_TIMEOUT))?;
+
+    let mut prober = Prober {
+        seq: 0,
+        framing_errors: 0,
+    };
+    let mut round_trips = LatencyWindow::with_capacity(Prober::ROUND_TRIPS as usize);
+    for _ in 0..Prober::ROUND_TRIPS {
+        round_trips.record(prober.round_trip(conn, &outbox, 16)?);
+    }
+    let mut large = vec![];
+    for &size in &Prober::LARGE_SIZES {
+        large.push((size, prober.round_trip(conn, &outbox, size)?));
+    }
+    // Everything is written before anything is read back, so the echoes queue up on both sides.
+    let frames: Vec<_> = (0..Prober::BURST).map(|_| prober.frame(32)).collect();
+    let started = Instant::now();
+    for frame in &frames {
+        prober.send(&outbox, frame)?;
+    }
+    for frame in &frames {
+        prober.check(conn, frame)?;
+    }
+    Ok(SelfTestReport {
+        round_trips: round_trips.stats(),
+        large,
+        burst_count: Prober::BURST,
+        burst_elapsed: started.elapsed(),
+        framing_errors: prober.framing_errors,
+    })
+}
+
+// Tests the first matching device to show up.
+pub fn run_usb(usb: &UsbConfig) -> Result<SelfTestReport, SessionError> {
+    let mut backoff = Backoff::new(usb.retry_min, usb.retry_max);
+    loop {
+        let devices = SwitchConnection::find_devices(&usb.devices).unwrap_or_default();
+        for device in devices {
+            match SwitchConnection::open(&device, &usb.devices.interface) {
+                Ok(conn) => {
+                    info!(
+                        "Connected to the switch on bus {} address {}, running the self-test...",
+                        device.bus_number(),
+                        device.address()
+                    );
+                    let conn = conn
+                        .with_timeouts(*usb.timeouts.get())
+                        .with_capture(usb.capture.clone());
+                    return run(&mut conn.pipelined());
+                }
+                Err(err) => warn!(
+                    "Can't connect to the device on bus {} address {}: {}",
+                    device.bus_number(),
+                    device.address(),
+                    err.remediation(&usb.devices)
+                ),
+            }
+        }
+        let delay = backoff.next_delay();
+        info!(
+            "No switch found (attempt {}). Retrying in {:.1} seconds...",
+            backoff.attempts(),
+            delay.as_secs_f64()
+        );
+        std::thread::sleep(delay);
+    }
+}
@@ -0,0 +1,2704 @@
+use crate::ab_test::{AbGame, AbTest};
+use crate::audit::{AuditLog, SessionAudit};
+use crate::backoff::Backoff;
+use crate::books::{Book, Books};
+use crate::build_info::BuildInfo;
+use crate::coach::{self, Coach};
+use crate::codec::Codec;
+use crate::engine::{Backend, Engine, EngineError, TbpBot};
+use crate::fumen;
+use crate::game_record::{GameEvent, GameRecord, GameRecorder};
+use crate::garbage;
+use crate::governor::{self, Governor};
+use crate::latency::{LatencyStats, LatencyWindow};
+use crate::monitor::{BotStatus, Feed, FeedEvent, FeedKind, Monitor};
+use crate::pc::{self, PcSolver};
+use crate::plugins::{Plugin, PluginEvaluator, Plugins};
+use crate::pool::WarmPool;
+use crate::presets::{patch, Presets};
+use crate::priority::ThreadPolicy;
+use crate::protocol::{
+    BoardState, BotReset, BotStats, ClientHello, Command, CommandError, Echo, ErrorCode,
+    EvaluatorChoice, FieldRows, HandleInfo, Hello, MoveOutcome, MoveResult, Notification, Pong,
+    Reply, Request, Response, ServerInfo, Welcome, PROTOCOL_VERSION,
+};
+use crate::reloadable::Reloadable;
+use crate::render;
+use crate::resume::SessionStore;
+use crate::shutdown;
+use crate::transport::{
+    loopback, Capture, DeviceFilter, HotplugEvent, Outbox, ReceiveError, StdioTransport,
+    SwitchConnection, TcpTransport, Transport, TransportError, UsbStats, UsbTimeouts,
+};
+use libtetris::Board;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use strum::VariantNames;
+use tracing::{debug, error, info, info_span, warn, Span};
+
+pub trait Responder {
+    fn respond(&mut self, msg: &impl Serialize);
+    fn ok(&mut self, value: impl Serialize) {
+        self.respond(&Response::Ok(value));
+    }
+    fn err(&mut self, err: CommandError) {
+        self.respond(&Response::<()>::Err(err.code, err.message));
+    }
+    // Responders with no console behind them have nobody to tell.
+    fn notify(&mut self, _notification: Notification) {}
+}
+
+// Everything needed to launch a bot again with the same options and evaluator.
+#[derive(Clone)]
+struct LaunchParams {
+    options: cold_clear::Options,
+    evaluator: cold_clear::evaluation::Standard,
+    policy: ThreadPolicy,
+    backend: Backend,
+    // Adds to the evaluator's score; only cold clear bots can use it.
+    plugin: Option<Arc<Plugin>>,
+    book: Option<Arc<Book>>,
+}
+
+impl LaunchParams {
+    // TBP bots only take the movement mode from the options.
+    fn launch(&self, board: Board) -> Result<Engine, EngineError> {
+        match &self.backend {
+            Backend::ColdClear => {
+                let (options, evaluator) = (self.options, self.evaluator.clone());
+                let plugin = self.plugin.clone();
+                let interface = self.policy.run(move || match plugin {
+                    Some(plugin) => {
+                        let evaluator = PluginEvaluator {
+                            standard: evaluator,
+                            plugin,
+                        };
+                        cold_clear::Interface::launch(board, options, evaluator)
+                    }
+                    None => cold_clear::Interface::launch(board, options, evaluator),
+                });
+                Ok(interface.into())
+            }
+            Backend::Tbp(command) => {
+                TbpBot::launch(command, self.options.mode, board).map(Engine::Tbp)
+            }
+        }
+    }
+    // Whether a bot launched with `other` would play the same. Options and weights are compared
+    // serialized, as the warm pool does; plugins and books by identity.
+    fn same_as(&self, other: &LaunchParams) -> bool {
+        fn same<T>(a: &Option<Arc<T>>, b: &Option<Arc<T>>) -> bool {
+            match (a, b) {
+                (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+                (a, b) => a.is_none() && b.is_none(),
+            }
+        }
+        let key = |params: &LaunchParams| {
+            serde_cbor::to_vec(&(&params.options, &params.evaluator)).unwrap()
+        };
+        key(self) == key(other)
+            && same(&self.plugin, &other.plugin)
+            && same(&self.book, &other.book)
+    }
+    // What the evaluator thinks of placing the current piece at `location`, for coaching.
+    fn score(&self, board: &Board, hold: bool, location: libtetris::FallingPiece) -> i32 {
+        match &self.plugin {
+            Some(plugin) => {
+                let evaluator = PluginEvaluator {
+                    standard: self.evaluator.clone(),
+                    plugin: plugin.clone(),
+                };
+                coach::score(&evaluator, board, hold, location)
+            }
+            None => coach::score(&self.evaluator, board, hold, location),
+        }
+    }
+}
+
+struct Bot {
+    interface: Engine,
+    params: LaunchParams,
+    // The game as the bridge has seen it, so the bot can be relaunched mid-game.
+    board: Board,
+    requested_at: Option<Instant>,
+    // When the time budget of the outstanding request runs out.
+    due: Option<Instant>,
+    // The incoming garbage of a request with a budget, which the bot is only asked for once the
+    // budget is up: cold clear makes its move as soon as it is asked and has searched its
+    // minimum, and keeps searching until then.
+    pending: Option<u32>,
+    // A move the bot found before the console asked for it.
+    ready: Option<(cold_clear::Move, cold_clear::Info)>,
+    // Whether `ready` came from the opening book, which the interface knows nothing about.
+    from_book: bool,
+    incoming: u32,
+    latency: LatencyWindow,
+    stats: BotStats,
+    // The placements the bot expects to make next, as of the last move it delivered.
+    plan: Vec<libtetris::FallingPiece>,
+    // Whether the plan was last seen leading to a perfect clear, so the console hears about changes.
+    pc_active: bool,
+    // The plan as fumen, starting from the board before the move that came with it.
+    fumen: Option<String>,
+    status: Arc<Mutex<BotStatus>>,
+    game: Option<Arc<Mutex<GameRecord>>>,
+    // Set when the bot is coaching a human rather than playing.
+    coach: Arc<Mutex<Option<Coach>>>,
+    // Log the board after every placement.
+    render: bool,
+    feed: Feed,
+}
+
+impl Bot {
+    fn new(
+        interface: Engine,
+        params: LaunchParams,
+        board: Board,
+        status: Arc<Mutex<BotStatus>>,
+        game: Option<Arc<Mutex<GameRecord>>>,
+        coach: Arc<Mutex<Option<Coach>>>,
+        render: bool,
+        feed: Feed,
+    ) -> Bot {
+        let bot = Bot {
+            interface,
+            params,
+            board,
+            requested_at: None,
+            due: None,
+            pending: None,
+            ready: None,
+            from_book: false,
+            incoming: 0,
+            latency: LatencyWindow::new(),
+            stats: BotStats::default(),
+            plan: vec![],
+            pc_active: false,
+            fumen: None,
+            status,
+            game,
+            coach,
+            render,
+            feed,
+        };
+        bot.publish();
+        bot
+    }
+    // Copies what the dashboard shows into the shared status.
+    fn publish(&self) {
+        let mut status = self.status.lock().unwrap();
+        status.board = self.board.clone();
+        status.last_think = self
+            .stats
+            .last_think_ms
+            .map(|ms| Duration::from_secs_f64(ms / 1000.0));
+        status.pieces_placed = self.stats.pieces_placed;
+        status.last_nodes = self.stats.last_nodes;
+        status.plan = self.plan.clone();
+        status.fumen = self.fumen.clone();
+    }
+    // Must come after publish, which is where the handle's board is brought up to date.
+    fn broadcast(&self, kind: FeedKind) {
+        let status = self.status.lock().unwrap();
+        self.feed.publish(FeedEvent {
+            handle: status.handle,
+            kind,
+            board: status.board.clone(),
+        });
+    }
+    fn record(&self, event: GameEvent) {
+        if let Some(game) = &self.game {
+            game.lock().unwrap().push(event);
+        }
+    }
+    fn add_next_piece(&mut self, piece: libtetris::Piece) {
+        self.interface.add_next_piece(piece);
+        self.board.add_next_piece(piece);
+        self.record(GameEvent::Piece { piece });
+        self.publish();
+        self.broadcast(FeedKind::Piece(piece));
+    }
+    fn set_incoming(&mut self, incoming: u32) {
+        if incoming != self.incoming {
+            self.record(GameEvent::Incoming { lines: incoming });
+        }
+        self.incoming = incoming;
+    }
+    fn reset(&mut self, field: [[bool; 10]; 40], b2b_active: bool, combo: u32) {
+        self.interface.reset(field, b2b_active, combo);
+        self.record(GameEvent::Reset {
+            field: FieldRows(field),
+            b2b_active,
+            combo,
+        });
+        self.board.set_field(field);
+        self.board.b2b_bonus = b2b_active;
+        self.board.combo = combo;
+        self.plan.clear();
+        self.fumen = None;
+        self.publish();
+        self.broadcast(FeedKind::Reset);
+    }
+    // Applies a placement to the shadow board the way the bot applies its own moves.
+    fn play(&mut self, hold: bool, location: libtetris::FallingPiece, forced: bool) {
+        let current = self.board.advance_queue();
+        if let (true, Some(current)) = (hold, current) {
+            if self.board.hold(current).is_none() {
+                self.board.advance_queue();
+            }
+        }
+        let lock = self.board.lock_piece(location);
+        self.record(GameEvent::Place {
+            hold,
+            location,
+            forced,
+            outcome: MoveOutcome::from_lock(&lock),
+        });
+        // Any other placement throws the plan off.
+        if self.plan.first() == Some(&location) {
+            self.plan.remove(0);
+        } else {
+            self.plan.clear();
+        }
+        self.stats.pieces_placed += 1;
+        self.status.lock().unwrap().last_placed = Some(location);
+        if self.render {
+            info!(
+                "Placed {:?}\n{}",
+                location.kind.0,
+                render::board(&self.board, Some(&location))
+            );
+        }
+        self.publish();
+        self.broadcast(FeedKind::Place { hold, location });
+    }
+    fn relaunch(&mut self) {
+        // A TBP bot is started over rather than replaced, which saves spawning a process.
+        if let Engine::Tbp(bot) = &mut self.interface {
+            bot.restart(self.board.clone());
+        } else {
+            match self.params.launch(self.board.clone()) {
+                Ok(interface) => self.interface = interface,
+                Err(err) => error!("Could not relaunch the bot: {}", err),
+            }
+        }
+        self.requested_at = None;
+        self.due = None;
+        self.pending = None;
+        self.ready = None;
+        self.from_book = false;
+    }
+    // Relaunches with new parameters, asking the new interface for the move the old one was
+    // working on, if any.
+    fn relaunch_with(&mut self, params: LaunchParams) {
+        let (requested_at, due, pending) = (self.requested_at, self.due, self.pending);
+        self.params = params;
+        self.relaunch();
+        if requested_at.is_some() {
+            if pending.is_none() {
+                self.interface.request_next_move(self.incoming);
+            }
+            self.requested_at = requested_at;
+            self.due = due;
+            self.pending = pending;
+        }
+    }
+    // Starts a new game on this thread, left as `Worker::spawn` would leave a new bot. A cold clear
+    // bot is given the interface launched for it, as cold clear's own keeps the queue and hold
+    // through a reset; a TBP bot is started over.
+    fn restart(
+        &mut self,
+        params: LaunchParams,
+        interface: Option<Engine>,
+        recorder: Option<&GameRecorder>,
+    ) {
+        save_game(self.game.as_ref(), &self.coach);
+        *self.coach.lock().unwrap() = None;
+        if let (Some(game), Some(recorder)) = (&self.game, recorder) {
+            let mut game = game.lock().unwrap();
+            let mut record = recorder.start(&Board::new());
+            record.handle = game.handle;
+            record.label = game.label.take();
+            record.slot = game.slot;
+            *game = record;
+        }
+        self.params = params;
+        self.board = Board::new();
+        match interface {
+            Some(interface) => self.interface = interface,
+            None => {
+                self.interface.resume();
+                self.relaunch();
+            }
+        }
+        self.requested_at = None;
+        self.due = None;
+        self.pending = None;
+        self.ready = None;
+        self.from_book = false;
+        self.incoming = 0;
+        self.latency = LatencyWindow::new();
+        self.stats = BotStats::default();
+        self.plan.clear();
+        self.pc_active = false;
+        self.fumen = None;
+        self.status.lock().unwrap().last_placed = None;
+        self.publish();
+    }
+    fn send_request(&mut self) {
+        if let Some(incoming) = self.pending.take() {
+            self.interface.request_next_move(incoming);
+        }
+    }
+    fn delivered(
+        &mut self,
+        (mv, info): (cold_clear::Move, cold_clear::Info),
+        out: &mut impl Responder,
+    ) -> MoveResult {
+        self.due = None;
+        if let Some(requested_at) = self.requested_at.take() {
+            let elapsed = requested_at.elapsed();
+            self.latency.record(elapsed);
+            self.stats.last_think_ms = Some(elapsed.as_secs_f64() * 1000.0);
+        }
+        self.stats.last_nodes = Some(info.nodes);
+        self.stats.last_depth = Some(info.depth);
+        self.stats.total_nodes += u64::from(info.nodes);
+        self.plan = info.plan.iter().map(|&(location, _)| location).collect();
+        let fumen = fumen::plan(&self.board, &self.plan);
+        debug!("Plan: {}{}", fumen::VIEWER, fumen);
+        self.fumen = Some(fumen);
+        let result = move_result(&self.board, mv, info);
+        self.play(result.mv.hold, result.mv.expected_location, false);
+        // Like a forced move, a book move is followed by relaunching from the board it left.
+        if self.from_book {
+            self.relaunch();
+        }
+        let pc = result.pc_pieces_remaining;
+        if pc.is_some() != self.pc_active {
+            self.pc_active = pc.is_some();
+            if self.pc_active {
+                info!("On course for a perfect clear in {} pieces", pc.unwrap());
+            }
+            out.notify(Notification::PcStatus {
+                handle: self.status.lock().unwrap().handle,
+                active: self.pc_active,
+                pieces_remaining: pc.unwrap_or(0),
+            });
+        }
+        result
+    }
+}
+
+// Locking a placement that is off the field panics, so ones from the console are checked first.
+fn check_placement(board: &Board, location: &libtetris::FallingPiece) -> Result<(), CommandError> {
+    let inside = location
+        .cells()
+        .iter()
+        .all(|&(x, y)| (0..10).contains(&x) && (0..40).contains(&y));
+    if !inside || board.obstructed(location) {
+        return Err(CommandError::new(
+            ErrorCode::InvalidArgument,
+            format!("{:?} doesn't fit on the board", location),
+        ));
+    }
+    Ok(())
+}
+
+// `board` is the board the move is made on.
+fn move_result(board: &Board, mv: cold_clear::Move, info: cold_clear::Info) -> MoveResult {
+    // The first step of the plan is the move itself, already simulated by the bot.
+    let outcome = info
+        .plan
+        .first()
+        .map(|(_, lock)| MoveOutcome::from_lock(lock));
+    let plan: Vec<_> = info.plan.iter().map(|&(location, _)| location).collect();
+    MoveResult {
+        pc_pieces_remaining: pc::pieces_to_pc(board, &plan),
+        mv,
+        outcome,
+        info,
+    }
+}
+
+type Run = Box<dyn FnOnce(&mut Bot) + Send>;
+
+struct Job {
+    run: Run,
+    // Answers the command with an error instead, if the bot has to be given up on.
+    abandon: Box<dyn FnOnce(CommandError) + Send>,
+    // The handle's span, for whatever is logged about the job.
+    span: Span,
+}
+
+// Every bot lives on its own thread and runs the commands for its handle in order, so a slow or
+// stuck bot only holds up its own handle. Dropping the worker ends the thread (and the bot) once
+// the command it is running returns.
+struct Worker {
+    jobs: Sender<Job>,
+    params: LaunchParams,
+    // The options as launched or set, before the governor scaled them to `params.options`.
+    requested: cold_clear::Options,
+    throttle: u8,
+    label: Option<String>,
+    last_used: Instant,
+    thinking: Arc<AtomicBool>,
+    status: Arc<Mutex<BotStatus>>,
+    game: Option<Arc<Mutex<GameRecord>>>,
+    coach: Arc<Mutex<Option<Coach>>>,
+    // Set for a bot playing a game of the A/B test.
+    ab_game: Option<AbGame>,
+    feed: Feed,
+}
+
+impl Worker {
+    fn spawn(
+        interface: Engine,
+        params: LaunchParams,
+        board: Board,
+        deadline: Option<Duration>,
+        recorder: Option<&GameRecorder>,
+        render: bool,
+        feed: Feed,
+    ) -> Worker {
+        let (jobs, receive_jobs) = channel::<Job>();
+        let thinking = Arc::new(AtomicBool::new(false));
+        let status = Arc::new(Mutex::new(BotStatus {
+            handle: 0,
+            label: None,
+            board: board.clone(),
+            last_think: None,
+            last_nodes: None,
+            pieces_placed: 0,
+            plan: vec![],
+            last_placed: None,
+            fumen: None,
+        }));
+        let game = recorder.map(|recorder| Arc::new(Mutex::new(recorder.start(&board))));
+        let coach = Arc::new(Mutex::new(None));
+        let bot = Bot::new(
+            interface,
+            params.clone(),
+            board,
+            status.clone(),
+            game.clone(),
+            coach.clone(),
+            render,
+            feed.clone(),
+        );
+        let bot_thinking = thinking.clone();
+        std::thread::spawn(move || supervise(bot, receive_jobs, deadline, bot_thinking));
+        Worker {
+            jobs,
+            requested: params.options,
+            throttle: 100,
+            params,
+            label: None,
+            last_used: Instant::now(),
+            thinking,
+            status,
+            game,
+            coach,
+            ab_game: None,
+            feed,
+        }
+    }
+    // Whether the bot has an A/B test game going that will be recorded when it goes away.
+    fn ab_played(&self) -> bool {
+        self.ab_game.is_some() && AbGame::counts(self.status.lock().unwrap().pieces_placed)
+    }
+    fn describe(&self, handle: u32) -> String {
+        match &self.label {
+            Some(label) => format!("handle {} ({})", handle, label),
+            None => format!("handle {}", handle),
+        }
+    }
+    // What a bot going away does besides saving its game record, which is up to its thread.
+    fn finish_game(&mut self) {
+        let status = self.status.lock().unwrap();
+        if let Some(ab_game) = self.ab_game.take() {
+            ab_game.finish(status.pieces_placed);
+        }
+        self.feed.publish(FeedEvent {
+            handle: status.handle,
+            kind: FeedKind::Gone,
+            board: status.board.clone(),
+        });
+    }
+}
+
+// The handle is gone, whether it was dropped, replaced or the session ended, so its game is over.
+impl Drop for Worker {
+    fn drop(&mut self) {
+        save_game(self.game.as_ref(), &self.coach);
+        self.finish_game();
+    }
+}
+
+fn save_game(game: Option<&Arc<Mutex<GameRecord>>>, coach: &Mutex<Option<Coach>>) {
+    let summary = coach.lock().unwrap().as_ref().map(Coach::summary);
+    if let Some(summary) = &summary {
+        info!(
+            "Coached {} pieces: {:.1} lost per piece, {} inaccuracies and {} mistakes",
+            summary.pieces, summary.average_loss, summary.inaccuracies, summary.mistakes
+        );
+    }
+    if let Some(game) = game {
+        let mut game = game.lock().unwrap();
+        game.coach = summary;
+        game.save();
+    }
+}
+
+// The bot runs its jobs on a thread of its own while this one watches the clock. A job that takes
+// longer than `deadline`, or panics, is answered with an error and the bot is relaunched from the
+// board as it was before that job; the stuck thread is left to finish (or not) on its own.
+fn supervise(bot: Bot, jobs: Receiver<Job>, deadline: Option<Duration>, thinking: Arc<AtomicBool>) {
+    let mut checkpoint = Checkpoint::of(&bot);
+    let status = bot.status.clone();
+    let game = bot.game.clone();
+    let coach = bot.coach.clone();
+    let render = bot.render;
+    let feed = bot.feed.clone();
+    let (mut runner, mut finished) = run_jobs(bot);
+    for Job { run, abandon, span } in jobs {
+        // If the runner is gone, the send fails and so does the wait below.
+        runner.send(run).ok();
+        let result = match deadline {
+            Some(deadline) => finished.recv_timeout(deadline),
+            None => finished.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match result {
+            Ok(after) => {
+                thinking.store(after.thinking, Ordering::Relaxed);
+                checkpoint = after;
+            }
+            Err(err) => {
+                let reason = match err {
+                    RecvTimeoutError::Timeout => "stopped responding",
+                    RecvTimeoutError::Disconnected => "crashed",
+                };
+                let _enter = span.enter();
+                warn!("A bot {}, relaunching it", reason);
+                abandon(CommandError::new(
+                    ErrorCode::BotRelaunched,
+                    format!("the bot {} and was relaunched", reason),
+                ));
+                let Checkpoint { board, params, .. } = &checkpoint;
+                let interface = match params.launch(board.clone()) {
+                    Ok(interface) => interface,
+                    Err(err) => {
+                        error!("Could not relaunch the bot: {}", err);
+                        break;
+                    }
+                };
+                let bot = Bot::new(
+                    interface,
+                    params.clone(),
+                    board.clone(),
+                    status.clone(),
+                    game.clone(),
+                    coach.clone(),
+                    render,
+                    feed.clone(),
+                );
+                bot.broadcast(FeedKind::Start);
+                let (new_runner, new_finished) = run_jobs(bot);
+                runner = new_runner;
+                finished = new_finished;
+                thinking.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+// What a bot is relaunched from if its next job doesn't finish.
+struct Checkpoint {
+    board: Board,
+    params: LaunchParams,
+    thinking: bool,
+}
+
+impl Checkpoint {
+    fn of(bot: &Bot) -> Checkpoint {
+        Checkpoint {
+            board: bot.board.clone(),
+            params: bot.params.clone(),
+            thinking: bot.requested_at.is_some(),
+        }
+    }
+}
+
+fn run_jobs(mut bot: Bot) -> (Sender<Run>, Receiver<Checkpoint>) {
+    let (runner, runs) = channel::<Run>();
+    let (send_finished, finished) = channel();
+    std::thread::spawn(move || {
+        for run in runs {
+            run(&mut bot);
+            if send_finished.send(Checkpoint::of(&bot)).is_err() {
+                break;
+            }
+        }
+    });
+    (runner, finished)
+}
+
+// Suggest and SolvePerfectClear run on threads of their own. While one runs it counts towards
+// `max_bots` and its threads towards the thread budget, like a bot would.
+#[derive(Default)]
+struct OneOffs {
+    running: usize,
+    threads: u32,
+}
+
+// Gives back what a one-off took once its thread is done with it, even if it panicked.
+struct OneOffGuard {
+    one_offs: Arc<Mutex<OneOffs>>,
+    threads: u32,
+}
+
+impl Drop for OneOffGuard {
+    fn drop(&mut self) {
+        let mut one_offs = self.one_offs.lock().unwrap();
+        one_offs.running -= 1;
+        one_offs.threads -= self.threads;
+    }
+}
+
+// Probes go out one at a time; the next is sent once the console has echoed the last.
+struct Calibration {
+    samples: u32,
+    seq: u32,
+    sent_at: Instant,
+    round_trips: LatencyWindow,
+    notify: Box<dyn FnMut(Notification) + Send>,
+    finish: Box<dyn FnOnce(LatencyStats) + Send>,
+}
+
+pub struct Bots {
+    handle_counter: u32,
+    handles: HashMap<u32, Worker>,
+    slots: HashMap<u8, u32>,
+    policy: ThreadPolicy,
+    pool: Option<WarmPool>,
+    max_bots: Option<usize>,
+    evict_idle: bool,
+    bot_threads: Option<u32>,
+    max_threads_per_bot: Option<u32>,
+    bot_deadline: Reloadable<Option<Duration>>,
+    capabilities: Vec<&'static str>,
+    presets: Reloadable<Presets>,
+    plugins: Arc<Plugins>,
+    books: Arc<Books>,
+    default_options: cold_clear::Options,
+    default_evaluator: cold_clear::evaluation::Standard,
+    monitor: Monitor,
+    recorder: Option<GameRecorder>,
+    render: bool,
+    backend: Backend,
+    // Round trips to the console and back, as the console reports them.
+    round_trip: LatencyWindow,
+    // Set for each connection the session runs on.
+    usb: Option<UsbStats>,
+    // When the frame holding the command being executed arrived.
+    received_at: Instant,
+    calibration: Option<Calibration>,
+    calibrated: Option<(u32, LatencyStats)>,
+    limiter: RateLimiter,
+    reset_when_flooding: bool,
+    one_offs: Arc<Mutex<OneOffs>>,
+    governor: Option<Governor>,
+    // The governor's level the bots were last scaled to.
+    throttle: u8,
+    // Set when the session is gone, so perfect clear searches nobody will hear back from stop.
+    cancel_one_offs: Arc<AtomicBool>,
+    ab_test: Option<AbTest>,
+}
+
+// Suggestions end on their own once the bot has moved, but a perfect clear search can run long.
+impl Drop for Bots {
+    fn drop(&mut self) {
+        self.cancel_one_offs.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Bots {
+    const MAX_ONE_OFFS: usize = 4;
+
+    pub fn new(config: &SessionConfig) -> Bots {
+        Bots {
+            handle_counter: 0,
+            handles: HashMap::new(),
+            slots: HashMap::new(),
+            policy: config.bot_policy.clone(),
+            // Only cold clear bots can be launched ahead of time.
+            pool: if config.warm_pool > 0 && matches!(config.backend, Backend::ColdClear) {
+                Some(WarmPool::new(
+                    config.warm_pool,
+                    config.bot_policy.clone(),
+                    config.default_options,
+                    config.default_evaluator.clone(),
+                ))
+            } else {
+                None
+            },
+            max_bots: config.max_bots,
+            evict_idle: config.evict_idle,
+            bot_threads: config.bot_threads,
+            max_threads_per_bot: config.max_threads_per_bot,
+            bot_deadline: config.bot_deadline.clone(),
+            capabilities: config.capabilities(),
+            presets: config.presets.clone(),
+            plugins: config.plugins.clone(),
+            books: config.books.clone(),
+            default_options: config.default_options,
+            default_evaluator: config.default_evaluator.clone(),
+            monitor: config.monitor.clone(),
+            recorder: config.recorder.clone(),
+            render: config.render,
+            backend: config.backend.clone(),
+            round_trip: LatencyWindow::new(),
+            usb: None,
+            received_at: Instant::now(),
+            calibration: None,
+            calibrated: None,
+            limiter: RateLimiter::new(config.rate_limit.per_second, config.rate_limit.burst),
+            reset_when_flooding: config.rate_limit.reset_when_flooding,
+            one_offs: Arc::new(Mutex::new(OneOffs::default())),
+            governor: config.governor.clone(),
+            throttle: 100,
+            cancel_one_offs: Arc::new(AtomicBool::new(false)),
+            ab_test: config.ab_test.clone(),
+        }
+    }
+    // Commands for a bot are answered from its worker thread, possibly after this returns.
+    pub fn execute<R>(&mut self, command: Command, out: &mut R)
+    where
+        R: Responder + Clone + Send + 'static,
+    {
+        if let Some(percent) = self.governor.as_ref().map(Governor::percent) {
+            if percent != self.throttle {
+                self.throttle(percent);
+            }
+        }
+        // Bots and one-offs that have gone since the last command leave the pool more room.
+        self.fit_pool(None);
+        match command {
+            Command::Batch { commands } => {
+                let batch = BatchResponder::new(commands.len(), out.clone());
+                for (index, command) in commands.into_iter().enumerate() {
+                    let mut out = batch.at(index);
+                    if let Err(err) = self.try_execute(command, &mut out) {
+                        out.err(err);
+                    }
+                }
+            }
+            command => {
+                if let Err(err) = self.try_execute(command, out) {
+                    out.err(err);
+                }
+            }
+        }
+    }
+    // Lets go of everything tied to the connection the session was on. A calibration answers
+    // through the connection's outbox, which over USB keeps the device claimed, so one left behind
+    // in a parked session would stop the console from reconnecting to resume it.
+    pub fn detach(&mut self) {
+        self.calibration = None;
+        self.usb = None;
+    }
+    // Waits until every bot has finished the commands it has been given so far.
+    pub fn wait_idle(&self) {
+        let (done, finished) = channel();
+        for worker in self.handles.values() {
+            let done = done.clone();
+            let job = Job {
+                run: Box::new(move |_| {
+                    done.send(()).ok();
+                }),
+                abandon: Box::new(|_| {}),
+                span: Span::none(),
+            };
+            worker.jobs.send(job).ok();
+        }
+        drop(done);
+        for () in finished {}
+    }
+    // `replacing` is the handle the bot will take over, whose threads are about to be freed.
+    fn launch(
+        &mut self,
+        options: cold_clear::Options,
+        evaluator: cold_clear::evaluation::Standard,
+        plugin: Option<Arc<Plugin>>,
+        book: Option<Arc<Book>>,
+        board: Option<&BoardState>,
+        replacing: Option<u32>,
+    ) -> Result<Worker, CommandError> {
+        let (requested, params) = self.params(options, evaluator, plugin, book, replacing)?;
+        let interface = self.interface(&params, board)?;
+        let board = board.map_or_else(Board::new, BoardState::to_board);
+        Ok(self.spawn(requested, params, interface, board))
+    }
+    fn spawn(
+        &self,
+        requested: cold_clear::Options,
+        params: LaunchParams,
+        interface: Engine,
+        board: Board,
+    ) -> Worker {
+        let mut worker = Worker::spawn(
+            interface,
+            params,
+            board,
+            *self.bot_deadline.get(),
+            self.recorder.as_ref(),
+            self.render,
+            self.monitor.feed(),
+        );
+        worker.requested = requested;
+        worker.throttle = self.throttle;
+        worker
+    }
+    // The parameters a bot is launched with, and the options it asked for before it was given
+    // its share of the threads and scaled by the governor.
+    fn params(
+        &self,
+        mut options: cold_clear::Options,
+        evaluator: cold_clear::evaluation::Standard,
+        plugin: Option<Arc<Plugin>>,
+        book: Option<Arc<Book>>,
+        replacing: Option<u32>,
+    ) -> Result<(cold_clear::Options, LaunchParams), CommandError> {
+        options.threads = self.threads(options.threads, replacing)?;
+        let requested = options;
+        let params = LaunchParams {
+            options: self.scale(options),
+            evaluator,
+            policy: self.policy.clone(),
+            backend: self.backend.clone(),
+            plugin,
+            book,
+        };
+        Ok((requested, params))
+    }
+    fn interface(
+        &mut self,
+        params: &LaunchParams,
+        board: Option<&BoardState>,
+    ) -> Result<Engine, CommandError> {
+        // Pooled interfaces were launched on an empty board, without a plugin.
+        let pooled = match (&mut self.pool, board, &params.plugin) {
+            (Some(pool), None, None) => pool.take(&params.options, &params.evaluator),
+            _ => None,
+        };
+        // Counting the new bot, so the pool doesn't fill back up past what it leaves.
+        self.fit_pool(Some(params.options.threads));
+        match pooled {
+            Some(interface) => Ok(interface.into()),
+            None => params
+                .launch(board.map_or_else(Board::new, BoardState::to_board))
+                .map_err(CommandError::launch_failed),
+        }
+    }
+    // Warm interfaces count towards `max_bots` and the thread budget like bots do, but only get
+    // what the bots and one-offs leave. `launching` is the threads of a bot being launched that
+    // isn't in `handles` yet.
+    fn fit_pool(&self, launching: Option<u32>) {
+        let pool = match &self.pool {
+            Some(pool) => pool,
+            None => return,
+        };
+        let one_offs = self.one_offs.lock().unwrap();
+        let bots = self.handles.len() + one_offs.running + usize::from(launching.is_some());
+        let mut room = self
+            .max_bots
+            .map_or(usize::MAX, |max_bots| max_bots.saturating_sub(bots));
+        if let Some(total) = self.bot_threads {
+            let used = self
+                .handles
+                .values()
+                .map(|worker| worker.params.options.threads)
+                .sum::<u32>()
+                + one_offs.threads
+                + launching.unwrap_or(0);
+            room = room.min((total.saturating_sub(used) / pool.threads().max(1)) as usize);
+        }
+        drop(one_offs);
+        pool.fit(room);
+    }
+    // TBP bots search however they like, so only cold clear bots are turned down.
+    fn scale(&self, options: cold_clear::Options) -> cold_clear::Options {
+        match self.backend {
+            Backend::ColdClear if self.throttle < 100 => governor::scale(options, self.throttle),
+            _ => options,
+        }
+    }
+    // Relaunches every bot with its options scaled to the governor's new level. Going back up, a
+    // bot only gets the threads that are left, as it would at Launch.
+    fn throttle(&mut self, percent: u8) {
+        self.throttle = percent;
+        let mut handles: Vec<u32> = self.handles.keys().copied().collect();
+        handles.sort();
+        for handle in handles {
+            let requested = self.handles[&handle].requested;
+            let mut options = self.scale(requested);
+            options.threads = match self.threads(requested.threads, Some(handle)) {
+                Ok(threads) => options.threads.min(threads),
+                Err(_) => continue,
+            };
+            let worker = self.handles.get_mut(&handle).unwrap();
+            worker.throttle = percent;
+            let current = worker.params.options;
+            if (current.min_nodes, current.max_nodes, current.threads)
+                == (options.min_nodes, options.max_nodes, options.threads)
+            {
+                continue;
+            }
+            worker.params.options = options;
+            let params = worker.params.clone();
+            let job = Job {
+                run: Box::new(move |bot| bot.relaunch_with(params)),
+                abandon: Box::new(|_| {}),
+                span: info_span!("bot", handle),
+            };
+            worker.jobs.send(job).ok();
+        }
+    }
+    // Caps a bot's search threads to the per-bot limit and to what is left of the total. TBP bots
+    // run their own threads, which the bridge can't limit.
+    fn threads(&self, requested: u32, replacing: Option<u32>) -> Result<u32, CommandError> {
+        if !matches!(self.backend, Backend::ColdClear) {
+            return Ok(requested);
+        }
+        let mut threads = requested;
+        if let Some(cap) = self.max_threads_per_bot {
+            threads = threads.min(cap);
+        }
+        if let Some(total) = self.bot_threads {
+            let used: u32 = self
+                .handles
+                .iter()
+                .filter(|&(&handle, _)| Some(handle) != replacing)
+                .map(|(_, worker)| worker.params.options.threads)
+                .sum::<u32>()
+                + self.one_offs.lock().unwrap().threads;
+            let left = total.saturating_sub(used);
+            if left == 0 {
+                return Err(CommandError::new(
+                    ErrorCode::TooManyBots,
+                    format!("all {} bot threads are in use", total),
+                ));
+            }
+            threads = threads.min(left);
+        }
+        if threads != requested {
+            info!(
+                "Giving the bot {} search threads instead of {}",
+                threads, requested
+            );
+        }
+        Ok(threads)
+    }
+    fn insert(&mut self, handle: u32, worker: Worker) {
+        {
+            let mut status = worker.status.lock().unwrap();
+            status.handle = handle;
+            status.label = worker.label.clone();
+        }
+        if let Some(game) = &worker.game {
+            let mut game = game.lock().unwrap();
+            game.handle = handle;
+            game.label = worker.label.clone();
+            game.slot = self.slot_of(handle);
+        }
+        self.monitor.add_bot(&worker.status);
+        let status = worker.status.clone();
+        // Whatever had the handle before is gone by the time the new bot is announced.
+        self.handles.insert(handle, worker);
+        let board = status.lock().unwrap().board.clone();
+        self.monitor.feed().publish(FeedEvent {
+            handle,
+            kind: FeedKind::Start,
+            board,
+        });
+    }
+    fn evaluator(
+        &self,
+        choice: Option<EvaluatorChoice>,
+    ) -> Result<(cold_clear::evaluation::Standard, Option<Arc<Plugin>>), CommandError> {
+        match choice {
+            None => Ok((self.default_evaluator.clone(), None)),
+            Some(EvaluatorChoice::Standard { weights }) => Ok((weights, None)),
+            Some(EvaluatorChoice::Preset { name }) => Ok((self.presets.get().get(&name)?, None)),
+            Some(EvaluatorChoice::Plugin { .. }) if !matches!(self.backend, Backend::ColdClear) => {
+                Err(CommandError::new(
+                    ErrorCode::InvalidArgument,
+                    "evaluator plugins only work with cold clear",
+                ))
+            }
+            Some(EvaluatorChoice::Plugin { name, base }) => {
+                let base = base.unwrap_or_else(|| self.default_evaluator.clone());
+                Ok((base, Some(self.plugins.get(&name)?)))
+            }
+        }
+    }
+    // A bot launched into the A/B test's slot, or reset there, starts a game with the profile
+    // whose turn it is, and plays with that profile's evaluator whatever the console asked for.
+    fn ab_game(
+        &self,
+        slot: Option<u8>,
+        replacing: Option<u32>,
+    ) -> Result<Option<(AbGame, cold_clear::evaluation::Standard)>, CommandError> {
+        let ab_test = match &self.ab_test {
+            Some(ab_test) if slot == Some(ab_test.slot) => ab_test,
+            _ => return Ok(None),
+        };
+        let replacing = replacing
+            .and_then(|handle| self.handles.get(&handle))
+            .map_or(false, Worker::ab_played);
+        let game = ab_test.start(replacing);
+        let evaluator = self.presets.get().get(&game.profile)?;
+        Ok(Some((game, evaluator)))
+    }
+    fn slot_of(&self, handle: u32) -> Option<u8> {
+        self.slots
+            .iter()
+            .find(|&(_, &occupant)| occupant == handle)
+            .map(|(&slot, _)| slot)
+    }
+    // Makes room for one more bot, evicting the least recently used one that isn't thinking if
+    // that is allowed. Returns the evicted handle.
+    fn make_room(&mut self) -> Result<Option<u32>, CommandError> {
+        let running = self.one_offs.lock().unwrap().running;
+        let max_bots = match self.max_bots {
+            Some(max_bots) if self.handles.len() + running >= max_bots => max_bots,
+            _ => return Ok(None),
+        };
+        let idle = self
+            .handles
+            .iter()
+            .filter(|(_, worker)| !worker.thinking.load(Ordering::Relaxed))
+            .min_by_key(|(_, worker)| worker.last_used)
+            .map(|(&handle, _)| handle);
+        match idle {
+            Some(handle) if self.evict_idle => {
+                let worker = self.handles.remove(&handle).unwrap();
+                self.slots.retain(|_, &mut occupant| occupant != handle);
+                info!(
+                    "Evicted {} to make room for a new bot",
+                    worker.describe(handle)
+                );
+                Ok(Some(handle))
+            }
+            _ => Err(CommandError::new(
+                ErrorCode::TooManyBots,
+                format!("the bridge is limited to {} bots at a time", max_bots),
+            )),
+        }
+    }
+    // A calibration that has gone this long without an echo is given up on by a new one.
+    const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+    const MAX_CALIBRATION_SAMPLES: u32 = 1000;
+
+    fn calibrate(
+        &mut self,
+        samples: u32,
+        mut notify: Box<dyn FnMut(Notification) + Send>,
+        finish: Box<dyn FnOnce(LatencyStats) + Send>,
+    ) -> Result<(), CommandError> {
+        if let Some(calibration) = &self.calibration {
+            if calibration.sent_at.elapsed() < Bots::PROBE_TIMEOUT {
+                return Err(CommandError::new(
+                    ErrorCode::Busy,
+                    "a calibration is already running",
+                ));
+            }
+        }
+        let samples = samples.max(1).min(Bots::MAX_CALIBRATION_SAMPLES);
+        notify(Notification::CalibrationProbe { seq: 0 });
+        self.calibration = Some(Calibration {
+            samples,
+            seq: 0,
+            sent_at: Instant::now(),
+            round_trips: LatencyWindow::with_capacity(samples as usize),
+            notify,
+            finish,
+        });
+        Ok(())
+    }
+    fn spawn_one_off(
+        &self,
+        threads: u32,
+        run: impl FnOnce() + Send + 'static,
+    ) -> Result<(), CommandError> {
+        let mut one_offs = self.one_offs.lock().unwrap();
+        if one_offs.running >= Bots::MAX_ONE_OFFS {
+            return Err(CommandError::new(
+                ErrorCode::Busy,
+                format!(
+                    "{} suggestions or solves are already running",
+                    one_offs.running
+                ),
+            ));
+        }
+        if let Some(max_bots) = self.max_bots {
+            if self.handles.len() + one_offs.running >= max_bots {
+                return Err(CommandError::new(
+                    ErrorCode::TooManyBots,
+                    format!("the bridge is limited to {} bots at a time", max_bots),
+                ));
+            }
+        }
+        one_offs.running += 1;
+        one_offs.threads += threads;
+        drop(one_offs);
+        self.fit_pool(None);
+        let guard = OneOffGuard {
+            one_offs: self.one_offs.clone(),
+            threads,
+        };
+        std::thread::spawn(move || {
+            let _guard = guard;
+            run();
+        });
+        Ok(())
+    }
+    fn on_bot<R>(
+        &mut self,
+        handle: u32,
+        out: &R,
+        job: impl FnOnce(&mut Bot, &mut R) + Send + 'static,
+    ) -> Result<(), CommandError>
+    where
+        R: Responder + Clone + Send + 'static,
+    {
+        let worker = self
+            .handles
+            .get_mut(&handle)
+            .ok_or_else(|| CommandError::invalid_handle(handle))?;
+        worker.last_used = Instant::now();
+        let mut out = out.clone();
+        let mut abandoned = out.clone();
+        let span = info_span!("bot", handle, label = tracing::field::Empty);
+        if let Some(label) = &worker.label {
+            span.record("label", &label.as_str());
+        }
+        let run_span = span.clone();
+        worker
+            .jobs
+            .send(Job {
+                run: Box::new(move |bot| {
+                    let _enter = run_span.enter();
+                    job(bot, &mut out)
+                }),
+                abandon: Box::new(move |err| abandoned.err(err)),
+                span,
+            })
+            .map_err(|_| {
+                CommandError::new(
+                    ErrorCode::InvalidHandle,
+                    format!("the bot with {} has crashed", worker.describe(handle)),
+                )
+            })
+    }
+    fn try_execute<R>(&mut self, command: Command, out: &mut R) -> Result<(), CommandError>
+    where
+        R: Responder + Clone + Send + 'static,
+    {
+        match command {
+            Command::Launch {
+                options,
+                evaluator,
+                preset,
+                slot,
+                board,
+                label,
+                plugin,
+                coach,
+                book,
+            } => {
+                if coach && !matches!(self.backend, Backend::ColdClear) {
+                    return Err(CommandError::new(
+                        ErrorCode::InvalidArgument,
+                        "only cold clear bots can coach",
+                    ));
+                }
+                let choice = match (evaluator, preset, plugin) {
+                    (Some(evaluator), _, _) => Some(evaluator.into_choice()),
+                    (None, Some(name), None) => Some(EvaluatorChoice::Preset { name }),
+                    (None, preset, Some(name)) => Some(EvaluatorChoice::Plugin {
+                        name,
+                        base: preset
+                            .map(|name| self.presets.get().get(&name))
+                            .transpose()?,
+                    }),
+                    (None, None, None) => None,
+                };
+                let (mut evaluator, mut plugin) = self.evaluator(choice)?;
+                let book = book.map(|name| self.books.get(&name)).transpose()?;
+                // A bot launched into an occupied slot takes the place of the one there, so it
+                // needs no room of its own.
+                let occupant = slot.and_then(|slot| self.slots.get(&slot).copied());
+                let ab_game = match self.ab_game(slot, occupant)? {
+                    Some((game, profile)) => {
+                        evaluator = profile;
+                        plugin = None;
+                        Some(game)
+                    }
+                    None => None,
+                };
+                let evicted = match occupant {
+                    Some(_) => None,
+                    None => self.make_room()?,
+                };
+                let mut worker =
+                    self.launch(options, evaluator, plugin, book, board.as_ref(), occupant)?;
+                worker.label = label;
+                worker.ab_game = ab_game;
+                if coach {
+                    *worker.coach.lock().unwrap() = Some(Coach::default());
+                }
+                self.handle_counter = self.handle_counter.wrapping_add(1);
+                let name = worker.describe(self.handle_counter);
+                let labelled = worker.label.is_some();
+                // The slot is taken first, so the bot's game record is made out to it.
+                if let Some(slot) = slot {
+                    if let Some(previous) = self.slots.insert(slot, self.handle_counter) {
+                        self.handles.remove(&previous);
+                        info!("Slot {}: handle {} replaced by {}", slot, previous, name);
+                    } else {
+                        info!("Slot {}: launched {}", slot, name);
+                    }
+                } else if labelled {
+                    info!("Launched {}", name);
+                }
+                self.insert(self.handle_counter, worker);
+                // Told before the launch is answered, so the console never sees both handles live.
+                if let Some(handle) = evicted {
+                    out.notify(Notification::HandleDropped {
+                        handle,
+                        replaced_by: None,
+                    });
+                }
+                if let Some(handle) = occupant {
+                    out.notify(Notification::HandleDropped {
+                        handle,
+                        replaced_by: Some(self.handle_counter),
+                    });
+                }
+                out.ok(self.handle_counter);
+            }
+            Command::Drop { handle } => {
+                self.handles
+                    .remove(&handle)
+                    .ok_or_else(|| CommandError::invalid_handle(handle))?;
+                self.slots.retain(|_, &mut occupant| occupant != handle);
+                out.ok(());
+            }
+            Command::RequestNextMove {
+                handle,
+                incoming,
+                budget_ms,
+            } => {
+                // The budget runs on the console's clock, which also counts the trip both ways.
+                let round_trip = self.round_trip.median().unwrap_or_default();
+                let budget = budget_ms
+                    .map(|ms| Duration::from_millis(u64::from(ms)).saturating_sub(round_trip));
+                self.on_bot(handle, out, move |bot, out| {
+                    let now = Instant::now();
+                    bot.requested_at = Some(now);
+                    let mode = bot.params.options.mode;
+                    let booked = match &bot.params.book {
+                        Some(book) => book.lookup(&bot.board, mode),
+                        None => None,
+                    };
+                    if let Some(found) = booked {
+                        // Book moves are ready at once, whatever the budget.
+                        bot.ready = Some(found);
+                        bot.from_book = true;
+                    } else {
+                        bot.due = budget.map(|budget| now + budget);
+                        match budget {
+                            Some(_) => bot.pending = Some(incoming),
+                            None => bot.interface.request_next_move(incoming),
+                        }
+                    }
+                    bot.set_incoming(incoming);
+                    out.ok(());
+                })?;
+            }
+            Command::PollNextMove { handle } => {
+                self.on_bot(handle, out, |bot, out| {
+                    let result = match bot.due {
+                        Some(due) if Instant::now() < due => Err(cold_clear::BotPollState::Waiting),
+                        _ => match bot.ready.take() {
+                            Some(found) => Ok(found),
+                            None => {
+                                bot.send_request();
+                                bot.interface.poll_next_move()
+                            }
+                        },
+                    };
+                    let result = result.map(|found| bot.delivered(found, out));
+                    out.ok(result);
+                })?;
+            }
+            Command::BlockNextMove { handle } => {
+                self.on_bot(handle, out, |bot, out| {
+                    if let Some(due) = bot.due {
+                        let now = Instant::now();
+                        if due > now {
+                            std::thread::sleep(due - now);
+                        }
+                    }
+                    let result = match bot.ready.take() {
+                        Some(found) => Some(found),
+                        None => {
+                            bot.send_request();
+                            bot.interface.block_next_move()
+                        }
+                    };
+                    let result = result.map(|found| bot.delivered(found, out));
+                    out.ok(result);
+                })?;
+            }
+            Command::Reset {
+                handle,
+                field,
+                b2b_active,
+                combo,
+            } => {
+                self.on_bot(handle, out, move |bot, out| {
+                    bot.reset(field, b2b_active, combo);
+                    out.ok(());
+                })?;
+            }
+            Command::AddNextPiece { handle, piece } => {
+                self.on_bot(handle, out, move |bot, out| {
+                    bot.add_next_piece(piece);
+                    out.ok(());
+                })?;
+            }
+            Command::DefaultOptions => {
+                out.ok(self.default_options);
+            }
+            Command::DefaultEvaluator => {
+                out.ok(&self.default_evaluator);
+            }
+            Command::QuerySlot { slot } => {
+                out.ok(self.slots.get(&slot));
+            }
+            Command::GenerateGarbageBoard { rows, rules, seed } => {
+                out.ok(FieldRows(garbage::generate_board(rows, &rules, seed)));
+            }
+            Command::QueryLatency { handle } => {
+                let round_trip = self.round_trip.median();
+                self.on_bot(handle, out, move |bot, out| {
+                    let mut stats = bot.latency.stats();
+                    stats.usb_rtt_ms = round_trip.map(|rtt| rtt.as_secs_f64() * 1000.0);
+                    out.ok(stats)
+                })?;
+            }
+            Command::EchoTimestamp {
+                client_time,
+                last_round_trip_us,
+            } => {
+                if let Some(us) = last_round_trip_us {
+                    self.round_trip.record(Duration::from_micros(us));
+                }
+                let bridge_time_us = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since| since.as_micros() as u64);
+                out.ok(Echo {
+                    client_time,
+                    bridge_time_us,
+                    round_trip: self.round_trip.stats(),
+                });
+            }
+            Command::Calibrate { samples } => {
+                let (mut notify, mut finish) = (out.clone(), out.clone());
+                self.calibrate(
+                    samples,
+                    Box::new(move |notification| notify.notify(notification)),
+                    Box::new(move |stats| finish.ok(stats)),
+                )?;
+            }
+            Command::CalibrationEcho { seq, console_us } => {
+                // Timed from when the probe was written to when its echo was read, so the
+                // bridge's own decoding and encoding aren't counted.
+                let calibration = match &mut self.calibration {
+                    Some(calibration) if calibration.seq == seq => calibration,
+                    _ => {
+                        return Err(CommandError::new(
+                            ErrorCode::InvalidArgument,
+                            format!("calibration probe {} isn't waiting for an echo", seq),
+                        ))
+                    }
+                };
+                let round_trip = self
+                    .received_at
+                    .saturating_duration_since(calibration.sent_at)
+                    .saturating_sub(Duration::from_micros(console_us.unwrap_or(0)));
+                calibration.round_trips.record(round_trip);
+                self.round_trip.record(round_trip);
+                out.ok(());
+                if calibration.seq + 1 < calibration.samples {
+                    calibration.seq += 1;
+                    (calibration.notify)(Notification::CalibrationProbe {
+                        seq: calibration.seq,
+                    });
+                    calibration.sent_at = Instant::now();
+                } else if let Some(calibration) = self.calibration.take() {
+                    let stats = calibration.round_trips.stats();
+                    info!(
+                        "Calibrated over {} round trips: {:.2} ms at least, {:.2} ms median, {:.2} ms P95",
+                        stats.samples, stats.min_ms, stats.median_ms, stats.p95_ms
+                    );
+                    self.calibrated = Some((calibration.samples, stats.clone()));
+                    (calibration.finish)(stats);
+                }
+            }
+            Command::Ping { client_time } => {
+                let bridge_time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |since| since.as_millis() as u64);
+                out.ok(Pong {
+                    client_time,
+                    bridge_time,
+                });
+            }
+            Command::ResetBot {
+                handle,
+                options,
+                evaluator,
+            } => {
+                let previous = self
+                    .handles
+                    .get(&handle)
+                    .ok_or_else(|| CommandError::invalid_handle(handle))?;
+                let options = options.unwrap_or(previous.requested);
+                let mut evaluator = evaluator.unwrap_or_else(|| previous.params.evaluator.clone());
+                let (mut plugin, book) =
+                    (previous.params.plugin.clone(), previous.params.book.clone());
+                let label = previous.label.clone();
+                let ab_game = match self.ab_game(self.slot_of(handle), Some(handle))? {
+                    Some((game, profile)) => {
+                        evaluator = profile;
+                        plugin = None;
+                        Some(game)
+                    }
+                    None => None,
+                };
+                let (requested, params) =
+                    self.params(options, evaluator, plugin, book, Some(handle))?;
+                if self.handles[&handle].params.same_as(&params) {
+                    // The same bot again keeps its threads, and a TBP bot its program.
+                    let interface = match self.backend {
+                        Backend::ColdClear => Some(self.interface(&params, None)?),
+                        Backend::Tbp(_) => None,
+                    };
+                    let (recorder, throttle) = (self.recorder.clone(), self.throttle);
+                    let worker = self.handles.get_mut(&handle).unwrap();
+                    worker.finish_game();
+                    worker.requested = requested;
+                    worker.throttle = throttle;
+                    worker.params = params.clone();
+                    worker.ab_game = ab_game;
+                    self.on_bot(handle, out, move |bot, out| {
+                        bot.restart(params, interface, recorder.as_ref());
+                        bot.broadcast(FeedKind::Start);
+                        out.ok(BotReset { reused: true });
+                    })?;
+                } else {
+                    let interface = self.interface(&params, None)?;
+                    let mut worker = self.spawn(requested, params, interface, Board::new());
+                    worker.label = label;
+                    worker.ab_game = ab_game;
+                    self.insert(handle, worker);
+                    out.ok(BotReset { reused: false });
+                }
+            }
+            Command::RecoverMisdrop {
+                handle,
+                field,
+                b2b_active,
+                combo,
+            } => {
+                self.on_bot(handle, out, move |bot, out| {
+                    bot.reset(field, b2b_active, combo);
+                    // The bot may already have made its move for the wrong field, and asking again
+                    // would only add a second one, so the outstanding request goes away with the
+                    // relaunched bot and is made again from scratch. The search so far doesn't
+                    // count towards its time either.
+                    bot.relaunch_with(bot.params.clone());
+                    if bot.requested_at.is_some() {
+                        bot.requested_at = Some(Instant::now());
+                    }
+                    out.ok(());
+                })?;
+            }
+            Command::UpdateIncoming { handle, incoming } => {
+                self.on_bot(handle, out, move |bot, out| {
+                    // Requesting again before the bot has committed to a move only replaces its
+                    // incoming garbage and the search carries on. Once the move has been made,
+                    // another request would have it make a second one, so the move is kept for
+                    // the console's next poll and goes out with the garbage it was asked for.
+                    let revisable = if bot.pending.is_some() {
+                        bot.pending = Some(incoming);
+                        true
+                    } else if bot.requested_at.is_some() && bot.ready.is_none() {
+                        match bot.interface.poll_next_move() {
+                            Ok(found) => {
+                                bot.ready = Some(found);
+                                false
+                            }
+                            Err(cold_clear::BotPollState::Waiting) => {
+                                bot.interface.request_next_move(incoming);
+                                true
+                            }
+                            Err(cold_clear::BotPollState::Dead) => false,
+                        }
+                    } else {
+                        false
+                    };
+                    if revisable {
+                        bot.set_incoming(incoming);
+                    }
+                    out.ok(revisable);
+                })?;
+            }
+            Command::ForceMove {
+                handle,
+                hold,
+                location,
+            } => {
+                // The interface can only follow its own moves, so it is relaunched from the board
+                // with the placement applied.
+                self.on_bot(handle, out, move |bot, out| {
+                    if let Err(err) = check_placement(&bot.board, &location) {
+                        return out.err(err);
+                    }
+                    bot.play(hold, location, true);
+                    bot.relaunch();
+                    out.ok(());
+                })?;
+            }
+            Command::ReportPlacement {
+                handle,
+                hold,
+                location,
+            } => {
+                let worker = self
+                    .handles
+                    .get(&handle)
+                    .ok_or_else(|| CommandError::invalid_handle(handle))?;
+                if worker.coach.lock().unwrap().is_none() {
+                    return Err(CommandError::new(
+                        ErrorCode::InvalidArgument,
+                        format!("handle {} isn't coaching", handle),
+                    ));
+                }
+                self.on_bot(handle, out, move |bot, out| {
+                    if let Err(err) = check_placement(&bot.board, &location) {
+                        return out.err(err);
+                    }
+                    // The bot's choice is the move it would have made had it been asked now.
+                    let best = match bot.ready.take() {
+                        Some(found) => Some(found),
+                        None => {
+                            if bot.requested_at.is_none() {
+                                bot.pending = Some(bot.incoming);
+                            }
+                            bot.send_request();
+                            bot.interface.block_next_move()
+                        }
+                    };
+                    let played = bot.params.score(&bot.board, hold, location);
+                    let best = best.map(|(mv, _)| {
+                        let score = bot.params.score(&bot.board, mv.hold, mv.expected_location);
+                        (mv, score)
+                    });
+                    let verdict = {
+                        let mut coach = bot.coach.lock().unwrap();
+                        let coach = coach.get_or_insert_with(Coach::default);
+                        coach.review(&bot.board, hold, location, played, best)
+                    };
+                    // Like ForceMove, the bot follows the human by being relaunched from there.
+                    bot.play(hold, location, true);
+                    bot.relaunch();
+                    out.ok(verdict);
+                })?;
+            }
+            Command::CoachSummary { handle } => {
+                let worker = self
+                    .handles
+                    .get(&handle)
+                    .ok_or_else(|| CommandError::invalid_handle(handle))?;
+                let summary = worker.coach.lock().unwrap().as_ref().map(Coach::summary);
+                match summary {
+                    Some(summary) => out.ok(summary),
+                    None => {
+                        return Err(CommandError::new(
+                            ErrorCode::InvalidArgument,
+                            format!("handle {} isn't coaching", handle),
+                        ))
+                    }
+                }
+            }
+            Command::ReportResult { handle, won } => {
+                let worker = self
+                    .handles
+                    .get_mut(&handle)
+                    .ok_or_else(|| CommandError::invalid_handle(handle))?;
+                match &mut worker.ab_game {
+                    Some(game) => game.won = Some(won),
+                    None => {
+                        return Err(CommandError::new(
+                            ErrorCode::InvalidArgument,
+                            format!("handle {} isn't playing an A/B test game", handle),
+                        ))
+                    }
+                }
+                out.ok(());
+            }
+            Command::CancelNextMove { handle } => {
+                // cold clear can't take back a request, so a bot that has been asked is relaunched
+                // from its current board instead. One still within its budget hasn't been asked.
+                self.on_bot(handle, out, |bot, out| {
+                    let outstanding = bot.requested_at.is_some();
+                    if bot.pending.take().is_some() {
+                        bot.requested_at = None;
+                        bot.due = None;
+                    } else if outstanding {
+                        bot.relaunch();
+                    }
+                    out.ok(outstanding);
+                })?;
+            }
+            Command::PauseThinking { handle } => {
+                self.on_bot(handle, out, |bot, out| out.ok(bot.interface.pause()))?;
+            }
+            Command::ResumeThinking { handle } => {
+                self.on_bot(handle, out, |bot, out| {
+                    bot.interface.resume();
+                    out.ok(());
+                })?;
+            }
+            Command::Suggest {
+                board,
+                incoming,
+                options,
+                evaluator,
+            } => {
+                let mut options = options.unwrap_or(self.default_options);
+                options.threads = self.threads(options.threads, None)?;
+                // Turned down with the bots, so asking for suggestions can't get round the governor.
+                let options = self.scale(options);
+                let params = LaunchParams {
+                    options,
+                    evaluator: evaluator.unwrap_or_else(|| self.default_evaluator.clone()),
+                    policy: self.policy.clone(),
+                    backend: self.backend.clone(),
+                    plugin: None,
+                    book: None,
+                };
+                let mut out = out.clone();
+                self.spawn_one_off(options.threads, move || {
+                    let board = board.to_board();
+                    match params.launch(board.clone()) {
+                        Ok(mut interface) => {
+                            interface.request_next_move(incoming);
+                            let result = interface.block_next_move();
+                            out.ok(result.map(|(mv, info)| move_result(&board, mv, info)));
+                        }
+                        Err(err) => out.err(CommandError::launch_failed(err)),
+                    }
+                })?;
+            }
+            Command::SolvePerfectClear {
+                field,
+                queue,
+                hold,
+                max_solutions,
+            } => {
+                // The search is single-threaded.
+                self.threads(1, None)?;
+                let solver = PcSolver::new(
+                    self.default_options.mode,
+                    max_solutions.unwrap_or(1).max(1) as usize,
+                )
+                .cancelled_by(self.cancel_one_offs.clone());
+                let policy = self.policy.clone();
+                let mut out = out.clone();
+                self.spawn_one_off(1, move || {
+                    policy.apply_to_current_thread();
+                    out.ok(solver.solve(field, hold, &queue));
+                })?;
+            }
+            Command::SyncBoard { handle, board } => {
+                let previous = self
+                    .handles
+                    .get(&handle)
+                    .ok_or_else(|| CommandError::invalid_handle(handle))?;
+                let (options, evaluator) = (previous.requested, previous.params.evaluator.clone());
+                let (plugin, book) = (previous.params.plugin.clone(), previous.params.book.clone());
+                let label = previous.label.clone();
+                let mut worker =
+                    self.launch(options, evaluator, plugin, book, Some(&board), Some(handle))?;
+                worker.label = label;
+                // The coaching so far and the A/B test game carry over to the new bot.
+                if let Some(previous) = self.handles.get_mut(&handle) {
+                    *worker.coach.lock().unwrap() = previous.coach.lock().unwrap().take();
+                    worker.ab_game = previous.ab_game.take();
+                }
+                self.insert(handle, worker);
+                out.ok(());
+            }
+            Command::SetOptions {
+                handle,
+                mut options,
+            } => {
+                options.threads = self.threads(options.threads, Some(handle))?;
+                let scaled = self.scale(options);
+                let worker = self
+                    .handles
+                    .get_mut(&handle)
+                    .ok_or_else(|| CommandError::invalid_handle(handle))?;
+                worker.requested = options;
+                worker.params.options = scaled;
+                let params = worker.params.clone();
+                self.on_bot(handle, out, move |bot, out| {
+                    bot.relaunch_with(params);
+                    out.ok(());
+                })?;
+            }
+            Command::UpdateEvaluator { handle, weights } => {
+                let worker = self
+                    .handles
+                    .get_mut(&handle)
+                    .ok_or_else(|| CommandError::invalid_handle(handle))?;
+                worker.params.evaluator = patch(&worker.params.evaluator, weights)?;
+                let params = worker.params.clone();
+                self.on_bot(handle, out, move |bot, out| {
+                    bot.relaunch_with(params);
+                    out.ok(());
+                })?;
+            }
+            Command::GetStats { handle } => {
+                let throttle = self
+                    .handles
+                    .get(&handle)
+                    .map(|worker| worker.throttle)
+                    .filter(|&percent| percent < 100);
+                self.on_bot(handle, out, move |bot, out| {
+                    bot.stats.throttle_percent = throttle;
+                    bot.stats.thread_policy = bot.params.policy.effective();
+                    out.ok(&bot.stats)
+                })?;
+            }
+            // Batches are unpacked by `execute`, so this is one inside another.
+            Command::Batch { .. } => {
+                return Err(CommandError::new(
+                    ErrorCode::InvalidArgument,
+                    "batches can't be nested",
+                ));
+            }
+            Command::ServerInfo => {
+                out.ok(ServerInfo {
+                    build: BuildInfo::get(),
+                    commands: Command::VARIANTS,
+                    capabilities: self.capabilities.clone(),
+                    max_bots: self.max_bots,
+                    evaluators: EvaluatorChoice::VARIANTS,
+                    presets: self.presets.get().names(),
+                    plugins: self.plugins.names(),
+                    books: self.books.names(),
+                    usb: self.usb.as_ref().map(UsbStats::report),
+                    rejected_commands: self.limiter.rejected(),
+                    calibration: self.calibrated.as_ref().map(|(_, stats)| stats.clone()),
+                    warm_pool: self.pool.as_ref().map(WarmPool::stats),
+                    throttle_percent: self.governor.as_ref().map(|_| self.throttle),
+                });
+            }
+            Command::ListHandles => {
+                let mut handles: Vec<_> = self
+                    .handles
+                    .iter()
+                    .map(|(&handle, worker)| HandleInfo {
+                        handle,
+                        label: worker.label.clone(),
+                        slot: self.slot_of(handle),
+                    })
+                    .collect();
+                handles.sort_by_key(|info| info.handle);
+                out.ok(handles);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Returns the session's token, its bots, which are the parked ones if the console resumed, and
+// the codec for the rest of the session.
+pub fn handshake(
+    conn: &mut impl Transport,
+    outbox: &Outbox,
+    config: &SessionConfig,
+    sessions: &SessionStore,
+) -> Result<(u64, Bots, Codec, Option<u32>), SessionError> {
+    outbox.send(&Hello {
+        protocol_version: PROTOCOL_VERSION,
+        commands: Command::VARIANTS,
+        build: BuildInfo::get(),
+        codecs: Codec::VARIANTS,
+    });
+    if let Some(err) = outbox.take_error() {
+        return Err(err.into());
+    }
+    let client: ClientHello = conn.receive()?;
+    if client.protocol_version == PROTOCOL_VERSION {
+        let resumed = client
+            .resume
+            .and_then(|token| Some((token, sessions.resume(token, conn.kind())?)));
+        let (token, bots, resumed) = match resumed {
+            Some((token, bots)) => (token, bots, true),
+            None => (sessions.new_token(), Bots::new(config), false),
+        };
+        let compression = config.compression && client.compression;
+        let codec = Codec::choose(&client.codecs);
+        let calibrate = client.calibrate.or_else(|| match &bots.calibrated {
+            Some(&(samples, _)) if resumed => Some(samples),
+            _ => None,
+        });
+        let welcome = Welcome {
+            session_token: token,
+            resumed,
+            selftest: false,
+            compression,
+            codec,
+        };
+        outbox.send(&Response::Ok(welcome));
+        if let Some(err) = outbox.take_error() {
+            sessions.park(token, conn.kind(), bots);
+            return Err(err.into());
+        }
+        if compression {
+            outbox.enable_compression();
+        }
+        outbox.set_codec(codec);
+        Ok((token, bots, codec, calibrate))
+    } else {
+        Err(reject_version(outbox, client.protocol_version))
+    }
+}
+
+pub(crate) fn reject_version(outbox: &Outbox, client_version: u32) -> SessionError {
+    let message = format!(
+        "protocol version mismatch: the bridge speaks version {}, the switch speaks version {}",
+        PROTOCOL_VERSION, client_version
+    );
+    outbox.send(&Response::<()>::Err(
+        ErrorCode::VersionMismatch,
+        message.clone(),
+    ));
+    match outbox.take_error() {
+        Some(err) => err.into(),
+        None => SessionError::VersionMismatch(message),
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SessionConfig {
+    pub bot_policy: ThreadPolicy,
+    pub warm_pool: usize,
+    // The console is considered gone once nothing has arrived from it for this long.
+    pub idle_timeout: Reloadable<Option<Duration>>,
+    pub resume_grace: Duration,
+    pub max_bots: Option<usize>,
+    // What to do when Launch would go over `max_bots`: evict an idle bot, or refuse.
+    pub evict_idle: bool,
+    // Search threads of all cold clear bots together; Launch asks for fewer when it would go over,
+    // and is refused when none are left.
+    pub bot_threads: Option<u32>,
+    pub max_threads_per_bot: Option<u32>,
+    // Bots that take longer than this to finish a command are relaunched.
+    pub bot_deadline: Reloadable<Option<Duration>>,
+    pub presets: Reloadable<Presets>,
+    // Evaluator plugins that Launch can pick by name.
+    pub plugins: Arc<Plugins>,
+    // Opening books that Launch can pick by name, loaded once at startup.
+    pub books: Arc<Books>,
+    // Returned by DefaultOptions and DefaultEvaluator and used wherever a command leaves them out.
+    pub default_options: cold_clear::Options,
+    pub default_evaluator: cold_clear::evaluation::Standard,
+    // Compress large frames when the console says it can read them.
+    pub compression: bool,
+    pub audit: Option<AuditLog>,
+    pub monitor: Monitor,
+    // Every handle's game is written here when the handle goes away.
+    pub recorder: Option<GameRecorder>,
+    // Log every bot's board after each placement.
+    pub render: bool,
+    pub backend: Backend,
+    pub rate_limit: RateLimit,
+    // Turns the bots down while the host is overloaded.
+    pub governor: Option<Governor>,
+    pub ab_test: Option<AbTest>,
+}
+
+impl SessionConfig {
+    // In seconds, for when neither a flag nor the config file sets them.
+    pub const DEFAULT_IDLE_TIMEOUT: u64 = 30;
+    pub const DEFAULT_BOT_DEADLINE: u64 = 60;
+
+    // Optional behaviour that is switched on for this session, as reported by ServerInfo.
+    pub fn capabilities(&self) -> Vec<&'static str> {
+        let mut capabilities = vec![];
+        if self.warm_pool > 0 {
+            capabilities.push("warm-pool");
+        }
+        if self.idle_timeout.get().is_some() {
+            capabilities.push("idle-timeout");
+        }
+        if self.resume_grace > Duration::from_secs(0) {
+            capabilities.push("resume");
+        }
+        if self.max_bots.is_some() {
+            capabilities.push(if self.evict_idle {
+                "bot-limit-evict"
+            } else {
+                "bot-limit"
+            });
+        }
+        if self.bot_deadline.get().is_some() {
+            capabilities.push("watchdog");
+        }
+        if self.bot_threads.is_some() || self.max_threads_per_bot.is_some() {
+            capabilities.push("thread-budget");
+        }
+        if self.compression {
+            capabilities.push("compression");
+        }
+        if self.rate_limit.reset_when_flooding {
+            capabilities.push("flood-reset");
+        }
+        if self.governor.is_some() {
+            capabilities.push("governor");
+        }
+        capabilities
+    }
+}
+
+// The connection is closed once the session is over, whatever still holds a writer for it.
+pub fn run_session(
+    conn: &mut impl Transport,
+    config: &SessionConfig,
+    sessions: &SessionStore,
+) -> SessionError {
+    let err = serve_session(conn, config, sessions);
+    conn.close();
+    err
+}
+
+fn serve_session(
+    conn: &mut impl Transport,
+    config: &SessionConfig,
+    sessions: &SessionStore,
+) -> SessionError {
+    if let Err(err) = conn.set_idle_timeout(*config.idle_timeout.get()) {
+        return err.into();
+    }
+    let outbox = match conn.writer() {
+        Ok(writer) => Outbox::new(writer),
+        Err(err) => return err.into(),
+    };
+    let (token, mut bots, codec, calibrate) = match handshake(conn, &outbox, config, sessions) {
+        Ok(session) => session,
+        Err(err) => return err,
+    };
+    let audit = config.audit.as_ref().map(|log| log.session(token));
+    bots.usb = conn.usb_stats();
+    let err = command_loop(conn, &mut bots, &outbox, codec, audit, calibrate);
+    if let Some(usb) = &bots.usb {
+        info!("USB: {}", usb.report());
+    }
+    // Nobody is coming back for the bots of a bridge that is exiting, and a session reset for
+    // flooding starts over without them, so they are dropped, which saves their games, once the
+    // console has been told.
+    match err {
+        SessionError::Transport(TransportError::Shutdown) | SessionError::Flooding => {
+            if let Err(err) = conn.flush() {
+                warn!("Could not tell the switch why the session ended: {:?}", err);
+            }
+        }
+        _ => sessions.park(token, conn.kind(), bots),
+    }
+    err
+}
+
+fn command_loop(
+    conn: &mut impl Transport,
+    bots: &mut Bots,
+    outbox: &Outbox,
+    codec: Codec,
+    audit: Option<SessionAudit>,
+    calibrate: Option<u32>,
+) -> SessionError {
+    let responder = |request_id| OutboxResponder {
+        outbox: outbox.clone(),
+        request_id,
+        audit: audit.clone(),
+    };
+    if let Some(samples) = calibrate {
+        let (mut notify, mut finish) = (responder(None), responder(None));
+        bots.calibrate(
+            samples,
+            Box::new(move |notification| notify.notify(notification)),
+            Box::new(move |round_trip| finish.notify(Notification::Calibrated { round_trip })),
+        )
+        .ok();
+    }
+    // The client's hello was its frame 0.
+    let mut expected_seq: u32 = 1;
+    loop {
+        let frame = match conn.receive_frame() {
+            Ok(frame) => {
+                bots.received_at = Instant::now();
+                frame
+            }
+            Err(TransportError::Shutdown) => {
+                responder(None).err(CommandError::new(
+                    ErrorCode::ShuttingDown,
+                    "the bridge is shutting down",
+                ));
+                return SessionError::Transport(TransportError::Shutdown);
+            }
+            Err(err) => return err.into(),
+        };
+        // Lost frames are reported and then skipped over, repeats are dropped.
+        let skipped = frame.seq.wrapping_sub(expected_seq) as i32;
+        if skipped < 0 {
+            warn!("Dropped a repeat of frame {} from the switch", frame.seq);
+            continue;
+        }
+        if skipped > 0 {
+            let message = format!(
+                "{} frame(s) from the switch were lost (expected frame {}, got {})",
+                skipped, expected_seq, frame.seq
+            );
+            warn!("{}", message);
+            responder(None).err(CommandError::new(ErrorCode::FramesLost, message));
+        }
+        expected_seq = frame.seq.wrapping_add(1);
+        match Request::decode(codec, &frame.payload) {
+            Ok(Request {
+                request_id,
+                command,
+            }) => {
+                if let Some(audit) = &audit {
+                    audit.command(request_id, &command);
+                }
+                let mut out = responder(Some(request_id));
+                // Every command in a batch counts, or batching would get around the limit. Pings
+                // are free so the console can still tell the bridge is alive during a flood.
+                let cost = match &command {
+                    Command::Ping { .. }
+                    | Command::EchoTimestamp { .. }
+                    | Command::CalibrationEcho { .. } => 0,
+                    Command::Batch { commands } => commands.len().max(1),
+                    _ => 1,
+                };
+                if bots.limiter.try_acquire(cost) {
+                    bots.execute(command, &mut out);
+                } else if bots.reset_when_flooding && bots.limiter.flooding() {
+                    warn!("Resetting the session of a switch that keeps flooding commands");
+                    out.err(CommandError::new(
+                        ErrorCode::Busy,
+                        "too many commands, the session is being reset",
+                    ));
+                    return SessionError::Flooding;
+                } else {
+                    out.err(CommandError::new(
+                        ErrorCode::Busy,
+                        "too many commands, slow down",
+                    ));
+                }
+            }
+            Err((request_id, err)) => responder(request_id).err(err),
+        }
+        if let Some(err) = outbox.take_error() {
+            return SessionError::Transport(err);
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UsbConfig {
+    pub devices: DeviceFilter,
+    // Bounds of the backoff between looks for devices. Hotplug events and sessions ending
+    // trigger a look right away.
+    pub retry_min: Duration,
+    pub retry_max: Duration,
+    pub timeouts: Reloadable<UsbTimeouts>,
+    pub capture: Option<Capture>,
+}
+
+impl Default for UsbConfig {
+    fn default() -> UsbConfig {
+        UsbConfig {
+            devices: DeviceFilter::default(),
+            retry_min: Backoff::DEFAULT_MIN,
+            retry_max: Backoff::DEFAULT_MAX,
+            timeouts: Reloadable::default(),
+            capture: None,
+        }
+    }
+}
+
+enum Wakeup {
+    Hotplug(HotplugEvent),
+    SessionEnded,
+    Shutdown,
+}
+
+// Every matching device gets its own session thread with its own handles, so one console
+// disconnecting doesn't affect the others. `sessions` is shared with `serve_tcp` when both run;
+// a session only resumes over the transport it was parked from.
+pub fn serve(config: SessionConfig, usb: UsbConfig, sessions: SessionStore) {
+    let active = Arc::new(Mutex::new(HashSet::new()));
+    let (wake, woken) = channel();
+    let hotplug = match SwitchConnection::watch_hotplug(&usb.devices) {
+        Some(watcher) => {
+            let wake = wake.clone();
+            std::thread::spawn(move || {
+                while let Ok(event) = watcher.recv() {
+                    if wake.send(Wakeup::Hotplug(event)).is_err() {
+                        break;
+                    }
+                }
+            });
+            true
+        }
+        None => {
+            warn!("USB hotplug is not available, polling for devices instead");
+            false
+        }
+    };
+    {
+        let wake = wake.clone();
+        std::thread::spawn(move || {
+            shutdown::wait();
+            wake.send(Wakeup::Shutdown).ok();
+        });
+    }
+    let mut backoff = Backoff::new(usb.retry_min, usb.retry_max);
+    loop {
+        let devices = match SwitchConnection::find_devices(&usb.devices) {
+            Ok(devices) => devices,
+            Err(err) => {
+                error!("Error: {:?}", err);
+                vec![]
+            }
+        };
+        for device in devices {
+            let id = (device.bus_number(), device.address());
+            if active.lock().unwrap().contains(&id) {
+                continue;
+            }
+            let mut conn = match SwitchConnection::open(&device, &usb.devices.interface) {
+                Ok(conn) => conn
+                    .with_timeouts(*usb.timeouts.get())
+                    .with_capture(usb.capture.clone())
+                    .with_traffic(Some(config.monitor.traffic()))
+                    .pipelined(),
+                Err(err) => {
+                    warn!(
+                        "Can't connect to the device on bus {} address {}: {}",
+                        id.0,
+                        id.1,
+                        err.remediation(&usb.devices)
+                    );
+                    continue;
+                }
+            };
+            info!(
+                "Successfully connected to the switch on bus {} address {}!",
+                id.0, id.1
+            );
+            info!("{}", BuildInfo::get());
+            backoff.reset();
+            active.lock().unwrap().insert(id);
+            let active = active.clone();
+            let config = config.clone();
+            let sessions = sessions.clone();
+            let wake = wake.clone();
+            std::thread::spawn(move || {
+                let span = info_span!("session", bus = id.0, address = id.1);
+                let _enter = span.enter();
+                let _connection = config
+                    .monitor
+                    .connection(format!("USB bus {} address {}", id.0, id.1));
+                let err = run_session(&mut conn, &config, &sessions);
+                warn!(
+                    "Lost connection to the switch on bus {} address {}: {:?}",
+                    id.0, id.1, err
+                );
+                drop(conn);
+                active.lock().unwrap().remove(&id);
+                wake.send(Wakeup::SessionEnded).ok();
+            });
+        }
+        // Devices are still rescanned while sessions are running, since a device can be
+        // replugged faster than its session notices.
+        let delay = backoff.next_delay();
+        if active.lock().unwrap().is_empty() {
+            info!(
+                "No switch connected (attempt {}). Retrying in {:.1} seconds{}...",
+                backoff.attempts(),
+                delay.as_secs_f64(),
+                if hotplug {
+                    " or as soon as one is plugged in"
+                } else {
+                    ""
+                }
+            );
+        }
+        match woken.recv_timeout(delay) {
+            Ok(Wakeup::Hotplug(HotplugEvent::Arrived { bus, address })) => {
+                info!("Switch plugged in on bus {} address {}", bus, address);
+                backoff.reset();
+            }
+            Ok(Wakeup::Hotplug(HotplugEvent::Left { bus, address })) => {
+                info!("Switch unplugged from bus {} address {}", bus, address);
+            }
+            Ok(Wakeup::SessionEnded) => backoff.reset(),
+            Ok(Wakeup::Shutdown) => break,
+            Err(_) => {}
+        }
+    }
+    // Sessions notice the shutdown on their own and end within a poll, releasing their devices.
+    info!("Shutting down...");
+    while !active.lock().unwrap().is_empty() {
+        if woken.recv().is_err() {
+            break;
+        }
+    }
+    sessions.clear();
+}
+
+pub fn serve_tcp(
+    listener: TcpListener,
+    config: SessionConfig,
+    sessions: SessionStore,
+) -> std::io::Result<()> {
+    let local_addr = listener.local_addr()?;
+    info!("Listening on {}", local_addr);
+    info!("{}", BuildInfo::get());
+    std::thread::spawn(move || {
+        shutdown::wait();
+        TcpStream::connect(loopback(local_addr)).ok();
+    });
+    // Every session holds a sender, so `finished` disconnects once all of them have ended.
+    let (running, finished) = channel::<()>();
+    for stream in listener.incoming() {
+        if shutdown::requested() {
+            break;
+        }
+        let accepted = stream.and_then(|stream| {
+            let conn = TcpTransport::new(stream)?;
+            let peer = conn.peer_addr()?;
+            Ok((conn, peer))
+        });
+        let (mut conn, peer) = match accepted {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                error!("Error: {:?}", err);
+                continue;
+            }
+        };
+        info!("Accepted connection from {}", peer);
+        let config = config.clone();
+        let sessions = sessions.clone();
+        let running = running.clone();
+        std::thread::spawn(move || {
+            let _running = running;
+            let span = info_span!("session", %peer);
+            let _enter = span.enter();
+            let _connection = config.monitor.connection(format!("TCP {}", peer));
+            let err = run_session(&mut conn, &config, &sessions);
+            warn!("Lost connection to {}: {:?}", peer, err);
+        });
+    }
+    info!("Shutting down...");
+    drop(running);
+    finished.recv().ok();
+    sessions.clear();
+    Ok(())
+}
+
+// Stdout carries the protocol in this mode, which is why all logging goes to stderr.
+pub fn serve_stdio(config: SessionConfig) {
+    info!("{}", BuildInfo::get());
+    // Stdin can't be reopened, so there is nothing to resume.
+    let sessions = SessionStore::new(Duration::from_secs(0));
+    let span = info_span!("session", transport = "stdio");
+    let _enter = span.enter();
+    let _connection = config.monitor.connection("stdio".to_owned());
+    let err = run_session(&mut StdioTransport::new(), &config, &sessions);
+    info!("Session ended: {:?}", err);
+}
+
+// Commands a session may send, as a token bucket: `per_second` on average, in bursts of up to
+// `burst`.
+#[derive(Clone, Copy, Debug)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: f64,
+    // End the session, dropping its bots, once the console has had a warning's worth of commands
+    // in a row rejected.
+    pub reset_when_flooding: bool,
+}
+
+impl Default for RateLimit {
+    fn default() -> RateLimit {
+        RateLimit {
+            per_second: RateLimiter::COMMANDS_PER_SECOND,
+            burst: RateLimiter::COMMAND_BURST,
+            reset_when_flooding: false,
+        }
+    }
+}
+
+pub struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last_refill: Instant,
+    throttled: u32,
+    // Rejected since the last command that got through.
+    streak: u32,
+}
+
+impl RateLimiter {
+    pub const COMMANDS_PER_SECOND: f64 = 500.0;
+    pub const COMMAND_BURST: f64 = 100.0;
+    pub const FLOOD_WARNING_INTERVAL: u32 = 1000;
+    pub fn new(rate: f64, burst: f64) -> RateLimiter {
+        RateLimiter {
+            rate,
+            burst,
+            tokens: burst,
+            last_refill: Instant::now(),
+            throttled: 0,
+            streak: 0,
+        }
+    }
+    pub fn rejected(&self) -> u32 {
+        self.throttled
+    }
+    pub fn flooding(&self) -> bool {
+        self.streak >= RateLimiter::FLOOD_WARNING_INTERVAL
+    }
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.last_refill = now;
+    }
+    pub fn try_acquire(&mut self, cost: usize) -> bool {
+        self.refill();
+        if self.tokens < cost as f64 {
+            self.throttled += 1;
+            self.streak += 1;
+            if self.throttled % RateLimiter::FLOOD_WARNING_INTERVAL == 0 {
+                warn!(
+                    "The switch is flooding commands ({} rejected so far)",
+                    self.throttled
+                );
+            }
+            return false;
+        }
+        self.tokens -= cost as f64;
+        self.streak = 0;
+        true
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+    Transport(TransportError),
+    Decode(serde_cbor::Error),
+    VersionMismatch(String),
+    // The console kept sending commands past the rate limit and the session was reset.
+    Flooding,
+}
+
+impl From<TransportError> for SessionError {
+    fn from(err: TransportError) -> SessionError {
+        SessionError::Transport(err)
+    }
+}
+
+impl From<ReceiveError> for SessionError {
+    fn from(err: ReceiveError) -> SessionError {
+        match err {
+            ReceiveError::Transport(err) => SessionError::Transport(err),
+            ReceiveError::Decode(err) => SessionError::Decode(err),
+        }
+    }
+}
+
+// Tags responses with the ID of the request they answer. Transport errors stay in the outbox for
+// the command loop to notice.
+#[derive(Clone)]
+struct OutboxResponder {
+    outbox: Outbox,
+    request_id: Option<u32>,
+    audit: Option<SessionAudit>,
+}
+
+impl Responder for OutboxResponder {
+    fn respond(&mut self, msg: &impl Serialize) {
+        if let Some(audit) = &self.audit {
+            audit.response(self.request_id, msg);
+        }
+        let reply = Reply {
+            request_id: self.request_id,
+            response: msg,
+        };
+        if let Err(err) = self.outbox.try_send(&reply) {
+            error!("Could not encode a response: {}", err);
+            self.outbox.send(&Reply {
+                request_id: self.request_id,
+                response: Response::<()>::Err(ErrorCode::Internal, err.to_string()),
+            });
+        }
+    }
+    fn notify(&mut self, notification: Notification) {
+        let mut unsolicited = OutboxResponder {
+            request_id: None,
+            ..self.clone()
+        };
+        unsolicited.ok(notification);
+    }
+}
+
+struct Batch<R> {
+    responses: Vec<Option<serde_cbor::Value>>,
+    remaining: usize,
+    out: R,
+}
+
+// Answers for one command of a batch. Responses can arrive from any bot's thread in any order;
+// whichever arrives last sends the whole batch.
+#[derive(Clone)]
+struct BatchResponder<R> {
+    batch: Arc<Mutex<Batch<R>>>,
+    index: usize,
+}
+
+impl<R: Responder> BatchResponder<R> {
+    fn new(len: usize, mut out: R) -> BatchResponder<R> {
+        if len == 0 {
+            out.ok(Vec::<()>::new());
+        }
+        BatchResponder {
+            batch: Arc::new(Mutex::new(Batch {
+                responses: vec![None; len],
+                remaining: len,
+                out,
+            })),
+            index: 0,
+        }
+    }
+    fn at(&self, index: usize) -> BatchResponder<R> {
+        BatchResponder {
+            batch: self.batch.clone(),
+            index,
+        }
+    }
+}
+
+impl<R: Responder> Responder for BatchResponder<R> {
+    fn respond(&mut self, msg: &impl Serialize) {
+        // Responses of every type are held together until the last arrives, so each is turned
+        // into a value now. The command still gets an answer if that fails.
+        let value = serde_cbor::value::to_value(msg).unwrap_or_else(|err| {
+            error!("Could not encode a batched response: {}", err);
+            let failed = Response::<()>::Err(ErrorCode::Internal, err.to_string());
+            serde_cbor::value::to_value(&failed).unwrap_or(serde_cbor::Value::Null)
+        });
+        let mut batch = self.batch.lock().unwrap();
+        if batch.responses[self.index].replace(value).is_some() {
+            return;
+        }
+        batch.remaining -= 1;
+        if batch.remaining == 0 {
+            let responses: Vec<_> = batch.responses.iter_mut().map(Option::take).collect();
+            batch.out.ok(responses);
+        }
+    }
+    fn notify(&mut self, notification: Notification) {
+        self.batch.lock().unwrap().out.notify(notification);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libtetris::{FallingPiece, Piece};
+    use serde_json::Value;
+
+    #[derive(Clone)]
+    struct Answer(Sender<Value>);
+
+    impl Responder for Answer {
+        fn respond(&mut self, msg: &impl Serialize) {
+            self.0.send(serde_json::to_value(msg).unwrap()).ok();
+        }
+    }
+
+    struct Session {
+        bots: Bots,
+        out: Answer,
+        answers: Receiver<Value>,
+    }
+
+    impl Session {
+        fn new() -> Session {
+            let (send, answers) = channel();
+            Session {
+                bots: Bots::new(&SessionConfig::default()),
+                out: Answer(send),
+                answers,
+            }
+        }
+        fn command(&mut self, command: Command) -> Value {
+            self.bots.execute(command, &mut self.out);
+            let mut answer = self.answers.recv().unwrap();
+            answer.get_mut("Ok").expect("the command failed").take()
+        }
+        fn launch(&mut self) -> u32 {
+            let launch = Command::Launch {
+                options: cold_clear::Options::default(),
+                evaluator: None,
+                preset: None,
+                slot: None,
+                board: None,
+                label: None,
+                plugin: None,
+                coach: false,
+                book: None,
+            };
+            serde_json::from_value(self.command(launch)).unwrap()
+        }
+        // Gives the bot `piece` six times and has it place one.
+        fn play(&mut self, handle: u32, piece: Piece) -> FallingPiece {
+            for _ in 0..6 {
+                self.command(Command::AddNextPiece { handle, piece });
+            }
+            self.command(Command::RequestNextMove {
+                handle,
+                incoming: 0,
+                budget_ms: None,
+            });
+            let result = self.command(Command::BlockNextMove { handle });
+            serde_json::from_value(result["move"]["expected_location"].clone()).unwrap()
+        }
+        fn reset(&mut self, handle: u32, options: Option<cold_clear::Options>) -> bool {
+            let reset = Command::ResetBot {
+                handle,
+                options,
+                evaluator: None,
+            };
+            self.command(reset)["reused"].as_bool().unwrap()
+        }
+        // Everything the bridge shows about the bot.
+        fn state(&mut self, handle: u32) -> Value {
+            let stats = self.command(Command::GetStats { handle });
+            let status = self.bots.handles[&handle].status.lock().unwrap();
+            serde_json::json!({
+                "stats": stats,
+                "queue": status.board.next_queue().collect::<Vec<_>>(),
+                "hold": status.board.hold_piece,
+                "field": (0..40)
+                    .map(|y| (0..10).map(|x| status.board.occupied(x, y)).collect())
+                    .collect::<Vec<Vec<bool>>>(),
+                "pieces_placed": status.pieces_placed,
+                "plan": status.plan,
+                "last_placed": status.last_placed,
+                "fumen": status.fumen,
+            })
+        }
+    }
+
+    // Backdates the last refill rather than sleeping, so the test doesn't depend on timing.
+    fn wait(limiter: &mut RateLimiter, time: Duration) {
+        limiter.last_refill -= time;
+    }
+
+    #[test]
+    fn rate_limiter_refills_at_its_rate() {
+        let mut limiter = RateLimiter::new(10.0, 2.0);
+        assert!(limiter.try_acquire(2));
+        assert!(!limiter.try_acquire(1));
+        assert_eq!(limiter.rejected(), 1);
+        wait(&mut limiter, Duration::from_millis(150));
+        assert!(limiter.try_acquire(1));
+        assert!(!limiter.try_acquire(1));
+        assert_eq!(limiter.rejected(), 2);
+    }
+
+    #[test]
+    fn rate_limiter_never_holds_more_than_its_burst() {
+        let mut limiter = RateLimiter::new(10.0, 2.0);
+        wait(&mut limiter, Duration::from_secs(60));
+        assert!(!limiter.try_acquire(3));
+        assert!(limiter.try_acquire(2));
+    }
+
+    #[test]
+    fn rate_limiter_reports_floods_until_a_command_gets_through() {
+        let mut limiter = RateLimiter::new(1.0, 1.0);
+        assert!(limiter.try_acquire(1));
+        for _ in 0..RateLimiter::FLOOD_WARNING_INTERVAL {
+            assert!(!limiter.flooding());
+            limiter.try_acquire(1);
+        }
+        assert!(limiter.flooding());
+        wait(&mut limiter, Duration::from_secs(1));
+        assert!(limiter.try_acquire(1));
+        assert!(!limiter.flooding());
+    }
+
+    #[test]
+    fn resetting_to_the_same_bot_reuses_it() {
+        let mut session = Session::new();
+        let handle = session.launch();
+        session.play(handle, Piece::T);
+        assert!(session.reset(handle, None));
+        let options = cold_clear::Options {
+            min_nodes: 1,
+            ..Default::default()
+        };
+        assert!(!session.reset(handle, Some(options)));
+    }
+
+    #[test]
+    fn reused_bots_start_like_launched_ones() {
+        let mut session = Session::new();
+        let reused = session.launch();
+        session.play(reused, Piece::T);
+        assert!(session.reset(reused, None));
+        let fresh = session.launch();
+        assert_eq!(session.state(reused), session.state(fresh));
+        // Pieces left over from the last game would be played first.
+        assert_eq!(session.play(reused, Piece::O).kind.0, Piece::O);
+        assert_eq!(session.state(reused)["pieces_placed"], 1);
+    }
+}
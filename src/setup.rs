@@ -0,0 +1,115 @@
+use cc_switch_usb_rs::transport::{DeviceFilter, SwitchConnection};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::process::Command;
+
+// Gets the host ready for the bridge and reports what is still in the way. Returns whether the
+// bridge can connect to a console afterwards.
+pub fn run(filter: &DeviceFilter, rules_file: &Path, yes: bool) -> bool {
+    if cfg!(target_os = "linux") && !install_udev_rule(filter, rules_file, yes) {
+        return false;
+    }
+    check_devices(filter)
+}
+
+fn install_udev_rule(filter: &DeviceFilter, rules_file: &Path, yes: bool) -> bool {
+    let rule = format!(
+        "# Lets the cold clear bridge open the console without root.\n\
+         SUBSYSTEM==\"usb\", ATTRS{{idVendor}}==\"{:04x}\", ATTRS{{idProduct}}==\"{:04x}\", \
+         MODE=\"0666\"\n",
+        filter.vendor_id, filter.product_id
+    );
+    if std::fs::read_to_string(rules_file).ok().as_deref() == Some(rule.as_str()) {
+        println!("The udev rule in {} is up to date.", rules_file.display());
+        return true;
+    }
+    println!(
+        "This udev rule will be written to {}:\n",
+        rules_file.display()
+    );
+    print!("{}", rule);
+    println!();
+    if !yes && !confirm("Write it?") {
+        println!("Left the udev rules alone.");
+        return false;
+    }
+    if let Err(err) = std::fs::write(rules_file, &rule) {
+        match err.kind() {
+            io::ErrorKind::PermissionDenied => println!(
+                "No permission to write {}. Run the setup again with sudo.",
+                rules_file.display()
+            ),
+            _ => println!("Could not write {}: {}", rules_file.display(), err),
+        }
+        return false;
+    }
+    println!("Wrote {}.", rules_file.display());
+    // The rule only applies to devices added after udev reloads it, so existing ones are
+    // triggered again.
+    for args in &[&["control", "--reload-rules"][..], &["trigger"][..]] {
+        match Command::new("udevadm").args(*args).status() {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                println!(
+                    "udevadm {} failed ({}). Replug the console.",
+                    args[0], status
+                );
+                return true;
+            }
+            Err(err) => {
+                println!("Could not run udevadm: {}. Replug the console.", err);
+                return true;
+            }
+        }
+    }
+    true
+}
+
+// Opening the device is how a missing driver shows up too: on Windows, libusb can't open a
+// device that doesn't have a WinUSB-compatible driver bound to it.
+fn check_devices(filter: &DeviceFilter) -> bool {
+    let devices = match SwitchConnection::find_devices(filter) {
+        Ok(devices) => devices,
+        Err(err) => {
+            println!("Could not list USB devices: {}", err);
+            return false;
+        }
+    };
+    if devices.is_empty() {
+        println!(
+            "No console is plugged in, so the connection couldn't be checked. Plug it in with a \
+             data cable, start the homebrew and run the setup again."
+        );
+        return false;
+    }
+    let mut ready = true;
+    for device in devices {
+        match SwitchConnection::open(&device, &filter.interface) {
+            Ok(_) => println!(
+                "Bus {:03} Device {:03}: the bridge can connect.",
+                device.bus_number(),
+                device.address()
+            ),
+            Err(err) => {
+                ready = false;
+                println!(
+                    "Bus {:03} Device {:03}: {}",
+                    device.bus_number(),
+                    device.address(),
+                    err.remediation(filter)
+                );
+            }
+        }
+    }
+    ready
+}
+
+fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    io::stdout().flush().ok();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
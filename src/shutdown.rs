@@ -0,0 +1,40 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// Set by SIGINT and SIGTERM (Ctrl+C on Windows) once `install` has run. Transports check it
+// whenever a read wakes up without data, which they do at least every `POLL`, and end the session
+// with `TransportError::Shutdown`.
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+pub const POLL: Duration = Duration::from_millis(250);
+
+// A second signal exits right away, for when shutting down cleanly hangs.
+pub fn install() {
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+extern "C" fn on_signal(_signal: libc::c_int) {
+    if REQUESTED.swap(true, Ordering::SeqCst) {
+        unsafe { libc::_exit(1) };
+    }
+}
+
+// Shuts down as if a signal had arrived, e.g. when the dashboard is quit.
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+// Blocks until a shutdown is requested.
+pub fn wait() {
+    while !requested() {
+        std::thread::sleep(POLL);
+    }
+}
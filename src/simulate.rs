@@ -0,0 +1,84 @@
+use crate::server::SessionConfig;
+use crate::versus::{self, VersusError, VersusRules};
+use std::fmt;
+use tracing::info;
+
+// Totals over every game, with A and B being the first and second evaluator whichever side they
+// played on.
+pub struct SimulationReport {
+    pub games: u32,
+    pub wins: [u32; 2],
+    pub draws: u32,
+    pub attack: [u64; 2],
+    pub pieces: u64,
+}
+
+impl fmt::Display for SimulationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let games = f64::from(self.games.max(1));
+        writeln!(
+            f,
+            "games:      {} (A won {:.1}%, B won {:.1}%, {} drawn)",
+            self.games,
+            f64::from(self.wins[0]) * 100.0 / games,
+            f64::from(self.wins[1]) * 100.0 / games,
+            self.draws
+        )?;
+        for (name, attack) in ["A", "B"].iter().zip(&self.attack) {
+            writeln!(
+                f,
+                "attack {}:   {:.1} a game, {:.2} per piece",
+                name,
+                *attack as f64 / games,
+                *attack as f64 / self.pieces.max(1) as f64
+            )?;
+        }
+        write!(f, "survival:   {:.1} pieces", self.pieces as f64 / games)
+    }
+}
+
+// Plays `games` versus games between the evaluators in `rules.evaluators`, one seed after
+// another from `rules.seed`. Sides are swapped every game, since P1's attack of a round lands
+// before P2's.
+pub fn run(
+    config: &SessionConfig,
+    rules: &VersusRules,
+    games: u32,
+) -> Result<SimulationReport, VersusError> {
+    let mut report = SimulationReport {
+        games,
+        wins: [0; 2],
+        draws: 0,
+        attack: [0; 2],
+        pieces: 0,
+    };
+    for game in 0..games {
+        let swapped = game % 2 == 1;
+        let mut rules = rules.clone();
+        rules.seed = rules.seed.wrapping_add(u64::from(game));
+        if swapped {
+            rules.evaluators.reverse();
+        }
+        let result = versus::run(config, &rules)?;
+        let side = |player: usize| if swapped { 1 - player } else { player };
+        match result.winner {
+            Some(player) => report.wins[side(player)] += 1,
+            None => report.draws += 1,
+        }
+        for player in 0..2 {
+            report.attack[side(player)] += u64::from(result.attack[player]);
+        }
+        report.pieces += u64::from(result.pieces);
+        info!(
+            "Game {}/{}: {}",
+            game + 1,
+            games,
+            match result.winner.map(side) {
+                Some(0) => "A won",
+                Some(_) => "B won",
+                None => "drawn",
+            }
+        );
+    }
+    Ok(report)
+}
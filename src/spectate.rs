@@ -0,0 +1,114 @@
+use cc_switch_usb_rs::engine::{start_message, tbp_move};
+use cc_switch_usb_rs::monitor::{FeedKind, Monitor};
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Plays the TBP frontend to whatever connects, so programs written as TBP bots (visualizers, for
+// instance) can follow a console game. Each connection follows the first handle that does
+// anything and moves on to the next once that handle is dropped. Suggestions are never asked for,
+// and anything the other end sends after `ready` is ignored.
+// Stops accepting once `closed` is set, as of the next connection.
+pub fn spawn(listener: TcpListener, monitor: Monitor, closed: Arc<AtomicBool>) {
+    std::thread::Builder::new()
+        .name("spectate".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                if closed.load(Ordering::Relaxed) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        warn!("Could not accept a TBP spectator: {}", err);
+                        continue;
+                    }
+                };
+                let monitor = monitor.clone();
+                std::thread::spawn(move || {
+                    if let Err(err) = handle(stream, &monitor) {
+                        debug!("TBP spectator disconnected: {}", err);
+                    }
+                });
+            }
+        })
+        .unwrap();
+}
+
+fn handle(mut stream: TcpStream, monitor: &Monitor) -> io::Result<()> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let hello = receive(&mut reader)?;
+    if hello["type"] != "info" {
+        return Err(invalid(&hello));
+    }
+    send(&mut stream, json!({ "type": "rules" }))?;
+    let ready = receive(&mut reader)?;
+    if ready["type"] != "ready" {
+        return Err(invalid(&ready));
+    }
+    info!(
+        "TBP spectator {} connected",
+        hello["name"].as_str().unwrap_or("(unnamed)")
+    );
+    stream.set_read_timeout(None)?;
+    // Read and drop the rest so the other end never blocks on a full socket.
+    std::thread::spawn(move || for _ in reader.lines() {});
+
+    let mut following = None;
+    for event in monitor.feed().subscribe() {
+        match (following, event.kind) {
+            (None, FeedKind::Gone) => {}
+            (None, _) => {
+                following = Some(event.handle);
+                send(&mut stream, start_message(&event.board))?;
+            }
+            (Some(handle), _) if handle != event.handle => {}
+            (Some(_), FeedKind::Start) | (Some(_), FeedKind::Reset) => {
+                send(&mut stream, json!({ "type": "stop" }))?;
+                send(&mut stream, start_message(&event.board))?;
+            }
+            (Some(_), FeedKind::Piece(piece)) => {
+                send(&mut stream, json!({ "type": "new_piece", "piece": piece }))?;
+            }
+            (Some(_), FeedKind::Place { location, .. }) => {
+                send(
+                    &mut stream,
+                    json!({ "type": "play", "move": tbp_move(&location) }),
+                )?;
+            }
+            (Some(_), FeedKind::Gone) => {
+                send(&mut stream, json!({ "type": "stop" }))?;
+                following = None;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn receive(reader: &mut impl BufRead) -> io::Result<Value> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    serde_json::from_str(&line).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn send(stream: &mut TcpStream, message: Value) -> io::Result<()> {
+    let mut line = message.to_string();
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+fn invalid(message: &Value) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected message {}", message),
+    )
+}
@@ -0,0 +1,177 @@
+use crate::codec::{Codec, CodecError};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::error;
+
+mod capture;
+mod frame;
+mod stdio;
+mod tcp;
+mod usb;
+
+pub use capture::Capture;
+pub use frame::{
+    decode_frame, encode_frame, read_frame, Frame, FRAME_MAGIC, FRAME_VERSION, MAX_FRAME_LEN,
+};
+pub use stdio::StdioTransport;
+pub use tcp::{loopback, systemd_listener, TcpTransport};
+pub use usb::{
+    DeviceFilter, HotplugEvent, HotplugWatcher, InterfaceFilter, PipelinedConnection,
+    SwitchConnection, SwitchConnectionError, UsbStats, UsbStatsReport, UsbTimeouts,
+};
+
+#[derive(Debug)]
+pub enum TransportError {
+    Usb(rusb::Error),
+    Connect(SwitchConnectionError),
+    Io(std::io::Error),
+    IdleTimeout,
+    // The bridge was asked to exit; see `shutdown`.
+    Shutdown,
+}
+
+impl From<rusb::Error> for TransportError {
+    fn from(err: rusb::Error) -> TransportError {
+        TransportError::Usb(err)
+    }
+}
+
+impl From<SwitchConnectionError> for TransportError {
+    fn from(err: SwitchConnectionError) -> TransportError {
+        TransportError::Connect(err)
+    }
+}
+
+impl From<std::io::Error> for TransportError {
+    fn from(err: std::io::Error) -> TransportError {
+        TransportError::Io(err)
+    }
+}
+
+#[derive(Debug)]
+pub enum ReceiveError {
+    Transport(TransportError),
+    Decode(serde_cbor::Error),
+}
+
+impl From<TransportError> for ReceiveError {
+    fn from(err: TransportError) -> ReceiveError {
+        ReceiveError::Transport(err)
+    }
+}
+
+pub trait Transport {
+    // What the connection runs over, e.g. "usb". Sessions only resume over the same kind.
+    fn kind(&self) -> &'static str;
+    fn read_all(&mut self, buf: &mut [u8]) -> Result<(), TransportError>;
+    // Reads at least one byte and at most what has already arrived. Transports that can't tell
+    // read one byte.
+    fn read_some(&mut self, buf: &mut [u8]) -> Result<usize, TransportError> {
+        self.read_all(&mut buf[..1])?;
+        Ok(1)
+    }
+    // Bytes `read_frame` has read but not used yet, which belong to the next frame.
+    fn unread(&mut self) -> &mut Vec<u8>;
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), TransportError>;
+    // Makes reads fail with `IdleTimeout` once nothing has arrived for `timeout`.
+    fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<(), TransportError>;
+    // A second handle for writing, so responses can be sent from other threads while this one is
+    // blocked reading.
+    fn writer(&mut self) -> Result<Box<dyn TransportWriter>, TransportError>;
+    // Stops whatever the transport runs in the background and lets go of the device, even while
+    // writers handed out by `writer` are still around. Writes through them fail from then on.
+    fn close(&mut self) {}
+    // Waits until everything written so far, from any writer, has been sent.
+    fn flush(&mut self) -> Result<(), TransportError> {
+        Ok(())
+    }
+    // Transfer counts, for connections over USB.
+    fn usb_stats(&self) -> Option<UsbStats> {
+        None
+    }
+
+    fn receive<T: DeserializeOwned>(&mut self) -> Result<T, ReceiveError>
+    where
+        Self: Sized,
+    {
+        let frame = self.receive_frame()?;
+        serde_cbor::from_slice(&frame.payload).map_err(ReceiveError::Decode)
+    }
+    fn receive_frame(&mut self) -> Result<Frame, TransportError>
+    where
+        Self: Sized,
+    {
+        read_frame(self)
+    }
+}
+
+pub trait TransportWriter: Send {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), TransportError>;
+}
+
+struct NumberedWriter {
+    writer: Box<dyn TransportWriter>,
+    next_seq: u32,
+    compress: bool,
+    codec: Codec,
+}
+
+// Sends messages from any number of threads. Each message is numbered and written under the lock
+// so frames never interleave or go out of order, and after the first error everything else is
+// dropped until it is taken. Everything a connection sends has to go through its one outbox.
+#[derive(Clone)]
+pub struct Outbox {
+    writer: Arc<Mutex<NumberedWriter>>,
+    error: Arc<Mutex<Option<TransportError>>>,
+}
+
+impl Outbox {
+    pub fn new(writer: Box<dyn TransportWriter>) -> Outbox {
+        Outbox {
+            writer: Arc::new(Mutex::new(NumberedWriter {
+                writer,
+                next_seq: 0,
+                compress: false,
+                codec: Codec::Cbor,
+            })),
+            error: Arc::new(Mutex::new(None)),
+        }
+    }
+    // For messages the bridge makes up itself, which always encode; a failure is logged.
+    pub fn send(&self, msg: &impl Serialize) {
+        if let Err(err) = self.try_send(msg) {
+            error!("Could not encode a message: {}", err);
+        }
+    }
+    // Nothing is sent when the message can't be encoded.
+    pub fn try_send(&self, msg: &impl Serialize) -> Result<(), CodecError> {
+        let codec = self.writer.lock().unwrap().codec;
+        self.send_payload(&codec.encode(msg)?);
+        Ok(())
+    }
+    pub fn send_payload(&self, payload: &[u8]) {
+        let mut error = self.error.lock().unwrap();
+        if error.is_some() {
+            return;
+        }
+        let mut writer = self.writer.lock().unwrap();
+        let frame = encode_frame(writer.next_seq, payload, writer.compress);
+        writer.next_seq = writer.next_seq.wrapping_add(1);
+        if let Err(err) = writer.writer.write_all(&frame) {
+            *error = Some(err);
+        }
+    }
+    pub fn take_error(&self) -> Option<TransportError> {
+        self.error.lock().unwrap().take()
+    }
+    // Large frames sent from now on are compressed. Only for once the other side has agreed.
+    pub fn enable_compression(&self) {
+        self.writer.lock().unwrap().compress = true;
+    }
+    // Messages sent from now on use this codec. Raw payloads are sent as they are.
+    pub fn set_codec(&self, codec: Codec) {
+        self.writer.lock().unwrap().codec = codec;
+    }
+}
@@ -0,0 +1,80 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+// Writes bulk transfers to a pcap file that Wireshark can open, using the Linux usbmon link type
+// whatever the platform. Each transfer is one packet: a write as the submission carrying the
+// outgoing data, a read as the completion carrying the incoming data.
+#[derive(Clone, Debug)]
+pub struct Capture {
+    file: Arc<Mutex<CaptureFile>>,
+}
+
+#[derive(Debug)]
+struct CaptureFile {
+    out: BufWriter<File>,
+    next_id: u64,
+}
+
+impl Capture {
+    const LINKTYPE_USB_LINUX: u32 = 189;
+    const SNAPLEN: usize = 1 << 18;
+    const USB_HEADER_LEN: usize = 48;
+    const TRANSFER_BULK: u8 = 3;
+
+    pub fn create(path: &Path) -> io::Result<Capture> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(&0xa1b2_c3d4u32.to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&4u16.to_le_bytes())?;
+        out.write_all(&0i32.to_le_bytes())?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(&(Capture::SNAPLEN as u32).to_le_bytes())?;
+        out.write_all(&Capture::LINKTYPE_USB_LINUX.to_le_bytes())?;
+        out.flush()?;
+        Ok(Capture {
+            file: Arc::new(Mutex::new(CaptureFile { out, next_id: 0 })),
+        })
+    }
+    pub fn record(&self, bus: u8, address: u8, endpoint: u8, data: &[u8]) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let captured = data.len().min(Capture::SNAPLEN - Capture::USB_HEADER_LEN);
+        let mut file = self.file.lock().unwrap();
+        let id = file.next_id;
+        file.next_id += 1;
+
+        let mut packet = Vec::with_capacity(16 + Capture::USB_HEADER_LEN + captured);
+        packet.extend_from_slice(&(now.as_secs() as u32).to_le_bytes());
+        packet.extend_from_slice(&now.subsec_micros().to_le_bytes());
+        packet.extend_from_slice(&((Capture::USB_HEADER_LEN + captured) as u32).to_le_bytes());
+        packet.extend_from_slice(&((Capture::USB_HEADER_LEN + data.len()) as u32).to_le_bytes());
+
+        packet.extend_from_slice(&id.to_le_bytes());
+        packet.push(if endpoint & 0x80 != 0 { b'C' } else { b'S' });
+        packet.push(Capture::TRANSFER_BULK);
+        packet.push(endpoint);
+        packet.push(address);
+        packet.extend_from_slice(&u16::from(bus).to_le_bytes());
+        // No setup packet; the data is present.
+        packet.push(b'-');
+        packet.push(0);
+        packet.extend_from_slice(&(now.as_secs() as i64).to_le_bytes());
+        packet.extend_from_slice(&(now.subsec_micros() as i32).to_le_bytes());
+        packet.extend_from_slice(&0i32.to_le_bytes());
+        packet.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        packet.extend_from_slice(&(captured as u32).to_le_bytes());
+        packet.extend_from_slice(&[0; 8]);
+        packet.extend_from_slice(&data[..captured]);
+
+        // Flushed every time so the capture is complete up to the moment something goes wrong.
+        let result = file.out.write_all(&packet).and_then(|()| file.out.flush());
+        if let Err(err) = result {
+            warn!("Could not write to the capture file: {}", err);
+        }
+    }
+}
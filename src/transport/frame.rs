@@ -0,0 +1,311 @@
+use super::{Transport, TransportError};
+use std::convert::TryInto;
+use tracing::warn;
+
+// Every message travels in a frame:
+//
+//   magic (2 bytes) | version | flags | sequence number (u32) | length (u32) | payload | CRC32
+//
+// Integers are little-endian. The CRC covers everything after the magic, up to the end of the
+// payload as sent. Each side numbers its frames from 0 at the start of every connection, so the
+// receiver can tell when frames went missing.
+//
+// The only flag is FLAG_COMPRESSED: the payload is LZ4 compressed, prefixed with its uncompressed
+// length (u32). Frames are only sent compressed once the handshake has agreed to it, but flagged
+// frames are always understood.
+pub const FRAME_MAGIC: [u8; 2] = *b"cc";
+pub const FRAME_VERSION: u8 = 2;
+pub const MAX_FRAME_LEN: usize = 16 << 20;
+const HEADER_LEN: usize = 12;
+const CRC_LEN: usize = 4;
+const FLAG_COMPRESSED: u8 = 1;
+// Below this, compression saves too little to be worth the time on either end.
+const COMPRESS_THRESHOLD: usize = 512;
+
+pub struct Frame {
+    pub seq: u32,
+    pub payload: Vec<u8>,
+}
+
+pub fn encode_frame(seq: u32, payload: &[u8], compress: bool) -> Vec<u8> {
+    let mut flags = 0;
+    let compressed;
+    let mut payload = payload;
+    if compress && payload.len() >= COMPRESS_THRESHOLD {
+        compressed = lz4_flex::compress_prepend_size(payload);
+        if compressed.len() < payload.len() {
+            flags |= FLAG_COMPRESSED;
+            payload = &compressed;
+        }
+    }
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len() + CRC_LEN);
+    frame.extend_from_slice(&FRAME_MAGIC);
+    frame.push(FRAME_VERSION);
+    frame.push(flags);
+    frame.extend_from_slice(&seq.to_le_bytes());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(payload);
+    let crc = crc32fast::hash(&frame[FRAME_MAGIC.len()..]);
+    frame.extend_from_slice(&crc.to_le_bytes());
+    frame
+}
+
+// Returns the next intact frame. Until a plausible header turns up the stream is scanned a byte
+// at a time. A frame with a bad checksum is scanned again from the byte after its magic, so the
+// frames its corrupted length swallowed are found too. While a body arrives, it is watched for an
+// intact frame starting inside it: one that is complete first means the length was corrupted, and
+// reading goes on from there rather than waiting for bytes that may never come.
+//
+// Bytes read past the frame that is returned are left in the transport for the next call.
+pub fn read_frame(conn: &mut impl Transport) -> Result<Frame, TransportError> {
+    let mut buf = std::mem::take(conn.unread());
+    let result = scan(conn, &mut buf);
+    *conn.unread() = buf;
+    result
+}
+
+fn scan(conn: &mut impl Transport, buf: &mut Vec<u8>) -> Result<Frame, TransportError> {
+    // Where the frame being looked at starts in `buf`.
+    let mut start = 0;
+    let mut skipped = 0;
+    loop {
+        // Everything before `start` has been given up on. It goes once it is half of what is held,
+        // so a noisy link can't grow the buffer and skipping a byte doesn't move all the others.
+        if start > 0 && start >= buf.len() / 2 {
+            buf.drain(..start);
+            start = 0;
+        }
+        fill(conn, buf, start + HEADER_LEN)?;
+        let end = match frame_end(&buf[start..]) {
+            Some(len) => start + len,
+            None => {
+                start += 1;
+                skipped += 1;
+                continue;
+            }
+        };
+        // Frames starting inside this one that are still waiting for the rest of their bytes.
+        let mut inner: Vec<(usize, usize)> = vec![];
+        let mut probe = start + 1;
+        let mut found = None;
+        while buf.len() < end && found.is_none() {
+            read_more(conn, buf, end - buf.len())?;
+            while probe < end && probe + HEADER_LEN <= buf.len() {
+                if let Some(len) = frame_end(&buf[probe..]) {
+                    inner.push((probe, probe + len));
+                }
+                probe += 1;
+            }
+            found = inner
+                .iter()
+                .find(|&&(from, to)| to <= buf.len() && intact(&buf[from..to]))
+                .map(|&(from, _)| from);
+            inner.retain(|&(_, to)| to > buf.len());
+        }
+        if let Some(from) = found {
+            warn!("Dropped a frame header with a corrupted length");
+            skipped += from - start;
+            start = from;
+            continue;
+        }
+        if !intact(&buf[start..end]) {
+            warn!("Dropped a frame with a bad checksum");
+            start += 1;
+            skipped += 1;
+            continue;
+        }
+        if skipped > 0 {
+            warn!("Skipped {} bytes to find the start of a frame", skipped);
+            skipped = 0;
+        }
+        let header = &buf[start..start + HEADER_LEN];
+        let seq = u32::from_le_bytes(header[4..8].try_into().unwrap());
+        let compressed = header[3] & FLAG_COMPRESSED != 0;
+        let body = &buf[start + HEADER_LEN..end - CRC_LEN];
+        let payload = if compressed {
+            decompress(body)
+        } else {
+            Some(body.to_vec())
+        };
+        buf.drain(..end);
+        start = 0;
+        match payload {
+            Some(payload) => return Ok(Frame { seq, payload }),
+            // The checksum matched, so the sender compressed it wrong. Nothing better to do than
+            // treat it as lost.
+            None => warn!("Dropped frame {} that failed to decompress", seq),
+        }
+    }
+}
+
+// The length of the whole frame whose header `buf` starts with, if that looks like a header.
+fn frame_end(buf: &[u8]) -> Option<usize> {
+    let header = buf.get(..HEADER_LEN)?;
+    let len = u32::from_le_bytes(header[8..].try_into().unwrap()) as usize;
+    if header[..2] != FRAME_MAGIC || header[2] != FRAME_VERSION || len > MAX_FRAME_LEN {
+        return None;
+    }
+    Some(HEADER_LEN + len + CRC_LEN)
+}
+
+// Whether the checksum at the end of `frame` matches.
+fn intact(frame: &[u8]) -> bool {
+    let (covered, crc) = frame.split_at(frame.len() - CRC_LEN);
+    crc32fast::hash(&covered[FRAME_MAGIC.len()..]) == u32::from_le_bytes(crc.try_into().unwrap())
+}
+
+fn fill(conn: &mut impl Transport, buf: &mut Vec<u8>, len: usize) -> Result<(), TransportError> {
+    while buf.len() < len {
+        read_more(conn, buf, len - buf.len())?;
+    }
+    Ok(())
+}
+
+// Appends whatever has arrived, up to `max` bytes, so nothing past what is needed is read.
+fn read_more(
+    conn: &mut impl Transport,
+    buf: &mut Vec<u8>,
+    max: usize,
+) -> Result<(), TransportError> {
+    let old_len = buf.len();
+    buf.resize(old_len + max, 0);
+    let result = conn.read_some(&mut buf[old_len..]);
+    buf.truncate(old_len + *result.as_ref().unwrap_or(&0));
+    result.map(|_| ())
+}
+
+// Decodes a frame that is already known to be exactly `buf`, without any resyncing.
+pub fn decode_frame(buf: &[u8]) -> Option<Frame> {
+    if frame_end(buf)? != buf.len() || !intact(buf) {
+        return None;
+    }
+    let header = &buf[..HEADER_LEN];
+    let payload = &buf[HEADER_LEN..buf.len() - CRC_LEN];
+    Some(Frame {
+        seq: u32::from_le_bytes(header[4..8].try_into().unwrap()),
+        payload: if header[3] & FLAG_COMPRESSED == 0 {
+            payload.to_vec()
+        } else {
+            decompress(payload)?
+        },
+    })
+}
+
+fn decompress(body: &[u8]) -> Option<Vec<u8>> {
+    // Checked first so a bogus length can't make us allocate more than a frame could hold.
+    let len = u32::from_le_bytes(body.get(..4)?.try_into().unwrap()) as usize;
+    if len > MAX_FRAME_LEN {
+        return None;
+    }
+    lz4_flex::decompress_size_prepended(body).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::TransportWriter;
+    use std::io;
+    use std::time::Duration;
+
+    // Hands out what it was given a few bytes at a time, then fails as a closed connection would.
+    struct Stream {
+        data: Vec<u8>,
+        pos: usize,
+        unread: Vec<u8>,
+    }
+
+    impl Stream {
+        fn new(data: Vec<u8>) -> Stream {
+            Stream {
+                data,
+                pos: 0,
+                unread: vec![],
+            }
+        }
+    }
+
+    impl Transport for Stream {
+        fn kind(&self) -> &'static str {
+            "test"
+        }
+        fn read_all(&mut self, buf: &mut [u8]) -> Result<(), TransportError> {
+            let mut filled = 0;
+            while filled < buf.len() {
+                filled += self.read_some(&mut buf[filled..])?;
+            }
+            Ok(())
+        }
+        fn read_some(&mut self, buf: &mut [u8]) -> Result<usize, TransportError> {
+            let len = buf.len().min(7).min(self.data.len() - self.pos);
+            if len == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+            buf[..len].copy_from_slice(&self.data[self.pos..self.pos + len]);
+            self.pos += len;
+            Ok(len)
+        }
+        fn unread(&mut self) -> &mut Vec<u8> {
+            &mut self.unread
+        }
+        fn write_all(&mut self, _buf: &[u8]) -> Result<(), TransportError> {
+            Ok(())
+        }
+        fn set_idle_timeout(&mut self, _timeout: Option<Duration>) -> Result<(), TransportError> {
+            Ok(())
+        }
+        fn writer(&mut self) -> Result<Box<dyn TransportWriter>, TransportError> {
+            Err(io::Error::new(io::ErrorKind::Other, "not a real connection").into())
+        }
+    }
+
+    fn frames(data: Vec<u8>) -> Vec<(u32, Vec<u8>)> {
+        let mut stream = Stream::new(data);
+        let mut frames = vec![];
+        while let Ok(frame) = read_frame(&mut stream) {
+            frames.push((frame.seq, frame.payload));
+        }
+        frames
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let small = b"hello".to_vec();
+        let large = b"abcd".repeat(1000);
+        let mut data = encode_frame(0, &small, true);
+        let compressed = encode_frame(1, &large, true);
+        assert!(compressed.len() < large.len());
+        assert_eq!(decode_frame(&compressed).unwrap().payload, large);
+        data.extend_from_slice(&compressed);
+        data.extend(encode_frame(2, &large, false));
+        assert_eq!(frames(data), [(0, small), (1, large.clone()), (2, large)]);
+    }
+
+    #[test]
+    fn corrupt_frames_are_dropped() {
+        let mut corrupt = encode_frame(0, b"lost", false);
+        corrupt[HEADER_LEN] ^= 1;
+        assert!(decode_frame(&corrupt).is_none());
+        corrupt.extend(encode_frame(1, b"kept", false));
+        assert_eq!(frames(corrupt), [(1, b"kept".to_vec())]);
+    }
+
+    #[test]
+    fn truncated_frames_are_not_returned() {
+        let frame = encode_frame(0, b"cut short", false);
+        let cut = frame[..frame.len() - 1].to_vec();
+        assert!(decode_frame(&cut).is_none());
+        assert!(frames(cut).is_empty());
+    }
+
+    #[test]
+    fn garbage_is_skipped_and_let_go_of() {
+        // Includes bytes that look like the start of a header.
+        let mut data = b"noise cc\x02".repeat(2000);
+        data.extend(encode_frame(7, b"found", false));
+        assert_eq!(frames(data), [(7, b"found".to_vec())]);
+
+        let mut stream = Stream::new(b"noise cc\x02".repeat(2000));
+        assert!(read_frame(&mut stream).is_err());
+        assert!(stream.unread().len() < 4 * HEADER_LEN);
+    }
+}
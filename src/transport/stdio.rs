@@ -0,0 +1,56 @@
+use super::{Transport, TransportError, TransportWriter};
+use std::io::{self, Read, Stdin, Stdout, Write};
+use std::time::Duration;
+
+pub struct StdioTransport {
+    stdin: Stdin,
+    stdout: Stdout,
+    unread: Vec<u8>,
+}
+
+impl StdioTransport {
+    pub fn new() -> StdioTransport {
+        StdioTransport {
+            stdin: io::stdin(),
+            stdout: io::stdout(),
+            unread: vec![],
+        }
+    }
+}
+
+impl Transport for StdioTransport {
+    fn kind(&self) -> &'static str {
+        "stdio"
+    }
+    fn read_all(&mut self, buf: &mut [u8]) -> Result<(), TransportError> {
+        Ok(self.stdin.lock().read_exact(buf)?)
+    }
+    fn read_some(&mut self, buf: &mut [u8]) -> Result<usize, TransportError> {
+        match self.stdin.lock().read(buf)? {
+            0 => Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+            read => Ok(read),
+        }
+    }
+    fn unread(&mut self) -> &mut Vec<u8> {
+        &mut self.unread
+    }
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), TransportError> {
+        TransportWriter::write_all(&mut self.stdout, buf)
+    }
+    // Stdin can't be read with a timeout, but the other end going away closes it, which already
+    // ends the session.
+    fn set_idle_timeout(&mut self, _timeout: Option<Duration>) -> Result<(), TransportError> {
+        Ok(())
+    }
+    fn writer(&mut self) -> Result<Box<dyn TransportWriter>, TransportError> {
+        Ok(Box::new(io::stdout()))
+    }
+}
+
+impl TransportWriter for Stdout {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), TransportError> {
+        let mut stdout = self.lock();
+        stdout.write_all(buf)?;
+        Ok(stdout.flush()?)
+    }
+}
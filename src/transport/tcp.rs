@@ -0,0 +1,121 @@
+use super::{Transport, TransportError, TransportWriter};
+use crate::shutdown;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream};
+use std::time::{Duration, Instant};
+
+// Reads on the socket time out every `shutdown::POLL` so a shutdown is noticed, and the idle
+// timeout is kept track of here instead.
+pub struct TcpTransport {
+    stream: TcpStream,
+    idle_timeout: Option<Duration>,
+    unread: Vec<u8>,
+}
+
+impl TcpTransport {
+    pub fn new(stream: TcpStream) -> std::io::Result<TcpTransport> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(shutdown::POLL))?;
+        Ok(TcpTransport {
+            stream,
+            idle_timeout: None,
+            unread: vec![],
+        })
+    }
+    pub fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+}
+
+impl Transport for TcpTransport {
+    fn kind(&self) -> &'static str {
+        "tcp"
+    }
+    fn read_all(&mut self, buf: &mut [u8]) -> Result<(), TransportError> {
+        let mut read = 0;
+        while read < buf.len() {
+            read += self.read_some(&mut buf[read..])?;
+        }
+        Ok(())
+    }
+    fn read_some(&mut self, buf: &mut [u8]) -> Result<usize, TransportError> {
+        let started = Instant::now();
+        loop {
+            match self.stream.read(buf) {
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into()),
+                Ok(bytes) => return Ok(bytes),
+                Err(err)
+                    if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut =>
+                {
+                    if shutdown::requested() {
+                        return Err(TransportError::Shutdown);
+                    }
+                    match self.idle_timeout {
+                        Some(timeout) if started.elapsed() >= timeout => {
+                            return Err(TransportError::IdleTimeout)
+                        }
+                        _ => {}
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+    fn unread(&mut self) -> &mut Vec<u8> {
+        &mut self.unread
+    }
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), TransportError> {
+        Ok(Write::write_all(&mut self.stream, buf)?)
+    }
+    fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<(), TransportError> {
+        self.idle_timeout = timeout;
+        Ok(())
+    }
+    fn writer(&mut self) -> Result<Box<dyn TransportWriter>, TransportError> {
+        Ok(Box::new(self.stream.try_clone()?))
+    }
+}
+
+// The listening socket systemd passed in, when the bridge was started by socket activation. Only
+// the first socket is used.
+#[cfg(unix)]
+pub fn systemd_listener() -> Option<TcpListener> {
+    use std::os::unix::io::{FromRawFd, RawFd};
+    const LISTEN_FDS_START: RawFd = 3;
+    let pid: u32 = std::env::var("LISTEN_PID").ok()?.parse().ok()?;
+    let fds: u32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+    if pid != std::process::id() || fds == 0 {
+        return None;
+    }
+    // Taken out of the environment so processes the bridge starts, like TBP bots, don't think
+    // the sockets are theirs, and the socket isn't inherited by them either.
+    for var in &["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        std::env::remove_var(var);
+    }
+    unsafe {
+        libc::fcntl(LISTEN_FDS_START, libc::F_SETFD, libc::FD_CLOEXEC);
+        Some(TcpListener::from_raw_fd(LISTEN_FDS_START))
+    }
+}
+
+#[cfg(not(unix))]
+pub fn systemd_listener() -> Option<TcpListener> {
+    None
+}
+
+// Where to connect to reach a listener bound to `addr` from this machine, e.g. to wake up a thread
+// blocked accepting on it.
+pub fn loopback(addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => (Ipv4Addr::LOCALHOST, addr.port()).into(),
+        IpAddr::V6(ip) if ip.is_unspecified() => (Ipv6Addr::LOCALHOST, addr.port()).into(),
+        _ => addr,
+    }
+}
+
+impl TransportWriter for TcpStream {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), TransportError> {
+        Ok(Write::write_all(self, buf)?)
+    }
+}
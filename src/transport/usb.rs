@@ -0,0 +1,911 @@
+use super::{Capture, Transport, TransportError, TransportWriter};
+use crate::monitor::Traffic;
+use crate::shutdown;
+use rusb::UsbContext;
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvError, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+#[derive(Debug)]
+pub enum SwitchConnectionError {
+    // The device matches but none of its interfaces does, which is what a console looks like
+    // when the homebrew isn't running.
+    NoHomebrew,
+    NoInterfaceDescriptor,
+    NoInEndpoint,
+    NoOutEndpoint,
+    // The interface didn't answer the identification request with the magic.
+    NotIdentified,
+    // No permission to open the device.
+    PermissionDenied,
+    // Another program has the interface claimed.
+    Busy,
+    // A kernel driver is bound to the interface and has to be detached first.
+    KernelDriver(u8),
+    RusbError(rusb::Error),
+}
+
+impl From<rusb::Error> for SwitchConnectionError {
+    fn from(err: rusb::Error) -> SwitchConnectionError {
+        match err {
+            rusb::Error::Access => SwitchConnectionError::PermissionDenied,
+            rusb::Error::Busy => SwitchConnectionError::Busy,
+            err => SwitchConnectionError::RusbError(err),
+        }
+    }
+}
+
+impl SwitchConnectionError {
+    // What to do about the error, for the logs and `list-devices`.
+    pub fn remediation(&self, filter: &DeviceFilter) -> String {
+        match self {
+            SwitchConnectionError::PermissionDenied => format!(
+                "permission denied. On Linux, add a udev rule such as \
+                 `SUBSYSTEM==\"usb\", ATTRS{{idVendor}}==\"{:04x}\", \
+                 ATTRS{{idProduct}}==\"{:04x}\", MODE=\"0666\"` and replug the console, or run \
+                 the bridge as root.",
+                filter.vendor_id, filter.product_id
+            ),
+            SwitchConnectionError::Busy => {
+                "the interface is claimed by another program, probably a bridge that is already \
+                 running. Stop it, or pin each bridge to its own console with --serial."
+                    .to_owned()
+            }
+            SwitchConnectionError::KernelDriver(interface) => format!(
+                "a kernel driver is bound to interface {}. Unbind it (e.g. through \
+                 /sys/bus/usb/drivers/<driver>/unbind) or blacklist the driver for this device.",
+                interface
+            ),
+            SwitchConnectionError::RusbError(rusb::Error::NotSupported) => {
+                "no usable driver. On Windows, install the WinUSB driver for this device (e.g. \
+                 with Zadig)."
+                    .to_owned()
+            }
+            SwitchConnectionError::NoHomebrew | SwitchConnectionError::NoInterfaceDescriptor => {
+                "no interface matches. Start the cold clear homebrew on the console, and check \
+                 the interface filter if it is running."
+                    .to_owned()
+            }
+            SwitchConnectionError::NoInEndpoint | SwitchConnectionError::NoOutEndpoint => {
+                "no matching interface has both bulk endpoints, so none of them is the \
+                 homebrew's."
+                    .to_owned()
+            }
+            SwitchConnectionError::NotIdentified => {
+                "the device didn't identify itself as the cold clear client. It may be running \
+                 another USB tool, or a client too old to support --identify."
+                    .to_owned()
+            }
+            SwitchConnectionError::RusbError(err) => err.to_string(),
+        }
+    }
+}
+
+// Busy from claiming or configuring is a kernel driver if one is bound to the interface, and
+// another program otherwise.
+fn claim_error(
+    handle: &rusb::DeviceHandle<rusb::Context>,
+    interface: u8,
+    err: rusb::Error,
+) -> SwitchConnectionError {
+    match err {
+        rusb::Error::Busy if handle.kernel_driver_active(interface).unwrap_or(false) => {
+            SwitchConnectionError::KernelDriver(interface)
+        }
+        err => err.into(),
+    }
+}
+
+// Which USB devices are treated as a switch running the homebrew.
+#[derive(Clone, Debug)]
+pub struct DeviceFilter {
+    pub vendor_id: u16,
+    pub product_id: u16,
+    // Pins the bridge to one device when several share the IDs (e.g. a Pro Controller).
+    pub serial: Option<String>,
+    pub interface: InterfaceFilter,
+}
+
+// Which of the device's interfaces carries the protocol. Unset fields match anything, so by
+// default the first interface with a pair of bulk endpoints is used.
+#[derive(Clone, Debug, Default)]
+pub struct InterfaceFilter {
+    pub class: Option<u8>,
+    pub subclass: Option<u8>,
+    pub protocol: Option<u8>,
+    // The interface's string descriptor.
+    pub name: Option<String>,
+    // Ask the interface to identify itself before claiming it. Off by default because clients
+    // built before the identification request existed don't answer it.
+    pub identify: bool,
+}
+
+impl InterfaceFilter {
+    pub fn matches(
+        &self,
+        handle: &rusb::DeviceHandle<rusb::Context>,
+        interface_desc: &rusb::InterfaceDescriptor,
+    ) -> bool {
+        let codes_match = self
+            .class
+            .map_or(true, |class| interface_desc.class_code() == class)
+            && self
+                .subclass
+                .map_or(true, |subclass| interface_desc.sub_class_code() == subclass)
+            && self
+                .protocol
+                .map_or(true, |protocol| interface_desc.protocol_code() == protocol);
+        match &self.name {
+            Some(name) if codes_match => {
+                interface_name(handle, interface_desc).as_ref() == Some(name)
+            }
+            _ => codes_match,
+        }
+    }
+}
+
+fn interface_name(
+    handle: &rusb::DeviceHandle<rusb::Context>,
+    interface_desc: &rusb::InterfaceDescriptor,
+) -> Option<String> {
+    const TIMEOUT: Duration = Duration::from_millis(500);
+    interface_desc.description_string_index()?;
+    let language = *handle.read_languages(TIMEOUT).ok()?.first()?;
+    handle
+        .read_interface_string(language, interface_desc, TIMEOUT)
+        .ok()
+}
+
+// A vendor request to the interface that only the cold clear client answers with the magic, so
+// the bridge never claims a console that is exposing USB for another tool (e.g. Goldleaf).
+// Anything else, including the request being stalled, means it's not the client.
+fn identify(
+    handle: &rusb::DeviceHandle<rusb::Context>,
+    interface: u8,
+) -> Result<(), SwitchConnectionError> {
+    const TIMEOUT: Duration = Duration::from_secs(1);
+    let request_type = rusb::request_type(
+        rusb::Direction::In,
+        rusb::RequestType::Vendor,
+        rusb::Recipient::Interface,
+    );
+    let mut buf = [0; 16];
+    match handle.read_control(
+        request_type,
+        SwitchConnection::IDENTIFY_REQUEST,
+        0,
+        u16::from(interface),
+        &mut buf,
+        TIMEOUT,
+    ) {
+        Ok(len) if &buf[..len] == SwitchConnection::IDENTIFY_MAGIC => Ok(()),
+        Ok(_) | Err(rusb::Error::Pipe) | Err(rusb::Error::Timeout) => {
+            Err(SwitchConnectionError::NotIdentified)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+// The first bulk IN and OUT endpoints of the interface.
+fn bulk_endpoints(
+    interface_desc: &rusb::InterfaceDescriptor,
+) -> Result<(u8, u8), SwitchConnectionError> {
+    let mut endpoint_in = None;
+    let mut endpoint_out = None;
+    for endpoint_desc in interface_desc.endpoint_descriptors() {
+        if endpoint_desc.transfer_type() != rusb::TransferType::Bulk {
+            continue;
+        }
+        let endpoint = match endpoint_desc.direction() {
+            rusb::Direction::In => &mut endpoint_in,
+            rusb::Direction::Out => &mut endpoint_out,
+        };
+        if endpoint.is_none() {
+            *endpoint = Some(endpoint_desc.address());
+        }
+    }
+    match (endpoint_in, endpoint_out) {
+        (Some(endpoint_in), Some(endpoint_out)) => Ok((endpoint_in, endpoint_out)),
+        (None, _) => Err(SwitchConnectionError::NoInEndpoint),
+        (_, None) => Err(SwitchConnectionError::NoOutEndpoint),
+    }
+}
+
+impl Default for DeviceFilter {
+    fn default() -> DeviceFilter {
+        DeviceFilter {
+            vendor_id: SwitchConnection::SWITCH_VENDOR_ID,
+            product_id: SwitchConnection::SWITCH_PRODUCT_ID,
+            serial: None,
+            interface: InterfaceFilter::default(),
+        }
+    }
+}
+
+impl DeviceFilter {
+    // Devices whose serial number can't be read don't match a filter that has one.
+    pub fn matches(
+        &self,
+        device: &rusb::Device<rusb::Context>,
+        device_desc: &rusb::DeviceDescriptor,
+    ) -> bool {
+        if device_desc.vendor_id() != self.vendor_id || device_desc.product_id() != self.product_id
+        {
+            return false;
+        }
+        match &self.serial {
+            Some(serial) => {
+                read_serial(device, device_desc).ok().flatten().as_ref() == Some(serial)
+            }
+            None => true,
+        }
+    }
+}
+
+// The serial number is a string descriptor, so reading it means opening the device.
+fn read_serial(
+    device: &rusb::Device<rusb::Context>,
+    device_desc: &rusb::DeviceDescriptor,
+) -> rusb::Result<Option<String>> {
+    const TIMEOUT: Duration = Duration::from_millis(500);
+    if device_desc.serial_number_string_index().is_none() {
+        return Ok(None);
+    }
+    let handle = device.open()?;
+    let language = match handle.read_languages(TIMEOUT)?.first() {
+        Some(&language) => language,
+        None => return Ok(None),
+    };
+    handle
+        .read_serial_number_string(language, device_desc, TIMEOUT)
+        .map(Some)
+}
+
+// `transfer` bounds each libusb transfer, so nothing ever blocks indefinitely inside libusb.
+// `write_deadline` bounds a whole write: a console that stops reading fails the write (ending the
+// session, after which the device is reopened) instead of stalling the bridge forever.
+#[derive(Clone, Copy, Debug)]
+pub struct UsbTimeouts {
+    pub transfer: Duration,
+    pub write_deadline: Duration,
+}
+
+impl Default for UsbTimeouts {
+    fn default() -> UsbTimeouts {
+        UsbTimeouts {
+            transfer: Duration::from_secs(1),
+            write_deadline: Duration::from_secs(10),
+        }
+    }
+}
+
+// Transfers on one connection, kept across reopening the same device. Reads time out whenever the
+// console has nothing to send, so only write timeouts mean the console is falling behind.
+#[derive(Clone, Debug)]
+pub struct UsbStats {
+    since: Instant,
+    counters: Arc<UsbCounters>,
+}
+
+#[derive(Debug, Default)]
+struct UsbCounters {
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    transfers_in: AtomicU64,
+    transfers_out: AtomicU64,
+    read_timeouts: AtomicU64,
+    write_timeouts: AtomicU64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct UsbStatsReport {
+    pub seconds: f64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub transfers_in: u64,
+    pub transfers_out: u64,
+    pub transfers_per_sec: f64,
+    pub average_transfer_bytes: f64,
+    pub read_timeouts: u64,
+    pub write_timeouts: u64,
+}
+
+impl fmt::Display for UsbStatsReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} bytes in and {} out over {:.0}s, ",
+            self.bytes_in, self.bytes_out, self.seconds
+        )?;
+        write!(
+            f,
+            "{:.1} transfers/s of {:.0} bytes on average, {} write timeouts",
+            self.transfers_per_sec, self.average_transfer_bytes, self.write_timeouts
+        )
+    }
+}
+
+impl UsbStats {
+    fn new() -> UsbStats {
+        UsbStats {
+            since: Instant::now(),
+            counters: Arc::default(),
+        }
+    }
+    pub fn report(&self) -> UsbStatsReport {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let counters = &self.counters;
+        let seconds = self.since.elapsed().as_secs_f64();
+        let transfers = load(&counters.transfers_in) + load(&counters.transfers_out);
+        let bytes = load(&counters.bytes_in) + load(&counters.bytes_out);
+        UsbStatsReport {
+            seconds,
+            bytes_in: load(&counters.bytes_in),
+            bytes_out: load(&counters.bytes_out),
+            transfers_in: load(&counters.transfers_in),
+            transfers_out: load(&counters.transfers_out),
+            transfers_per_sec: transfers as f64 / seconds.max(1.0),
+            average_transfer_bytes: bytes as f64 / transfers.max(1) as f64,
+            read_timeouts: load(&counters.read_timeouts),
+            write_timeouts: load(&counters.write_timeouts),
+        }
+    }
+    fn transfer(&self, incoming: bool, bytes: usize) {
+        let (transfers, total) = if incoming {
+            (&self.counters.transfers_in, &self.counters.bytes_in)
+        } else {
+            (&self.counters.transfers_out, &self.counters.bytes_out)
+        };
+        transfers.fetch_add(1, Ordering::Relaxed);
+        total.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+    fn timeout<T>(&self, incoming: bool, result: &rusb::Result<T>) {
+        if let Err(rusb::Error::Timeout) = result {
+            let counter = if incoming {
+                &self.counters.read_timeouts
+            } else {
+                &self.counters.write_timeouts
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+pub struct SwitchConnection {
+    device: rusb::Device<rusb::Context>,
+    handle: rusb::DeviceHandle<rusb::Context>,
+    interface: u8,
+    endpoint_in: u8,
+    endpoint_out: u8,
+    idle_timeout: Option<Duration>,
+    timeouts: UsbTimeouts,
+    capture: Option<Capture>,
+    traffic: Option<Traffic>,
+    stats: UsbStats,
+    unread: Vec<u8>,
+}
+
+impl SwitchConnection {
+    pub const SWITCH_VENDOR_ID: u16 = 0x057E;
+    pub const SWITCH_PRODUCT_ID: u16 = 0x3000;
+    pub const IDENTIFY_REQUEST: u8 = 0xCC;
+    pub const IDENTIFY_MAGIC: &'static [u8] = b"COLDCLEAR";
+    pub fn find_devices(filter: &DeviceFilter) -> rusb::Result<Vec<rusb::Device<rusb::Context>>> {
+        let mut found = vec![];
+        for device in rusb::Context::new()?.devices()?.iter() {
+            if filter.matches(&device, &device.device_descriptor()?) {
+                found.push(device);
+            }
+        }
+        Ok(found)
+    }
+    pub fn open(
+        device: &rusb::Device<rusb::Context>,
+        interface_filter: &InterfaceFilter,
+    ) -> Result<SwitchConnection, SwitchConnectionError> {
+        // Every connection gets a libusb context of its own, found again by bus and address, so
+        // closing it can't disturb other connections, the hotplug thread, or other users of
+        // libusb in the same process.
+        let context = rusb::Context::new()?;
+        let device = context
+            .devices()?
+            .iter()
+            .find(|other| {
+                other.bus_number() == device.bus_number() && other.address() == device.address()
+            })
+            .ok_or(rusb::Error::NoDevice)?;
+        let mut handle = device.open()?;
+        handle
+            .set_active_configuration(1)
+            .map_err(|err| claim_error(&handle, 0, err))?;
+        let config_desc = device.active_config_descriptor()?;
+        // The homebrew may share the device with other USB services, so every interface and
+        // alternate setting is tried before giving up.
+        let mut err = SwitchConnectionError::NoHomebrew;
+        for interface in config_desc.interfaces() {
+            if interface.descriptors().next().is_none() {
+                err = SwitchConnectionError::NoInterfaceDescriptor;
+            }
+            for interface_desc in interface.descriptors() {
+                if !interface_filter.matches(&handle, &interface_desc) {
+                    continue;
+                }
+                let (endpoint_in, endpoint_out) = match bulk_endpoints(&interface_desc) {
+                    Ok(endpoints) => endpoints,
+                    Err(missing) => {
+                        err = missing;
+                        continue;
+                    }
+                };
+                if interface_filter.identify {
+                    if let Err(missing) = identify(&handle, interface.number()) {
+                        err = missing;
+                        continue;
+                    }
+                }
+                handle
+                    .claim_interface(interface.number())
+                    .map_err(|err| claim_error(&handle, interface.number(), err))?;
+                if interface_desc.setting_number() != 0 {
+                    handle.set_alternate_setting(
+                        interface.number(),
+                        interface_desc.setting_number(),
+                    )?;
+                }
+                return Ok(SwitchConnection {
+                    device,
+                    handle,
+                    interface: interface.number(),
+                    endpoint_in,
+                    endpoint_out,
+                    idle_timeout: None,
+                    timeouts: UsbTimeouts::default(),
+                    capture: None,
+                    traffic: None,
+                    stats: UsbStats::new(),
+                    unread: vec![],
+                });
+            }
+        }
+        Err(err)
+    }
+    pub fn with_timeouts(mut self, timeouts: UsbTimeouts) -> SwitchConnection {
+        self.timeouts = timeouts;
+        self
+    }
+    pub fn with_capture(mut self, capture: Option<Capture>) -> SwitchConnection {
+        self.capture = capture;
+        self
+    }
+    pub fn with_traffic(mut self, traffic: Option<Traffic>) -> SwitchConnection {
+        self.traffic = traffic;
+        self
+    }
+    pub fn device(&self) -> &rusb::Device<rusb::Context> {
+        &self.device
+    }
+    pub fn read(&self, buf: &mut [u8]) -> rusb::Result<usize> {
+        self.read_timeout(buf, self.timeouts.transfer)
+    }
+    pub fn read_timeout(&self, buf: &mut [u8], timeout: Duration) -> rusb::Result<usize> {
+        let result = self.handle.read_bulk(self.endpoint_in, buf, timeout);
+        self.stats.timeout(true, &result);
+        let read = result?;
+        self.observe(self.endpoint_in, &buf[..read]);
+        Ok(read)
+    }
+    pub fn write(&self, buf: &[u8]) -> rusb::Result<usize> {
+        let result = self
+            .handle
+            .write_bulk(self.endpoint_out, buf, self.timeouts.transfer);
+        self.stats.timeout(false, &result);
+        let written = result?;
+        self.observe(self.endpoint_out, &buf[..written]);
+        Ok(written)
+    }
+    pub fn stats(&self) -> UsbStats {
+        self.stats.clone()
+    }
+    fn observe(&self, endpoint: u8, data: &[u8]) {
+        self.stats
+            .transfer(endpoint == self.endpoint_in, data.len());
+        if let Some(traffic) = &self.traffic {
+            if endpoint == self.endpoint_in {
+                traffic.add_read(data.len());
+            } else {
+                traffic.add_written(data.len());
+            }
+        }
+        if let Some(capture) = &self.capture {
+            capture.record(
+                self.device.bus_number(),
+                self.device.address(),
+                endpoint,
+                data,
+            );
+        }
+    }
+    fn write_all_shared(&self, buf: &[u8]) -> rusb::Result<()> {
+        let started = Instant::now();
+        let mut written: usize = 0;
+        while written < buf.len() {
+            match self.write(&buf[written..]) {
+                Ok(bytes) => written += bytes,
+                Err(rusb::Error::Timeout) if started.elapsed() < self.timeouts.write_deadline => {}
+                Err(rusb::Error::Timeout) => {
+                    warn!(
+                        "The switch hasn't accepted any data for {} seconds, giving up on it",
+                        self.timeouts.write_deadline.as_secs()
+                    );
+                    return Err(rusb::Error::Timeout);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+    pub fn pipelined(self) -> PipelinedConnection {
+        PipelinedConnection::new(self)
+    }
+}
+
+impl Transport for SwitchConnection {
+    fn kind(&self) -> &'static str {
+        "usb"
+    }
+    fn read_all(&mut self, buf: &mut [u8]) -> Result<(), TransportError> {
+        let mut read: usize = 0;
+        while read < buf.len() {
+            read += self.read_some(&mut buf[read..])?;
+        }
+        Ok(())
+    }
+    fn read_some(&mut self, buf: &mut [u8]) -> Result<usize, TransportError> {
+        let started = Instant::now();
+        loop {
+            match self.read(buf) {
+                Ok(0) => {}
+                Ok(bytes) => return Ok(bytes),
+                Err(rusb::Error::Timeout) if shutdown::requested() => {
+                    return Err(TransportError::Shutdown)
+                }
+                Err(rusb::Error::Timeout) => match self.idle_timeout {
+                    Some(idle_timeout) if started.elapsed() >= idle_timeout => {
+                        return Err(TransportError::IdleTimeout)
+                    }
+                    _ => {}
+                },
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+    fn unread(&mut self) -> &mut Vec<u8> {
+        &mut self.unread
+    }
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), TransportError> {
+        Ok(self.write_all_shared(buf)?)
+    }
+    fn usb_stats(&self) -> Option<UsbStats> {
+        Some(self.stats())
+    }
+    fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<(), TransportError> {
+        self.idle_timeout = timeout;
+        Ok(())
+    }
+    // Only the pipelined connection can be shared between threads.
+    fn writer(&mut self) -> Result<Box<dyn TransportWriter>, TransportError> {
+        Err(rusb::Error::NotSupported.into())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum HotplugEvent {
+    Arrived { bus: u8, address: u8 },
+    Left { bus: u8, address: u8 },
+}
+
+// Dropping the watcher stops its thread, which deregisters the callback and closes the context.
+pub struct HotplugWatcher {
+    events: Receiver<HotplugEvent>,
+    stop: Arc<AtomicBool>,
+}
+
+impl HotplugWatcher {
+    const STOP_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+    pub fn recv(&self) -> Result<HotplugEvent, RecvError> {
+        self.events.recv()
+    }
+}
+
+impl Drop for HotplugWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+struct HotplugNotifier(Sender<HotplugEvent>);
+
+impl rusb::Hotplug<rusb::Context> for HotplugNotifier {
+    fn device_arrived(&mut self, device: rusb::Device<rusb::Context>) {
+        let bus = device.bus_number();
+        let address = device.address();
+        self.0.send(HotplugEvent::Arrived { bus, address }).ok();
+    }
+    fn device_left(&mut self, device: rusb::Device<rusb::Context>) {
+        let bus = device.bus_number();
+        let address = device.address();
+        self.0.send(HotplugEvent::Left { bus, address }).ok();
+    }
+}
+
+impl SwitchConnection {
+    // Returns None when libusb doesn't support hotplug on this platform. libusb only delivers
+    // hotplug callbacks while events are being handled, so a thread is dedicated to that.
+    pub fn watch_hotplug(filter: &DeviceFilter) -> Option<HotplugWatcher> {
+        if !rusb::has_hotplug() {
+            return None;
+        }
+        let (send, events) = channel();
+        let (send_ready, ready) = channel();
+        let filter = filter.clone();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        std::thread::spawn(move || {
+            let context = match rusb::Context::new() {
+                Ok(context) => context,
+                Err(err) => {
+                    send_ready.send(Err(err)).ok();
+                    return;
+                }
+            };
+            let _registration = match context.register_callback(
+                Some(filter.vendor_id),
+                Some(filter.product_id),
+                None,
+                Box::new(HotplugNotifier(send)),
+            ) {
+                Ok(registration) => {
+                    send_ready.send(Ok(())).ok();
+                    registration
+                }
+                Err(err) => {
+                    send_ready.send(Err(err)).ok();
+                    return;
+                }
+            };
+            while !stopped.load(Ordering::Relaxed) {
+                if let Err(err) = context.handle_events(Some(HotplugWatcher::STOP_CHECK_INTERVAL)) {
+                    error!("Error while handling USB events: {:?}", err);
+                }
+            }
+        });
+        match ready.recv() {
+            Ok(Ok(())) => Some(HotplugWatcher { events, stop }),
+            Ok(Err(err)) => {
+                warn!("Could not register for hotplug events: {:?}", err);
+                None
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+// rusb has no asynchronous transfer API, so transfers are overlapped with the rest of the bridge
+// by running them on dedicated threads: the reader keeps reading ahead while commands are being
+// dispatched, and writes return as soon as the response is queued.
+pub struct PipelinedConnection {
+    conn: Arc<SwitchConnection>,
+    incoming: Receiver<rusb::Result<Vec<u8>>>,
+    buffer: Vec<u8>,
+    position: usize,
+    outgoing: Option<Sender<Vec<u8>>>,
+    write_error: Arc<Mutex<Option<rusb::Error>>>,
+    // Buffers queued for the writer thread that it hasn't finished writing.
+    pending: Arc<AtomicUsize>,
+    closed: Arc<AtomicBool>,
+    idle_timeout: Option<Duration>,
+    unread: Vec<u8>,
+}
+
+impl PipelinedConnection {
+    pub const READ_CHUNK: usize = 16 * 1024;
+    pub fn new(conn: SwitchConnection) -> PipelinedConnection {
+        let conn = Arc::new(conn);
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let (send_incoming, incoming) = channel();
+        let reader = conn.clone();
+        let reader_closed = closed.clone();
+        std::thread::spawn(move || {
+            let mut buf = vec![0; PipelinedConnection::READ_CHUNK];
+            // Reads time out regularly so a closed connection releases the device handle even if
+            // the console has stopped sending anything.
+            while !reader_closed.load(Ordering::Relaxed) {
+                let result = match reader.read(&mut buf) {
+                    Ok(read) => Ok(buf[..read].to_vec()),
+                    Err(rusb::Error::Timeout) => continue,
+                    Err(err) => Err(err),
+                };
+                let failed = result.is_err();
+                if send_incoming.send(result).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        let (outgoing, receive_outgoing) = channel::<Vec<u8>>();
+        let writer = conn.clone();
+        let write_error = Arc::new(Mutex::new(None));
+        let writer_error = write_error.clone();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let writer_pending = pending.clone();
+        let writer_closed = closed.clone();
+        // Writers can outlive the connection in jobs that are still running, so the thread stops
+        // when the connection is closed rather than when the last of them is dropped.
+        std::thread::spawn(move || loop {
+            let buf = match receive_outgoing.recv_timeout(shutdown::POLL) {
+                Ok(buf) => buf,
+                Err(RecvTimeoutError::Timeout) if !writer_closed.load(Ordering::Relaxed) => {
+                    continue
+                }
+                Err(_) => break,
+            };
+            if writer_closed.load(Ordering::Relaxed) {
+                break;
+            }
+            let result = writer.write_all_shared(&buf);
+            writer_pending.fetch_sub(1, Ordering::SeqCst);
+            if let Err(err) = result {
+                *writer_error.lock().unwrap() = Some(err);
+                break;
+            }
+        });
+
+        PipelinedConnection {
+            conn,
+            incoming,
+            buffer: vec![],
+            position: 0,
+            outgoing: Some(outgoing),
+            write_error,
+            pending,
+            closed,
+            idle_timeout: None,
+            unread: vec![],
+        }
+    }
+    pub fn device(&self) -> &rusb::Device<rusb::Context> {
+        self.conn.device()
+    }
+    fn queue_writer(&self) -> Option<QueueWriter> {
+        Some(QueueWriter {
+            outgoing: self.outgoing.clone()?,
+            write_error: self.write_error.clone(),
+            pending: self.pending.clone(),
+        })
+    }
+}
+
+struct QueueWriter {
+    outgoing: Sender<Vec<u8>>,
+    write_error: Arc<Mutex<Option<rusb::Error>>>,
+    pending: Arc<AtomicUsize>,
+}
+
+impl QueueWriter {
+    fn take_write_error(&self) -> TransportError {
+        match self.write_error.lock().unwrap().take() {
+            Some(err) => err.into(),
+            None => rusb::Error::NoDevice.into(),
+        }
+    }
+}
+
+impl TransportWriter for QueueWriter {
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), TransportError> {
+        if self.write_error.lock().unwrap().is_some() {
+            return Err(self.take_write_error());
+        }
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        match self.outgoing.send(buf.to_vec()) {
+            Ok(()) => Ok(()),
+            Err(_) => {
+                self.pending.fetch_sub(1, Ordering::SeqCst);
+                Err(self.take_write_error())
+            }
+        }
+    }
+}
+
+impl Transport for PipelinedConnection {
+    fn kind(&self) -> &'static str {
+        "usb"
+    }
+    fn usb_stats(&self) -> Option<UsbStats> {
+        Some(self.conn.stats())
+    }
+    fn read_all(&mut self, buf: &mut [u8]) -> Result<(), TransportError> {
+        let mut read = 0;
+        while read < buf.len() {
+            read += self.read_some(&mut buf[read..])?;
+        }
+        Ok(())
+    }
+    fn read_some(&mut self, buf: &mut [u8]) -> Result<usize, TransportError> {
+        while self.position == self.buffer.len() {
+            // Waits in steps, so a shutdown is noticed even while the console is quiet.
+            let started = Instant::now();
+            let result = loop {
+                match self.incoming.recv_timeout(shutdown::POLL) {
+                    Ok(result) => break result,
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => return Err(rusb::Error::NoDevice.into()),
+                }
+                if shutdown::requested() {
+                    return Err(TransportError::Shutdown);
+                }
+                match self.idle_timeout {
+                    Some(timeout) if started.elapsed() >= timeout => {
+                        return Err(TransportError::IdleTimeout)
+                    }
+                    _ => {}
+                }
+            };
+            self.buffer = result?;
+            self.position = 0;
+        }
+        let available = &self.buffer[self.position..];
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.position += count;
+        Ok(count)
+    }
+    fn unread(&mut self) -> &mut Vec<u8> {
+        &mut self.unread
+    }
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), TransportError> {
+        match self.queue_writer() {
+            Some(mut writer) => writer.write_all(buf),
+            None => Err(rusb::Error::NoDevice.into()),
+        }
+    }
+    fn set_idle_timeout(&mut self, timeout: Option<Duration>) -> Result<(), TransportError> {
+        self.idle_timeout = timeout;
+        Ok(())
+    }
+    fn writer(&mut self) -> Result<Box<dyn TransportWriter>, TransportError> {
+        match self.queue_writer() {
+            Some(writer) => Ok(Box::new(writer)),
+            None => Err(rusb::Error::NoDevice.into()),
+        }
+    }
+    // The writer thread gives up on a console that stops reading after the write deadline, so
+    // waiting any longer than that is pointless.
+    fn close(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.outgoing = None;
+    }
+    fn flush(&mut self) -> Result<(), TransportError> {
+        let deadline = Instant::now() + self.conn.timeouts.write_deadline;
+        while self.pending.load(Ordering::SeqCst) > 0 {
+            if let Some(err) = self.write_error.lock().unwrap().take() {
+                return Err(err.into());
+            }
+            if Instant::now() >= deadline {
+                return Err(rusb::Error::Timeout.into());
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PipelinedConnection {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
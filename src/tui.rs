@@ -0,0 +1,168 @@
+use cc_switch_usb_rs::monitor::{BotStatus, Monitor, Snapshot};
+use cc_switch_usb_rs::shutdown;
+use crossterm::event::{self, Event, KeyCode};
+use crossterm::execute;
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::text::{Span, Spans};
+use ratatui::widgets::{Block, Borders, Paragraph};
+use ratatui::{Frame, Terminal};
+use std::io::{self, Stdout};
+use std::time::{Duration, Instant};
+
+const REFRESH: Duration = Duration::from_millis(250);
+const BOARD_ROWS: i32 = 20;
+// Board plus border, and room for the handle, label and stats next to it.
+const BOT_WIDTH: u16 = 34;
+const BOT_HEIGHT: u16 = BOARD_ROWS as u16 + 2;
+
+// Takes over the terminal until `q` is pressed or the bridge is shutting down, then hands back the
+// terminal and calls `quit`. Logs go to the bottom panel instead of stderr, which would tear up
+// the screen.
+pub fn spawn(monitor: Monitor, quit: fn()) {
+    std::thread::Builder::new()
+        .name("tui".to_owned())
+        .spawn(move || {
+            if let Err(err) = run(&monitor) {
+                restore();
+                eprintln!("The dashboard failed: {}", err);
+            }
+            quit();
+        })
+        .unwrap();
+}
+
+fn run(monitor: &Monitor) -> io::Result<()> {
+    terminal::enable_raw_mode()?;
+    execute!(io::stdout(), EnterAlternateScreen)?;
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        restore();
+        default_hook(info);
+    }));
+    let mut terminal: Terminal<CrosstermBackend<Stdout>> =
+        Terminal::new(CrosstermBackend::new(io::stdout()))?;
+    terminal.clear()?;
+
+    let mut last = (Instant::now(), 0, 0);
+    let mut rates = (0.0, 0.0);
+    loop {
+        let snapshot = monitor.snapshot();
+        let elapsed = last.0.elapsed().as_secs_f64();
+        if elapsed >= 1.0 {
+            rates = (
+                (snapshot.bytes_read - last.1) as f64 / elapsed / 1024.0,
+                (snapshot.bytes_written - last.2) as f64 / elapsed / 1024.0,
+            );
+            last = (Instant::now(), snapshot.bytes_read, snapshot.bytes_written);
+        }
+        terminal.draw(|f| draw(f, &snapshot, rates))?;
+
+        if shutdown::requested() {
+            break;
+        }
+        if event::poll(REFRESH)? {
+            if let Event::Key(key) = event::read()? {
+                if key.code == KeyCode::Char('q') {
+                    break;
+                }
+            }
+        }
+    }
+    restore();
+    Ok(())
+}
+
+fn restore() {
+    let _ = terminal::disable_raw_mode();
+    let _ = execute!(io::stdout(), LeaveAlternateScreen);
+}
+
+fn draw<B: Backend>(f: &mut Frame<B>, snapshot: &Snapshot, (read, written): (f64, f64)) {
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(snapshot.connections.len().max(1) as u16 + 3),
+            Constraint::Length(BOT_HEIGHT),
+            Constraint::Min(3),
+        ])
+        .split(f.size());
+
+    let mut lines: Vec<Spans> = snapshot
+        .connections
+        .iter()
+        .map(|(name, since)| Spans::from(format!("{} (for {}s)", name, since.as_secs())))
+        .collect();
+    if lines.is_empty() {
+        lines.push(Spans::from("Waiting for a console..."));
+    }
+    lines.push(Spans::from(format!(
+        "USB: {:.1} KiB/s in, {:.1} KiB/s out ({} / {} bytes total)",
+        read, written, snapshot.bytes_read, snapshot.bytes_written
+    )));
+    f.render_widget(
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title("Connections")),
+        rows[0],
+    );
+
+    let bots = Block::default()
+        .borders(Borders::ALL)
+        .title(format!("Bots ({})", snapshot.bots.len()));
+    let area = bots.inner(rows[1]);
+    f.render_widget(bots, rows[1]);
+    let fit = (area.width / BOT_WIDTH) as usize;
+    let columns = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![Constraint::Length(BOT_WIDTH); fit])
+        .split(area);
+    for (bot, &column) in snapshot.bots.iter().zip(columns.iter()) {
+        draw_bot(f, bot, column);
+    }
+
+    let height = rows[2].height.saturating_sub(2) as usize;
+    let log: Vec<Spans> = snapshot
+        .log
+        .iter()
+        .skip(snapshot.log.len().saturating_sub(height))
+        .map(|line| Spans::from(line.as_str()))
+        .collect();
+    f.render_widget(
+        Paragraph::new(log).block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Log (q to quit)"),
+        ),
+        rows[2],
+    );
+}
+
+fn draw_bot<B: Backend>(f: &mut Frame<B>, bot: &BotStatus, area: Rect) {
+    let mut lines = vec![];
+    for y in (0..BOARD_ROWS).rev() {
+        let row: String = (0..10)
+            .map(|x| if bot.board.occupied(x, y) { "[]" } else { " ." })
+            .collect();
+        let info = match BOARD_ROWS - 1 - y {
+            0 => format!("#{}", bot.handle),
+            1 => bot.label.clone().unwrap_or_default(),
+            3 => format!("placed {}", bot.pieces_placed),
+            4 => match bot.last_think {
+                Some(think) => format!("think {}ms", think.as_millis()),
+                None => "think -".to_owned(),
+            },
+            5 => match bot.board.hold_piece {
+                Some(piece) => format!("hold {:?}", piece),
+                None => "hold -".to_owned(),
+            },
+            _ => String::new(),
+        };
+        lines.push(Spans::from(vec![
+            Span::raw("|"),
+            Span::raw(row),
+            Span::raw("| "),
+            Span::raw(info),
+        ]));
+    }
+    f.render_widget(Paragraph::new(lines), area);
+}
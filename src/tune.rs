@@ -0,0 +1,169 @@
+use crate::garbage::SplitMix64;
+use crate::protocol::EvaluatorChoice;
+use crate::server::SessionConfig;
+use crate::simulate;
+use crate::versus::{VersusError, VersusRules};
+use cold_clear::evaluation::Standard;
+use serde_json::Value;
+use std::io;
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Debug)]
+pub enum TuneError {
+    Versus(VersusError),
+    Io(PathBuf, io::Error),
+    // The weights couldn't be turned back into an evaluator or written as a preset.
+    Encode(String),
+}
+
+impl From<VersusError> for TuneError {
+    fn from(err: VersusError) -> TuneError {
+        TuneError::Versus(err)
+    }
+}
+
+pub struct TuneOptions {
+    pub generations: u32,
+    pub population: usize,
+    // How many of the best candidates the next generation is drawn around.
+    pub elite: usize,
+    // Games each candidate plays against the current best.
+    pub games: u32,
+    pub start: Standard,
+    // The preset file the best weights so far are written to after every generation.
+    pub out: PathBuf,
+    pub rules: VersusRules,
+}
+
+// The cross-entropy method over every numeric weight of the standard evaluator. Each generation
+// samples candidates from a normal distribution around the current best, scores them by playing
+// against it (wins minus losses, with attack breaking ties), and moves the distribution to the
+// mean and spread of the top `elite`. Every candidate of a generation plays the same seeds.
+pub fn run(config: &SessionConfig, options: &TuneOptions) -> Result<Standard, TuneError> {
+    // The first generation is drawn within about half of each weight either way.
+    const START_SPREAD: f64 = 0.5;
+    const MIN_START_SPREAD: f64 = 10.0;
+    const MIN_SPREAD: f64 = 2.0;
+    let mut rng = SplitMix64(options.rules.seed);
+    let mut mean = weights(&options.start)?;
+    let mut spread: Vec<f64> = mean
+        .iter()
+        .map(|weight| (weight.abs() * START_SPREAD).max(MIN_START_SPREAD))
+        .collect();
+    let mut best = options.start.clone();
+    for generation in 0..options.generations {
+        let mut rules = options.rules.clone();
+        rules.seed = options
+            .rules
+            .seed
+            .wrapping_add(u64::from(generation) * u64::from(options.games));
+        let mut scored = Vec::with_capacity(options.population);
+        for _ in 0..options.population {
+            let sample: Vec<f64> = mean
+                .iter()
+                .zip(&spread)
+                .map(|(mean, spread)| mean + spread * normal(&mut rng))
+                .collect();
+            let candidate = with_weights(&options.start, &sample)?;
+            rules.evaluators = [
+                Some(EvaluatorChoice::Standard {
+                    weights: candidate.clone(),
+                }),
+                Some(EvaluatorChoice::Standard {
+                    weights: best.clone(),
+                }),
+            ];
+            let report = simulate::run(config, &rules, options.games)?;
+            let attack = report.attack[0] as f64 - report.attack[1] as f64;
+            let total = (report.attack[0] + report.attack[1]).max(1) as f64;
+            let score = f64::from(report.wins[0]) - f64::from(report.wins[1]) + attack / total;
+            scored.push((score, weights(&candidate)?));
+        }
+        scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+        let elite = &scored[..options.elite.min(scored.len()).max(1)];
+        for (index, (mean, spread)) in mean.iter_mut().zip(&mut spread).enumerate() {
+            let count = elite.len() as f64;
+            *mean = elite.iter().map(|(_, sample)| sample[index]).sum::<f64>() / count;
+            let variance = elite
+                .iter()
+                .map(|(_, sample)| (sample[index] - *mean).powi(2))
+                .sum::<f64>()
+                / count;
+            *spread = variance.sqrt().max(MIN_SPREAD);
+        }
+        best = with_weights(&options.start, &mean)?;
+        let preset = toml::to_string(&best).map_err(|err| TuneError::Encode(err.to_string()))?;
+        std::fs::write(&options.out, preset)
+            .map_err(|err| TuneError::Io(options.out.clone(), err))?;
+        info!(
+            "Generation {}/{}: the best candidate scored {:.2}; wrote the new weights to {}",
+            generation + 1,
+            options.generations,
+            scored[0].0,
+            options.out.display()
+        );
+    }
+    Ok(best)
+}
+
+// The evaluator's numbers in a fixed order: fields by name, arrays in order.
+fn weights(evaluator: &Standard) -> Result<Vec<f64>, TuneError> {
+    let mut value = serde_json::to_value(evaluator).map_err(encode)?;
+    let mut weights = vec![];
+    numbers(&mut value, &mut |number| {
+        weights.push(number.as_f64().unwrap_or(0.0))
+    });
+    Ok(weights)
+}
+
+// `base` with its numbers replaced, in the order `weights` lists them. Weights keep the type and
+// sign they have in `base`, so integer and unsigned fields stay valid.
+fn with_weights(base: &Standard, weights: &[f64]) -> Result<Standard, TuneError> {
+    let mut value = serde_json::to_value(base).map_err(encode)?;
+    let mut weights = weights.iter();
+    numbers(&mut value, &mut |number| {
+        let weight = *weights.next().unwrap();
+        let weight = match number.as_f64() {
+            Some(current) if current < 0.0 => weight.min(0.0),
+            _ => weight.max(0.0),
+        };
+        *number = if number.is_f64() {
+            Value::from(weight)
+        } else if number.is_u64() {
+            Value::from(weight.round() as u64)
+        } else {
+            Value::from(weight.round() as i64)
+        };
+    });
+    serde_json::from_value(value).map_err(encode)
+}
+
+fn numbers(value: &mut Value, visit: &mut dyn FnMut(&mut Value)) {
+    match value {
+        Value::Number(_) => visit(value),
+        Value::Array(items) => {
+            for item in items {
+                numbers(item, visit);
+            }
+        }
+        Value::Object(fields) => {
+            for field in fields.values_mut() {
+                numbers(field, visit);
+            }
+        }
+        _ => {}
+    }
+}
+
+// A standard normal sample, by the Box-Muller transform.
+fn normal(rng: &mut SplitMix64) -> f64 {
+    let uniform = |rng: &mut SplitMix64| (rng.next() >> 11) as f64 / (1u64 << 53) as f64;
+    let u = 1.0 - uniform(rng);
+    let v = uniform(rng);
+    (-2.0 * u.ln()).sqrt() * (2.0 * std::f64::consts::PI * v).cos()
+}
+
+fn encode(err: serde_json::Error) -> TuneError {
+    TuneError::Encode(err.to_string())
+}
@@ -0,0 +1,299 @@
+use crate::benchmark::bag_queue;
+use crate::garbage::SplitMix64;
+use crate::protocol::{Command, EvaluatorChoice, EvaluatorSpec};
+use crate::server::{Bots, Responder, SessionConfig};
+use libtetris::{Board, FallingPiece};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+use tracing::{debug, info};
+
+#[derive(Clone, Debug)]
+pub struct VersusRules {
+    // Pieces the receiver places before garbage sent to it lands.
+    pub garbage_delay: u32,
+    // Whether attacking cancels the attacker's own pending garbage before any is sent.
+    pub cancel: bool,
+    // The game is a draw once both bots have placed this many pieces.
+    pub max_pieces: u32,
+    pub seed: u64,
+    // Time between rounds, so people can follow along on the dashboard.
+    pub pace: Duration,
+    // The evaluators of P1 and P2; the default evaluator without one.
+    pub evaluators: [Option<EvaluatorChoice>; 2],
+}
+
+#[derive(Debug)]
+pub enum VersusError {
+    // A command to one of the bots failed; the message is the bridge's.
+    Command(String),
+}
+
+pub struct VersusReport {
+    pub pieces: u32,
+    // 0 for P1 and 1 for P2; None for a draw.
+    pub winner: Option<usize>,
+    pub attack: [u32; 2],
+    pub lines: [u32; 2],
+}
+
+impl fmt::Display for VersusReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.winner {
+            Some(winner) => writeln!(f, "P{} wins after {} pieces", winner + 1, self.pieces)?,
+            None => writeln!(f, "Draw after {} pieces", self.pieces)?,
+        }
+        for player in 0..2 {
+            write!(
+                f,
+                "P{}: {} attack ({:.2} per piece), {} lines",
+                player + 1,
+                self.attack[player],
+                f64::from(self.attack[player]) / f64::from(self.pieces.max(1)),
+                self.lines[player]
+            )?;
+            if player == 0 {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The bridge's copy of one side of the game: the board the bot is playing on and the garbage
+// waiting to land on it, oldest first, each with the number of pieces left before it does.
+struct Player {
+    handle: u32,
+    board: Board,
+    pending: VecDeque<(u32, u32)>,
+    attack: u32,
+    lines: u32,
+}
+
+impl Player {
+    fn incoming(&self) -> u32 {
+        self.pending.iter().map(|&(lines, _)| lines).sum()
+    }
+    // Plays the move on the bridge's board, the way the bot plays it on its own. Returns the
+    // attack and whether any lines were cleared.
+    fn place(&mut self, hold: bool, location: FallingPiece) -> (u32, bool) {
+        let current = self.board.advance_queue();
+        if let (true, Some(current)) = (hold, current) {
+            if self.board.hold(current).is_none() {
+                self.board.advance_queue();
+            }
+        }
+        let lock = self.board.lock_piece(location);
+        self.attack += lock.garbage_sent;
+        self.lines += lock.cleared_lines.len() as u32;
+        (lock.garbage_sent, !lock.cleared_lines.is_empty())
+    }
+    // Takes the attack off the pending garbage, oldest first, and returns what is left of it.
+    fn cancel(&mut self, mut attack: u32) -> u32 {
+        while let Some((lines, _)) = self.pending.front_mut() {
+            if attack == 0 {
+                break;
+            }
+            let cancelled = attack.min(*lines);
+            attack -= cancelled;
+            *lines -= cancelled;
+            if *lines == 0 {
+                self.pending.pop_front();
+            }
+        }
+        attack
+    }
+    // Counts down the pending garbage and, unless the piece cleared lines, pushes up whatever is
+    // due, each batch with its own hole column. Returns the new field if anything landed.
+    fn land(
+        &mut self,
+        cleared: bool,
+        rng: &mut SplitMix64,
+        alive: &mut bool,
+    ) -> Option<[[bool; 10]; 40]> {
+        for (_, countdown) in &mut self.pending {
+            *countdown = countdown.saturating_sub(1);
+        }
+        if cleared {
+            return None;
+        }
+        let mut field = self.board.get_field();
+        let mut landed = false;
+        while let Some(&(lines, 0)) = self.pending.front() {
+            self.pending.pop_front();
+            let lines = (lines as usize).min(40);
+            let hole = rng.below(10) as usize;
+            // Anything pushed off the top of the field tops the bot out.
+            if field[40 - lines..].iter().any(|row| row.contains(&true)) {
+                *alive = false;
+            }
+            field.copy_within(..40 - lines, lines);
+            for row in &mut field[..lines] {
+                *row = [true; 10];
+                row[hole] = false;
+            }
+            landed = true;
+        }
+        if !landed {
+            return None;
+        }
+        self.board.set_field(field);
+        Some(field)
+    }
+}
+
+#[derive(Clone)]
+struct Answer(Sender<Value>);
+
+impl Responder for Answer {
+    fn respond(&mut self, msg: &impl Serialize) {
+        self.0
+            .send(serde_json::to_value(msg).unwrap_or(Value::Null))
+            .ok();
+    }
+}
+
+// Plays two bots against each other on the host, through the same handles a console would
+// launch, so they show up on the dashboards and in game records like any others. Both get the
+// same seeded 7-bag queue and move in rounds: both are asked for a move, then each move is
+// applied and its attack sent to the other side, where it lands after `garbage_delay` pieces on
+// a placement that doesn't clear lines. Landed garbage reaches the bot as a Reset.
+pub fn run(config: &SessionConfig, rules: &VersusRules) -> Result<VersusReport, VersusError> {
+    const PREVIEWS: usize = 5;
+    let mut bots = Bots::new(config);
+    let (send, answers) = channel();
+    let mut out = Answer(send);
+    let queue = bag_queue(rules.max_pieces as usize + PREVIEWS, rules.seed);
+    let mut rng = SplitMix64(rules.seed);
+
+    let mut players = vec![];
+    for (index, evaluator) in rules.evaluators.iter().enumerate() {
+        let launch = Command::Launch {
+            options: config.default_options,
+            evaluator: evaluator.clone().map(EvaluatorSpec::Choice),
+            preset: None,
+            slot: None,
+            board: None,
+            label: Some(format!("P{}", index + 1)),
+            plugin: None,
+            coach: false,
+            book: None,
+        };
+        let handle = command(&mut bots, &mut out, &answers, launch)?;
+        let mut player = Player {
+            handle: serde_json::from_value(handle).map_err(decode)?,
+            board: Board::new(),
+            pending: VecDeque::new(),
+            attack: 0,
+            lines: 0,
+        };
+        for &piece in &queue[..PREVIEWS] {
+            player.board.add_next_piece(piece);
+            let add = Command::AddNextPiece {
+                handle: player.handle,
+                piece,
+            };
+            command(&mut bots, &mut out, &answers, add)?;
+        }
+        players.push(player);
+    }
+
+    let mut pieces = 0;
+    let mut winner = None;
+    for &piece in &queue[PREVIEWS..] {
+        for player in &players {
+            let request = Command::RequestNextMove {
+                handle: player.handle,
+                incoming: player.incoming(),
+                budget_ms: None,
+            };
+            command(&mut bots, &mut out, &answers, request)?;
+        }
+        let mut alive = [true; 2];
+        for index in 0..2 {
+            let block = Command::BlockNextMove {
+                handle: players[index].handle,
+            };
+            let result = command(&mut bots, &mut out, &answers, block)?;
+            if result.is_null() {
+                alive[index] = false;
+                continue;
+            }
+            let hold = result["move"]["hold"].as_bool().unwrap_or(false);
+            let location: FallingPiece =
+                serde_json::from_value(result["move"]["expected_location"].clone())
+                    .map_err(decode)?;
+            let (mut attack, cleared) = players[index].place(hold, location);
+            if rules.cancel {
+                attack = players[index].cancel(attack);
+            }
+            if attack > 0 {
+                debug!("P{} sends {} lines", index + 1, attack);
+                players[1 - index]
+                    .pending
+                    .push_back((attack, rules.garbage_delay));
+            }
+            if let Some(field) = players[index].land(cleared, &mut rng, &mut alive[index]) {
+                let reset = Command::Reset {
+                    handle: players[index].handle,
+                    field,
+                    b2b_active: players[index].board.b2b_bonus,
+                    combo: players[index].board.combo,
+                };
+                command(&mut bots, &mut out, &answers, reset)?;
+            }
+        }
+        pieces += 1;
+        if alive != [true, true] {
+            // Both topping out in the same round is a draw.
+            winner = alive.iter().position(|&alive| alive);
+            break;
+        }
+        for player in &mut players {
+            player.board.add_next_piece(piece);
+            let add = Command::AddNextPiece {
+                handle: player.handle,
+                piece,
+            };
+            command(&mut bots, &mut out, &answers, add)?;
+        }
+        if !rules.pace.is_zero() {
+            std::thread::sleep(rules.pace);
+        }
+    }
+    info!("The game ended after {} pieces", pieces);
+    Ok(VersusReport {
+        pieces,
+        winner,
+        attack: [players[0].attack, players[1].attack],
+        lines: [players[0].lines, players[1].lines],
+    })
+}
+
+// Runs a command and waits for its answer, which for bot commands comes from the bot's thread.
+fn command(
+    bots: &mut Bots,
+    out: &mut Answer,
+    answers: &Receiver<Value>,
+    command: Command,
+) -> Result<Value, VersusError> {
+    bots.execute(command, out);
+    let mut answer = answers
+        .recv()
+        .map_err(|_| VersusError::Command("the bot went away".to_owned()))?;
+    if let Some(value) = answer.get_mut("Ok") {
+        return Ok(value.take());
+    }
+    Err(VersusError::Command(
+        answer["Err"][1]
+            .as_str()
+            .map_or_else(|| answer.to_string(), str::to_owned),
+    ))
+}
+
+fn decode(err: serde_json::Error) -> VersusError {
+    VersusError::Command(format!("unexpected answer from the bridge: {}", err))
+}
@@ -0,0 +1,126 @@
+use cc_switch_usb_rs::fumen;
+use cc_switch_usb_rs::monitor::{BotStatus, Monitor, Snapshot};
+use libtetris::FallingPiece;
+use serde_json::{json, Value};
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const PAGE: &str = include_str!("web/index.html");
+const REFRESH: Duration = Duration::from_millis(250);
+const BOARD_ROWS: i32 = 20;
+
+// Serves the dashboard page, and pushes the state of every bot over a WebSocket at /ws whenever
+// it changes. Anyone who can reach the address can watch, but nothing can be sent to the bots.
+// Stops accepting once `closed` is set, as of the next connection.
+pub fn spawn(listener: TcpListener, monitor: Monitor, closed: Arc<AtomicBool>) {
+    std::thread::Builder::new()
+        .name("web".to_owned())
+        .spawn(move || {
+            for stream in listener.incoming() {
+                if closed.load(Ordering::Relaxed) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        warn!("Could not accept a dashboard connection: {}", err);
+                        continue;
+                    }
+                };
+                let monitor = monitor.clone();
+                std::thread::spawn(move || {
+                    if let Err(err) = handle(stream, &monitor) {
+                        debug!("Dashboard connection closed: {}", err);
+                    }
+                });
+            }
+        })
+        .unwrap();
+}
+
+fn handle(mut stream: TcpStream, monitor: &Monitor) -> io::Result<()> {
+    // Peeked rather than read so the WebSocket handshake still sees the whole request.
+    let mut start = [0; 16];
+    let len = stream.peek(&mut start)?;
+    if start[..len].starts_with(b"GET /ws") {
+        let socket = tungstenite::accept(stream)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+        return stream_updates(socket, monitor);
+    }
+    // The request itself doesn't matter; every other path gets the page.
+    let mut request = [0; 4096];
+    let _ = io::Read::read(&mut stream, &mut request)?;
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        PAGE.len(),
+        PAGE
+    )
+}
+
+fn stream_updates(
+    mut socket: tungstenite::WebSocket<TcpStream>,
+    monitor: &Monitor,
+) -> io::Result<()> {
+    let mut last = String::new();
+    loop {
+        let update = to_json(&monitor.snapshot()).to_string();
+        if update != last {
+            socket
+                .write_message(tungstenite::Message::Text(update.clone()))
+                .map_err(|err| io::Error::new(io::ErrorKind::ConnectionAborted, err.to_string()))?;
+            last = update;
+        }
+        std::thread::sleep(REFRESH);
+    }
+}
+
+fn to_json(snapshot: &Snapshot) -> Value {
+    json!({
+        "connections": snapshot
+            .connections
+            .iter()
+            .map(|(name, since)| json!({ "name": name, "seconds": since.as_secs() }))
+            .collect::<Vec<_>>(),
+        "bytes_read": snapshot.bytes_read,
+        "bytes_written": snapshot.bytes_written,
+        "bots": snapshot.bots.iter().map(bot_to_json).collect::<Vec<_>>(),
+    })
+}
+
+// Rows are listed from the top of the visible field down, and the plan and the last placement as
+// the cells each covers.
+pub fn bot_to_json(bot: &BotStatus) -> Value {
+    let field: Vec<Vec<bool>> = (0..BOARD_ROWS)
+        .rev()
+        .map(|y| (0..10).map(|x| bot.board.occupied(x, y)).collect())
+        .collect();
+    let cells = |piece: &FallingPiece| -> Vec<(i32, i32)> {
+        piece.cells().iter().map(|&(x, y, _)| (x, y)).collect()
+    };
+    let plan: Vec<_> = bot.plan.iter().map(cells).collect();
+    json!({
+        "handle": bot.handle,
+        "label": bot.label,
+        "field": field,
+        "hold": bot.board.hold_piece.map(|piece| format!("{:?}", piece)),
+        "queue": bot
+            .board
+            .next_queue()
+            .map(|piece| format!("{:?}", piece))
+            .collect::<Vec<_>>(),
+        "plan": plan,
+        "last_move": bot.last_placed.as_ref().map(|piece| json!({
+            "piece": format!("{:?}", piece.kind.0),
+            "cells": cells(piece),
+        })),
+        "fumen": bot.fumen.as_ref().map(|fumen| format!("{}{}", fumen::VIEWER, fumen)),
+        "pieces_placed": bot.pieces_placed,
+        "last_think_ms": bot.last_think.map(|think| think.as_millis() as u64),
+        "last_nodes": bot.last_nodes,
+    })
+}